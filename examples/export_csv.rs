@@ -0,0 +1,41 @@
+//! Export the ingested variables and geography of a dataset and vintage as CSV files:
+//!
+//! `cargo run --example export_csv -- acs5 2020 exports/`
+//!
+//! This writes `exports/acs5_2020_variables.csv` and `exports/acs5_2020_geography.csv`. The
+//! database URL is read from DATABASE_URL, which may be set in .local.env.
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use us_census::prelude::*;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let (Some(dataset), Some(vintage)) = (args.next(), args.next()) else {
+        return Err("usage: export_csv <dataset> <vintage> [output directory]".into());
+    };
+    let vintage: i32 = vintage.parse()?;
+    let output_dir = args
+        .next()
+        .map_or_else(|| PathBuf::from("."), PathBuf::from);
+    std::fs::create_dir_all(&output_dir)?;
+
+    let conn = &mut establish_read_database_connection(None, None)?;
+    let variables_path = output_dir.join(format!("{}_{}_variables.csv", dataset, vintage));
+    let variables = export_variables_csv(
+        conn,
+        &dataset,
+        vintage,
+        BufWriter::new(File::create(&variables_path)?),
+    )?;
+    println!("{}: {} variables", variables_path.display(), variables);
+    let geography_path = output_dir.join(format!("{}_{}_geography.csv", dataset, vintage));
+    let geographies = export_geography_csv(
+        conn,
+        &dataset,
+        vintage,
+        BufWriter::new(File::create(&geography_path)?),
+    )?;
+    println!("{}: {} geographies", geography_path.display(), geographies);
+    Ok(())
+}
@@ -0,0 +1,45 @@
+//! Parse a variables.json and a geography.json without the network stack.
+//!
+//! This example builds without the default features, so it doubles as a check that the parsing
+//! modules don't depend on `reqwest`, `tokio` or `diesel`:
+//!
+//! `cargo run --example parse_only --no-default-features`
+use us_census::prelude::*;
+
+const VARIABLES_JSON: &str = r#"
+{
+  "variables": {
+    "B01001_001E": {
+      "label": "Estimate!!Total:",
+      "concept": "SEX BY AGE",
+      "predicateType": "int",
+      "group": "B01001",
+      "limit": 0,
+      "attributes": "B01001_001EA,B01001_001M,B01001_001MA"
+    }
+  }
+}"#;
+
+const GEOGRAPHY_JSON: &str = r#"
+{
+  "fips": [
+    {
+      "name": "us",
+      "geoLevelDisplay": "010",
+      "referenceDate": "2020-01-01"
+    }
+  ]
+}"#;
+
+fn main() -> Result<(), serde_json::Error> {
+    let variables: VariablesCollection = serde_json::from_str(VARIABLES_JSON)?;
+    for variable in &variables.variables {
+        println!("{}: {}", variable.name, variable.label.join(" > "));
+    }
+
+    let geography: GeographyCollection = serde_json::from_str(GEOGRAPHY_JSON)?;
+    for item in &geography.fips {
+        println!("{}: {:?}", item.name, item.geo_level_display);
+    }
+    Ok(())
+}
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS geography_requires_jsonb_idx;
+ALTER TABLE geography
+    DROP COLUMN IF EXISTS requires_jsonb,
+    DROP COLUMN IF EXISTS wildcard_jsonb;
+DROP FUNCTION IF EXISTS immutable_text_array_to_jsonb;
@@ -0,0 +1,16 @@
+-- Store `requires` and `wildcard` as JSONB too, which supports containment queries
+-- such as "geographies requiring state" (`requires_jsonb @> '["state"]'`).
+CREATE FUNCTION immutable_text_array_to_jsonb(input TEXT[]) RETURNS JSONB
+AS
+    $$
+BEGIN
+RETURN to_jsonb(input);
+END;
+$$
+LANGUAGE plpgsql IMMUTABLE;
+
+ALTER TABLE geography
+    ADD COLUMN requires_jsonb JSONB GENERATED ALWAYS AS (immutable_text_array_to_jsonb(requires)) STORED,
+    ADD COLUMN wildcard_jsonb JSONB GENERATED ALWAYS AS (immutable_text_array_to_jsonb(wildcard)) STORED;
+
+CREATE INDEX geography_requires_jsonb_idx ON geography USING GIN (requires_jsonb);
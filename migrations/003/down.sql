@@ -0,0 +1,2 @@
+ALTER TABLE geography
+    DROP CONSTRAINT IF EXISTS geography_name_geo_level_id_reference_date_key;
@@ -0,0 +1,33 @@
+-- Share one `geography` row between all the endpoints with the same geography, so that
+-- geographies can be upserted instead of deleted and re-inserted for each endpoint.
+-- First merge the existing duplicates into the row with the smallest id.
+CREATE TEMPORARY TABLE geography_canonical AS
+SELECT id, MIN(id) OVER (PARTITION BY name, geo_level_id, reference_date) AS canonical_id
+FROM geography;
+
+INSERT INTO api_paths_geography_association (api_paths_id, geography_id)
+SELECT DISTINCT association.api_paths_id, canonical.canonical_id
+FROM api_paths_geography_association association
+         JOIN geography_canonical canonical ON association.geography_id = canonical.id
+WHERE canonical.id <> canonical.canonical_id
+ON CONFLICT DO NOTHING;
+
+DELETE
+FROM api_paths_geography_association association
+    USING geography_canonical canonical
+WHERE association.geography_id = canonical.id
+  AND canonical.id <> canonical.canonical_id;
+
+DELETE
+FROM geography
+    USING geography_canonical canonical
+WHERE geography.id = canonical.id
+  AND canonical.id <> canonical.canonical_id;
+
+DROP TABLE geography_canonical;
+
+-- `geo_level_id` and `reference_date` are often NULL, and NULLs must compare equal for
+-- geographies without them to be shared.
+ALTER TABLE geography
+    ADD CONSTRAINT geography_name_geo_level_id_reference_date_key
+        UNIQUE NULLS NOT DISTINCT (name, geo_level_id, reference_date);
@@ -0,0 +1,11 @@
+ALTER TABLE variables
+    DROP CONSTRAINT IF EXISTS variables_name__attributes_hash__concept_hash__first_group_key;
+ALTER TABLE variables
+    DROP COLUMN IF EXISTS _attributes_hash;
+ALTER TABLE variables
+    ADD COLUMN _attributes_hash TEXT GENERATED ALWAYS AS (
+        immutable_md5(immutable_array_to_string(COALESCE(attributes, '{}'), ','))) STORED;
+ALTER TABLE variables
+    ADD CONSTRAINT variables_name__attributes_hash__concept_hash__first_group_key
+        UNIQUE (name, _attributes_hash, _concept_hash, _first_group);
+DROP FUNCTION IF EXISTS immutable_sorted_distinct_array_to_string;
@@ -0,0 +1,55 @@
+-- Hash `attributes` regardless of their order and duplicates, as `VariablesItem::attributes_hash`
+-- does, and keep the hash NULL when there are no attributes. Elements are sorted byte-wise
+-- (`COLLATE "C"`), like Rust sorts strings.
+CREATE FUNCTION immutable_sorted_distinct_array_to_string(input TEXT[], delimiter TEXT) RETURNS TEXT
+AS
+    $$
+BEGIN
+RETURN array_to_string(
+        ARRAY(SELECT element FROM unnest(input) AS element GROUP BY element ORDER BY element COLLATE "C"),
+        delimiter);
+END;
+$$
+LANGUAGE plpgsql IMMUTABLE STRICT;
+
+ALTER TABLE variables
+    DROP CONSTRAINT variables_name__attributes_hash__concept_hash__first_group_key;
+ALTER TABLE variables
+    DROP COLUMN _attributes_hash;
+ALTER TABLE variables
+    ADD COLUMN _attributes_hash TEXT GENERATED ALWAYS AS (
+        immutable_md5(immutable_sorted_distinct_array_to_string(attributes, ','))) STORED;
+
+-- Variables whose attributes only differed in order are now duplicates. Merge them into the
+-- row with the smallest id.
+CREATE TEMPORARY TABLE variables_canonical AS
+SELECT id,
+       MIN(id) OVER (PARTITION BY name, _attributes_hash, _concept_hash, _first_group) AS canonical_id
+FROM variables;
+
+INSERT INTO api_paths_variables_association (api_paths_id, variables_id)
+SELECT DISTINCT association.api_paths_id, canonical.canonical_id
+FROM api_paths_variables_association association
+         JOIN variables_canonical canonical ON association.variables_id = canonical.id
+WHERE canonical.id <> canonical.canonical_id
+ON CONFLICT DO NOTHING;
+
+DELETE
+FROM api_paths_variables_association association
+    USING variables_canonical canonical
+WHERE association.variables_id = canonical.id
+  AND canonical.id <> canonical.canonical_id;
+
+DELETE
+FROM variables
+    USING variables_canonical canonical
+WHERE variables.id = canonical.id
+  AND canonical.id <> canonical.canonical_id;
+
+DROP TABLE variables_canonical;
+
+-- The hash of missing attributes is NULL, and NULLs must compare equal for such variables to
+-- be upserted rather than duplicated.
+ALTER TABLE variables
+    ADD CONSTRAINT variables_name__attributes_hash__concept_hash__first_group_key
+        UNIQUE NULLS NOT DISTINCT (name, _attributes_hash, _concept_hash, _first_group);
@@ -0,0 +1,38 @@
+ALTER TABLE variables
+    DROP CONSTRAINT IF EXISTS variables_name__attributes_hash__concept_hash__first_group_key;
+ALTER TABLE variables
+    DROP COLUMN IF EXISTS _first_group;
+ALTER TABLE variables
+    ADD COLUMN _first_group TEXT GENERATED ALWAYS AS (COALESCE("group"[0], '')) STORED;
+
+-- Variables that only differed in their first group are duplicates again. Merge them into the
+-- row with the smallest id.
+CREATE TEMPORARY TABLE variables_canonical AS
+SELECT id,
+       MIN(id) OVER (PARTITION BY name, _attributes_hash, _concept_hash, _first_group) AS canonical_id
+FROM variables;
+
+INSERT INTO api_paths_variables_association (api_paths_id, variables_id)
+SELECT DISTINCT association.api_paths_id, canonical.canonical_id
+FROM api_paths_variables_association association
+         JOIN variables_canonical canonical ON association.variables_id = canonical.id
+WHERE canonical.id <> canonical.canonical_id
+ON CONFLICT DO NOTHING;
+
+DELETE
+FROM api_paths_variables_association association
+    USING variables_canonical canonical
+WHERE association.variables_id = canonical.id
+  AND canonical.id <> canonical.canonical_id;
+
+DELETE
+FROM variables
+    USING variables_canonical canonical
+WHERE variables.id = canonical.id
+  AND canonical.id <> canonical.canonical_id;
+
+DROP TABLE variables_canonical;
+
+ALTER TABLE variables
+    ADD CONSTRAINT variables_name__attributes_hash__concept_hash__first_group_key
+        UNIQUE NULLS NOT DISTINCT (name, _attributes_hash, _concept_hash, _first_group);
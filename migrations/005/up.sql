@@ -0,0 +1,13 @@
+-- `"group"[0]` is always NULL because Postgres arrays are 1-indexed, so `_first_group` was
+-- always ''. Store the first element of `group` instead, or NULL if there is none, as
+-- `VariablesItem::first_group` does. Variables are only told apart by more than before, so
+-- no existing rows become duplicates.
+ALTER TABLE variables
+    DROP CONSTRAINT variables_name__attributes_hash__concept_hash__first_group_key;
+ALTER TABLE variables
+    DROP COLUMN _first_group;
+ALTER TABLE variables
+    ADD COLUMN _first_group TEXT GENERATED ALWAYS AS ("group"[1]) STORED;
+ALTER TABLE variables
+    ADD CONSTRAINT variables_name__attributes_hash__concept_hash__first_group_key
+        UNIQUE NULLS NOT DISTINCT (name, _attributes_hash, _concept_hash, _first_group);
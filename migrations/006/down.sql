@@ -0,0 +1 @@
+DROP TABLE IF EXISTS variable_values;
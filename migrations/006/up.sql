@@ -0,0 +1,10 @@
+-- The coded values of a variable, e.g. the race or income brackets that its estimates are
+-- reported in, from the `values.item` map of variables.json.
+CREATE TABLE variable_values
+(
+    id           SERIAL PRIMARY KEY,
+    variables_id INT  NOT NULL REFERENCES variables (id),
+    code         TEXT NOT NULL,
+    label        TEXT NOT NULL,
+    UNIQUE (variables_id, code)
+);
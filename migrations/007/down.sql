@@ -0,0 +1,2 @@
+ALTER TABLE variables
+    ALTER COLUMN "limit" TYPE SMALLINT;
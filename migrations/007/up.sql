@@ -0,0 +1,4 @@
+-- Census JSON has limits beyond the range of SMALLINT, e.g. 65536 in geography.json, so store
+-- the limits of variables as INTEGER, like those of geographies.
+ALTER TABLE variables
+    ALTER COLUMN "limit" TYPE INTEGER;
@@ -0,0 +1,2 @@
+ALTER TABLE geography
+    DROP COLUMN IF EXISTS reference_date_precision;
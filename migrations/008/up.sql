@@ -0,0 +1,5 @@
+-- Whether the month and day of `reference_date` were given or inferred, e.g. 'year' for a
+-- `referenceDate` of "2010", which is stored as 2010-01-01. NULL for rows inserted before
+-- this column was added.
+ALTER TABLE geography
+    ADD COLUMN reference_date_precision TEXT;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS variables_search_idx;
+DROP FUNCTION IF EXISTS variables_search_text(TEXT, TEXT[]);
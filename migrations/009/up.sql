@@ -0,0 +1,14 @@
+-- The text searched by `query::search_variables`: the concept and the label of a variable.
+CREATE FUNCTION variables_search_text(concept TEXT, label TEXT[]) RETURNS TEXT
+AS
+    $$
+BEGIN
+RETURN COALESCE(concept, '') || ' ' || immutable_array_to_string(label, ' ');
+END;
+$$
+LANGUAGE plpgsql IMMUTABLE;
+
+-- Index the search text so that searching doesn't parse every variable. Queries must use
+-- the same expression, including the 'english' configuration, to use the index.
+CREATE INDEX variables_search_idx
+    ON variables USING GIN (to_tsvector('english', variables_search_text(concept, label)));
@@ -0,0 +1,5 @@
+ALTER TABLE api_paths
+    DROP CONSTRAINT IF EXISTS api_paths_c_vintage_c_dataset_key;
+ALTER TABLE api_paths
+    ADD CONSTRAINT api_paths_c_vintage_c_dataset_key
+        UNIQUE (c_vintage, c_dataset);
@@ -0,0 +1,46 @@
+-- Time series have no vintage, and NULL vintages must compare equal for their API paths to be
+-- upserted rather than duplicated. Merge the duplicates into the row with the smallest id first.
+CREATE TEMPORARY TABLE api_paths_canonical AS
+SELECT id,
+       MIN(id) OVER (PARTITION BY c_vintage, c_dataset) AS canonical_id
+FROM api_paths;
+
+INSERT INTO api_paths_variables_association (api_paths_id, variables_id)
+SELECT DISTINCT canonical.canonical_id, association.variables_id
+FROM api_paths_variables_association association
+         JOIN api_paths_canonical canonical ON association.api_paths_id = canonical.id
+WHERE canonical.id <> canonical.canonical_id
+ON CONFLICT DO NOTHING;
+
+INSERT INTO api_paths_geography_association (api_paths_id, geography_id)
+SELECT DISTINCT canonical.canonical_id, association.geography_id
+FROM api_paths_geography_association association
+         JOIN api_paths_canonical canonical ON association.api_paths_id = canonical.id
+WHERE canonical.id <> canonical.canonical_id
+ON CONFLICT DO NOTHING;
+
+DELETE
+FROM api_paths_variables_association association
+    USING api_paths_canonical canonical
+WHERE association.api_paths_id = canonical.id
+  AND canonical.id <> canonical.canonical_id;
+
+DELETE
+FROM api_paths_geography_association association
+    USING api_paths_canonical canonical
+WHERE association.api_paths_id = canonical.id
+  AND canonical.id <> canonical.canonical_id;
+
+DELETE
+FROM api_paths
+    USING api_paths_canonical canonical
+WHERE api_paths.id = canonical.id
+  AND canonical.id <> canonical.canonical_id;
+
+DROP TABLE api_paths_canonical;
+
+ALTER TABLE api_paths
+    DROP CONSTRAINT api_paths_c_vintage_c_dataset_key;
+ALTER TABLE api_paths
+    ADD CONSTRAINT api_paths_c_vintage_c_dataset_key
+        UNIQUE NULLS NOT DISTINCT (c_vintage, c_dataset);
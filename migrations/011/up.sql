@@ -0,0 +1,7 @@
+-- The API paths whose ingestion completed, so that an interrupted run can resume with the
+-- others instead of ingesting every API path again.
+CREATE TABLE ingest_state
+(
+    api_paths_id INT PRIMARY KEY REFERENCES api_paths (id),
+    completed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,6 @@
+ALTER TABLE api_paths
+    DROP COLUMN IF EXISTS c_is_aggregate,
+    DROP COLUMN IF EXISTS c_is_available,
+    DROP COLUMN IF EXISTS spatial,
+    DROP COLUMN IF EXISTS temporal,
+    DROP COLUMN IF EXISTS modified;
@@ -0,0 +1,8 @@
+-- Dataset-level metadata of data.json that was dropped, e.g. to only ingest the available or
+-- the aggregate datasets. Older catalogs may lack any of them.
+ALTER TABLE api_paths
+    ADD COLUMN c_is_aggregate BOOLEAN,
+    ADD COLUMN c_is_available BOOLEAN,
+    ADD COLUMN spatial        TEXT,
+    ADD COLUMN temporal       TEXT,
+    ADD COLUMN modified       TEXT;
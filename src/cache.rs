@@ -0,0 +1,312 @@
+//! Storage for the responses cached by `CachedClient`.
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Write;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Where `CachedClient` stores response bodies. `path` is the cache path of a URL, e.g.
+/// `<base cache dir>/data/2020/acs/acs5/variables.json`.
+///
+/// The methods block, so `CachedClient` calls them on tokio's blocking thread pool.
+pub trait CacheBackend: fmt::Debug + Send + Sync {
+    /// Return the body cached for `path`, or `None` if there's none.
+    fn read(&self, path: &Path) -> io::Result<Option<String>>;
+
+    /// Cache `body` for `path`, replacing any previous body.
+    fn write(&self, path: &Path, body: &str) -> io::Result<()>;
+
+    /// Return a reader over the body cached for `path`, or `None` if there's none. By default,
+    /// the body is read whole with `read`; backends that store it in a file stream the file.
+    fn open(&self, path: &Path) -> io::Result<Option<CachedBody>> {
+        Ok(self.read(path)?.map(CachedBody::from))
+    }
+}
+
+/// A reader over a cached body, returned by `CacheBackend::open` and
+/// `CachedClient::fetch_reader`.
+pub struct CachedBody {
+    reader: Box<dyn BufRead + Send>,
+    len: u64,
+}
+
+impl CachedBody {
+    /// Stream the body from the file at `path`, or return `None` if it doesn't exist.
+    pub fn open_file(path: &Path) -> io::Result<Option<Self>> {
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(CachedBody {
+            len: file.metadata()?.len(),
+            reader: Box::new(BufReader::new(file)),
+        }))
+    }
+
+    /// Return the length of the body in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Return whether the body is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl From<String> for CachedBody {
+    fn from(body: String) -> Self {
+        CachedBody {
+            len: body.len() as u64,
+            reader: Box::new(Cursor::new(body.into_bytes())),
+        }
+    }
+}
+
+impl fmt::Debug for CachedBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedBody")
+            .field("len", &self.len)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Read for CachedBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl BufRead for CachedBody {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount)
+    }
+}
+
+/// Store each body in a file at its cache path. This is the default backend.
+#[derive(Debug, Default)]
+pub struct FileCache;
+
+impl CacheBackend for FileCache {
+    fn read(&self, path: &Path) -> io::Result<Option<String>> {
+        read_if_exists(path)
+    }
+
+    fn write(&self, path: &Path, body: &str) -> io::Result<()> {
+        write_atomically(path, body.as_bytes())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Option<CachedBody>> {
+        CachedBody::open_file(path)
+    }
+}
+
+/// How much `ContentAddressedCache` deduplicated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    /// Number of distinct bodies written, i.e. objects.
+    pub blobs_written: usize,
+    /// Number of bodies that were already stored for another path.
+    pub duplicates: usize,
+    /// Total size of the duplicate bodies, which weren't written again.
+    pub bytes_saved: u64,
+}
+
+/// Store each distinct body once, at `objects/<sha256>` under the store directory, where
+/// `<sha256>` is the hex-encoded SHA-256 hash of the body. The file at a cache path is a
+/// pointer file, which contains `sha256:<sha256>` rather than the body.
+///
+/// Many endpoints across vintages share byte-identical geography.json files, which this
+/// stores only once.
+#[derive(Debug)]
+pub struct ContentAddressedCache {
+    objects_dir: PathBuf,
+    blobs_written: AtomicUsize,
+    duplicates: AtomicUsize,
+    bytes_saved: AtomicU64,
+}
+
+impl ContentAddressedCache {
+    /// Create a store whose objects are in `store_dir/objects`. `store_dir` may be the
+    /// client's base cache directory.
+    pub fn new(store_dir: PathBuf) -> Self {
+        ContentAddressedCache {
+            objects_dir: store_dir.join(OBJECTS_DIR),
+            blobs_written: AtomicUsize::new(0),
+            duplicates: AtomicUsize::new(0),
+            bytes_saved: AtomicU64::new(0),
+        }
+    }
+
+    /// Return the directory of the objects, `store_dir/objects`.
+    pub fn objects_dir(&self) -> &Path {
+        &self.objects_dir
+    }
+
+    /// Return the deduplication statistics of the writes through this instance.
+    pub fn stats(&self) -> DedupStats {
+        DedupStats {
+            blobs_written: self.blobs_written.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            bytes_saved: self.bytes_saved.load(Ordering::Relaxed),
+        }
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.objects_dir.join(hash)
+    }
+
+    /// Delete the objects that no pointer file under `cache_dir` refers to anymore, e.g. after
+    /// `CachedClient::invalidate` or a refetch replaced their bodies. Don't call this while
+    /// fetching, since an object may be written before the pointer file that refers to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache_dir` - the directory of the cache paths, e.g. the client's base cache directory.
+    ///   It may contain the objects directory.
+    ///
+    /// # Returns
+    ///
+    /// The number of objects deleted.
+    pub fn gc(&self, cache_dir: &Path) -> io::Result<usize> {
+        // Compare canonical paths, so that the objects directory is skipped even if one of the
+        // paths is relative or goes through a symlink.
+        let objects_dir = match self.objects_dir.canonicalize() {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            objects_dir => objects_dir?,
+        };
+        let mut referenced = HashSet::new();
+        match cache_dir.canonicalize() {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            cache_dir => collect_references(&cache_dir?, &objects_dir, &mut referenced)?,
+        }
+        let mut deleted = 0;
+        for entry in fs::read_dir(&objects_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file()
+                && !referenced.contains(entry.file_name().to_string_lossy().as_ref())
+            {
+                fs::remove_file(entry.path())?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+/// The subdirectory of the store directory that holds the objects.
+const OBJECTS_DIR: &str = "objects";
+
+/// What the contents of a pointer file start with, followed by the hash of its object.
+const POINTER_PREFIX: &str = "sha256:";
+
+/// Length of a hex-encoded SHA-256 hash.
+const HASH_LEN: usize = 64;
+
+/// Return the hex-encoded SHA-256 hash of `body`.
+fn sha256_hex(body: &[u8]) -> String {
+    Sha256::digest(body)
+        .iter()
+        .fold(String::with_capacity(HASH_LEN), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
+
+/// Return the hash that the contents of a pointer file refer to, or `None` if `contents`
+/// aren't those of a pointer file.
+fn parse_pointer(contents: &str) -> Option<&str> {
+    contents
+        .strip_prefix(POINTER_PREFIX)
+        .filter(|hash| hash.len() == HASH_LEN && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Add the hash of each pointer file under `dir` to `referenced`, skipping `objects_dir`.
+/// Both paths must be canonical.
+fn collect_references(
+    dir: &Path,
+    objects_dir: &Path,
+    referenced: &mut HashSet<String>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() && path != objects_dir {
+            collect_references(&path, objects_dir, referenced)?;
+        } else if file_type.is_file()
+            && entry.metadata()?.len() == (POINTER_PREFIX.len() + HASH_LEN) as u64
+        {
+            let contents = fs::read_to_string(&path)?;
+            if let Some(hash) = parse_pointer(&contents) {
+                referenced.insert(hash.to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+impl CacheBackend for ContentAddressedCache {
+    fn read(&self, path: &Path) -> io::Result<Option<String>> {
+        match read_if_exists(path)?.as_deref().and_then(parse_pointer) {
+            Some(hash) => read_if_exists(&self.object_path(hash)),
+            None => Ok(None),
+        }
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Option<CachedBody>> {
+        match read_if_exists(path)?.as_deref().and_then(parse_pointer) {
+            Some(hash) => CachedBody::open_file(&self.object_path(hash)),
+            None => Ok(None),
+        }
+    }
+
+    fn write(&self, path: &Path, body: &str) -> io::Result<()> {
+        let hash = sha256_hex(body.as_bytes());
+        let object_path = self.object_path(&hash);
+        if object_path.exists() {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+            self.bytes_saved
+                .fetch_add(body.len() as u64, Ordering::Relaxed);
+        } else {
+            write_atomically(&object_path, body.as_bytes())?;
+            self.blobs_written.fetch_add(1, Ordering::Relaxed);
+        }
+        write_atomically(path, format!("{}{}", POINTER_PREFIX, hash).as_bytes())
+    }
+}
+
+fn read_if_exists(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Write `contents` to a temporary file next to `path`, then rename it to `path`, so that
+/// concurrent fetches of the same URL never read a partially written file. Create the parent
+/// directory if needed.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    static WRITE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        WRITE_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, path)
+}
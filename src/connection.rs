@@ -0,0 +1,331 @@
+//! Connecting to the database, from a URL or from the environment.
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PoolError};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Why a database connection couldn't be established.
+#[derive(Debug, Error)]
+pub enum ConnectError {
+    #[error("No database URL was given and DATABASE_URL isn't set")]
+    MissingDatabaseUrl,
+
+    #[error("The env file {0} doesn't exist")]
+    EnvFileNotFound(PathBuf),
+
+    #[error("Error loading the env file {path}: {source}")]
+    EnvFile {
+        path: PathBuf,
+        source: dotenvy::Error,
+    },
+
+    #[error("Connection error: {0}")]
+    Connection(#[from] ConnectionError),
+
+    #[error("Connection pool error: {0}")]
+    Pool(#[from] PoolError),
+}
+
+/// Return a database connection.
+///
+/// # Arguments
+///
+/// * `database_url` - An optional database URL. If not provided, it will try to read
+///   it from the `DATABASE_URL` environment variable.
+/// * `env_path` - An optional path to a `.env` file. If not provided, it will default to `.local.env`.
+///
+/// # Returns
+///
+/// * `Ok(PgConnection)` - A connection to the PostgreSQL database
+/// * `Err(ConnectError)` - If there's no database URL, if `env_path` couldn't be loaded, or
+///   the error returned by `PgConnection::establish` if the connection fails
+pub fn establish_database_connection(
+    database_url: Option<String>,
+    env_path: Option<&Path>,
+) -> Result<PgConnection, ConnectError> {
+    let url = database_url_or_env(database_url, env_path, &["DATABASE_URL"])?;
+    Ok(PgConnection::establish(&url)?)
+}
+
+/// Return an async database connection, for `insert_variables_and_geography_for_api_path_async`.
+/// See `establish_database_connection` for the arguments.
+#[cfg(feature = "async-db")]
+pub async fn establish_async_database_connection(
+    database_url: Option<String>,
+    env_path: Option<&Path>,
+) -> Result<diesel_async::AsyncPgConnection, ConnectError> {
+    use diesel_async::AsyncConnection;
+
+    let url = database_url_or_env(database_url, env_path, &["DATABASE_URL"])?;
+    Ok(diesel_async::AsyncPgConnection::establish(&url).await?)
+}
+
+/// Return a pool of up to `max_size` database connections, e.g. to ingest several API paths
+/// concurrently.
+///
+/// A pooled connection dereferences to a `PgConnection`, so `&mut pool.get()?` can be passed
+/// to any function of this crate that takes a connection.
+///
+/// # Arguments
+///
+/// * `database_url` - An optional database URL. If not provided, it will try to read
+///   it from the `DATABASE_URL` environment variable.
+/// * `env_path` - An optional path to a `.env` file. If not provided, it will default to `.local.env`.
+/// * `max_size` - the maximum number of connections. Must be greater than 0.
+///
+/// # Returns
+///
+/// * `Ok(Pool)` - A pool whose connections are already established
+/// * `Err(ConnectError)` - If there's no database URL, if `env_path` couldn't be loaded, or
+///   the error returned by r2d2 if the connections couldn't be established before its
+///   connection timeout
+pub fn establish_pool(
+    database_url: Option<String>,
+    env_path: Option<&Path>,
+    max_size: u32,
+) -> Result<Pool<ConnectionManager<PgConnection>>, ConnectError> {
+    let url = database_url_or_env(database_url, env_path, &["DATABASE_URL"])?;
+    Ok(Pool::builder()
+        .max_size(max_size)
+        .build(ConnectionManager::new(url))?)
+}
+
+/// Return `database_url`, or else the first of the environment variables `names` that's set,
+/// after loading `env_path`.
+fn database_url_or_env(
+    database_url: Option<String>,
+    env_path: Option<&Path>,
+    names: &[&str],
+) -> Result<String, ConnectError> {
+    if let Some(database_url) = database_url {
+        return Ok(database_url);
+    }
+    load_env(env_path)?;
+    names
+        .iter()
+        .find_map(|name| env::var(name).ok())
+        .ok_or(ConnectError::MissingDatabaseUrl)
+}
+
+/// Return a connection to `database_url`, trying up to `attempts` times and sleeping `delay`
+/// between attempts.
+///
+/// Unlike `establish_database_connection`, this waits for a database that's still starting
+/// up, e.g. a Postgres container started alongside this crate's by `docker compose`.
+///
+/// # Arguments
+///
+/// * `database_url` - the database URL
+/// * `attempts` - the maximum number of connection attempts. `0` is treated as `1`.
+/// * `delay` - how long to sleep after a failed attempt
+///
+/// # Returns
+///
+/// * `Ok(PgConnection)` - A connection to the PostgreSQL database
+/// * `Err(diesel::ConnectionError)` - The error of the last attempt if all of them failed
+pub fn establish_with_retry(
+    database_url: &str,
+    attempts: u32,
+    delay: Duration,
+) -> ConnectionResult<PgConnection> {
+    retry(attempts, delay, || PgConnection::establish(database_url))
+}
+
+/// Call `connect` until it succeeds or was called `attempts` times, sleeping `delay` in between.
+fn retry<T>(
+    attempts: u32,
+    delay: Duration,
+    mut connect: impl FnMut() -> ConnectionResult<T>,
+) -> ConnectionResult<T> {
+    let mut attempt = 1;
+    loop {
+        match connect() {
+            Err(_) if attempt < attempts => {
+                std::thread::sleep(delay);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Return a read-only database connection, e.g. to a read replica, for the functions in
+/// `query`. Ingestion should use `establish_database_connection` for the primary database.
+///
+/// # Arguments
+///
+/// * `read_database_url` - An optional database URL. If not provided, it will try to read
+///   it from the `READ_DATABASE_URL` environment variable, then from `DATABASE_URL`.
+/// * `env_path` - An optional path to a `.env` file. If not provided, it will default to `.local.env`.
+///
+/// # Returns
+///
+/// * `Ok(PgConnection)` - A connection whose transactions are read-only
+/// * `Err(ConnectError)` - If there's no database URL, if `env_path` couldn't be loaded, or
+///   the error returned by `PgConnection::establish` if the connection fails or couldn't be
+///   made read-only
+pub fn establish_read_database_connection(
+    read_database_url: Option<String>,
+    env_path: Option<&Path>,
+) -> Result<PgConnection, ConnectError> {
+    let url = database_url_or_env(
+        read_database_url,
+        env_path,
+        &["READ_DATABASE_URL", "DATABASE_URL"],
+    )?;
+    let mut conn = PgConnection::establish(&url)?;
+    diesel::sql_query("SET SESSION CHARACTERISTICS AS TRANSACTION READ ONLY")
+        .execute(&mut conn)
+        .map_err(ConnectionError::CouldntSetupConfiguration)?;
+    Ok(conn)
+}
+
+/// Load the environment variables in `env_path`, or in `.local.env` by default. Unlike
+/// `env_path`, `.local.env` is optional: if it doesn't exist, the environment is left as is.
+fn load_env(env_path: Option<&Path>) -> Result<(), ConnectError> {
+    match env_path {
+        Some(path) => dotenvy::from_path(path).map_err(|e| {
+            if e.not_found() {
+                ConnectError::EnvFileNotFound(path.to_path_buf())
+            } else {
+                ConnectError::EnvFile {
+                    path: path.to_path_buf(),
+                    source: e,
+                }
+            }
+        }),
+        None => {
+            dotenvy::from_path(".local.env").ok();
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::first_attempt(0, 3, Some(1))]
+    #[case::eventually(2, 3, Some(3))]
+    #[case::exhausted(3, 3, None)]
+    #[case::zero_attempts(1, 0, None)]
+    fn test_retry(
+        #[case] failures: u32,
+        #[case] attempts: u32,
+        #[case] expected_calls: Option<u32>,
+    ) {
+        // Arrange
+        let mut calls = 0;
+
+        // Act
+        let result = retry(attempts, Duration::ZERO, || {
+            calls += 1;
+            if calls <= failures {
+                Err(ConnectionError::BadConnection(format!("attempt {}", calls)))
+            } else {
+                Ok(calls)
+            }
+        });
+
+        // Assert
+        match expected_calls {
+            Some(expected) => assert_eq!(result.unwrap(), expected),
+            None => {
+                assert_eq!(calls, attempts.max(1));
+                assert_eq!(
+                    result.unwrap_err().to_string(),
+                    format!("attempt {}", calls)
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_establish_with_retry() {
+        // Arrange
+        load_env(None).unwrap();
+        let url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+
+        // Act
+        let result = establish_with_retry(&url, 2, Duration::from_millis(10));
+
+        // Assert
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_missing_database_url() {
+        // Act
+        let result = database_url_or_env(None, None, &["US_CENSUS_UNSET_DATABASE_URL"]);
+
+        // Assert
+        assert!(matches!(result, Err(ConnectError::MissingDatabaseUrl)));
+    }
+
+    #[test]
+    fn test_env_file_not_found() {
+        // Arrange
+        let env_path = Path::new("does/not/exist.env");
+
+        // Act
+        let result = establish_database_connection(None, Some(env_path));
+
+        // Assert
+        assert!(matches!(
+            result,
+            Err(ConnectError::EnvFileNotFound(path)) if path == env_path
+        ));
+    }
+
+    #[test]
+    fn test_bad_database_url() {
+        // Act
+        let result = establish_database_connection(Some("not a database url".to_string()), None);
+
+        // Assert
+        assert!(matches!(result, Err(ConnectError::Connection(_))));
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_establish_pool() {
+        use crate::models::ApiPaths;
+        use crate::test_utils::insert_api_path;
+        use std::borrow::Cow;
+
+        // Arrange
+        let pool = establish_pool(None, None, 2).unwrap();
+        let api_path = |dataset: &'static str| ApiPaths {
+            id: 0,
+            c_vintage: Some(2099),
+            c_dataset: vec![Some(Cow::from("acs")), Some(Cow::from(dataset))],
+            c_geography_link: Cow::from(format!("http://localhost/{}/geography.json", dataset)),
+            c_variables_link: Cow::from(format!("http://localhost/{}/variables.json", dataset)),
+            title: Cow::from(dataset),
+            description: Cow::from(""),
+            c_is_aggregate: None,
+            c_is_available: None,
+            spatial: None,
+            temporal: None,
+            modified: None,
+        };
+
+        // Act
+        let mut connections = [pool.get().unwrap(), pool.get().unwrap()];
+        let mut ids = Vec::new();
+        for (conn, dataset) in connections.iter_mut().zip(["acs1", "acs5"]) {
+            conn.begin_test_transaction().unwrap();
+            ids.push(insert_api_path(conn, &api_path(dataset)));
+        }
+
+        // Assert
+        assert_eq!(pool.state().connections, 2);
+        assert_eq!(pool.state().idle_connections, 0);
+        assert_ne!(ids[0], ids[1]);
+    }
+}
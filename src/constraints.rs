@@ -1,5 +1,5 @@
 use diesel::deserialize::QueryableByName;
-use diesel::sql_types::Text;
+use diesel::sql_types::{Array, Text};
 use diesel::{sql_query, PgConnection, RunQueryDsl};
 
 #[derive(QueryableByName, Debug)]
@@ -8,6 +8,32 @@ struct ConstraintName {
     conname: String,
 }
 
+#[derive(QueryableByName, Debug)]
+struct ConstraintNameAndKind {
+    #[diesel(sql_type = Text)]
+    conname: String,
+    #[diesel(sql_type = Text)]
+    contype: String,
+}
+
+#[derive(QueryableByName, Debug)]
+struct ConstraintNameAndColumns {
+    #[diesel(sql_type = Text)]
+    conname: String,
+    #[diesel(sql_type = Array<Text>)]
+    columns: Vec<String>,
+}
+
+/// The names of a table's constraints, by kind.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableConstraints {
+    pub table: String,
+    pub primary_key: Vec<String>,
+    pub unique: Vec<String>,
+    pub foreign_keys: Vec<String>,
+    pub checks: Vec<String>,
+}
+
 /// Return the unique constraints for a table.
 ///
 /// # Arguments
@@ -19,13 +45,10 @@ pub fn get_unique_constraints(
     table_name: &str,
 ) -> Result<Vec<String>, diesel::result::Error> {
     // 'u' = unique constraint
-    // ::regclass returns the table's object ID
-    let query = format!(
-        "SELECT conname FROM pg_constraint WHERE conrelid = '{}'::regclass AND contype = 'u'",
-        table_name
-    );
-
-    sql_query(query)
+    // ::regclass returns the table's object ID. The table name is bound rather than
+    // interpolated, so a name that isn't a table is an error rather than SQL.
+    sql_query("SELECT conname FROM pg_constraint WHERE conrelid = $1::regclass AND contype = 'u'")
+        .bind::<Text, _>(table_name)
         .load::<ConstraintName>(conn)
         .map(|constraints| {
             constraints
@@ -34,3 +57,163 @@ pub fn get_unique_constraints(
                 .collect()
         })
 }
+
+/// Return the unique constraints for a table, with the columns each covers in the order of
+/// the constraint, e.g. to choose the conflict target of an upsert. Constraints are sorted by
+/// name.
+///
+/// # Arguments
+///
+/// * `conn` - the connection to the database
+/// * `table_name` - the name of the table
+pub fn get_unique_constraints_with_columns(
+    conn: &mut PgConnection,
+    table_name: &str,
+) -> Result<Vec<(String, Vec<String>)>, diesel::result::Error> {
+    // `conkey` holds the column numbers of the constraint, which `pg_attribute` names.
+    sql_query(
+        "SELECT c.conname, array_agg(a.attname::TEXT ORDER BY k.position) AS columns \
+         FROM pg_constraint c \
+         CROSS JOIN LATERAL unnest(c.conkey) WITH ORDINALITY AS k(attnum, position) \
+         JOIN pg_attribute a ON a.attrelid = c.conrelid AND a.attnum = k.attnum \
+         WHERE c.conrelid = $1::regclass AND c.contype = 'u' \
+         GROUP BY c.conname ORDER BY c.conname",
+    )
+    .bind::<Text, _>(table_name)
+    .load::<ConstraintNameAndColumns>(conn)
+    .map(|constraints| {
+        constraints
+            .into_iter()
+            .map(|constraint| (constraint.conname, constraint.columns))
+            .collect()
+    })
+}
+
+/// Return the primary key, unique, foreign key and check constraints of each table, in the
+/// order of `tables`. Constraint names are sorted.
+///
+/// # Arguments
+///
+/// * `conn` - the connection to the database
+/// * `tables` - the names of the tables
+pub fn describe_schema(
+    conn: &mut PgConnection,
+    tables: &[&str],
+) -> Result<Vec<TableConstraints>, diesel::result::Error> {
+    tables
+        .iter()
+        .map(|table| {
+            let constraints = sql_query(
+                "SELECT conname, contype::TEXT AS contype FROM pg_constraint \
+                 WHERE conrelid = $1::regclass ORDER BY conname",
+            )
+            .bind::<Text, _>(table)
+            .load::<ConstraintNameAndKind>(conn)?;
+
+            let mut table_constraints = TableConstraints {
+                table: table.to_string(),
+                ..Default::default()
+            };
+            for constraint in constraints {
+                let names = match constraint.contype.as_str() {
+                    "p" => &mut table_constraints.primary_key,
+                    "u" => &mut table_constraints.unique,
+                    "f" => &mut table_constraints.foreign_keys,
+                    "c" => &mut table_constraints.checks,
+                    // Exclusion constraints and constraint triggers aren't used by this crate.
+                    _ => continue,
+                };
+                names.push(constraint.conname);
+            }
+            Ok(table_constraints)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::test_connection;
+    use diesel::Connection;
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_get_unique_constraints_quoted_table_name() {
+        // Arrange
+        let conn = &mut test_connection();
+        let table_name = "variables'::regclass; DROP TABLE variables; --";
+
+        // Act
+        // Roll back to a savepoint on error, so that the test transaction remains usable.
+        let result = conn.transaction(|conn| get_unique_constraints(conn, table_name));
+
+        // Assert
+        assert!(
+            matches!(result, Err(diesel::result::Error::DatabaseError(..))),
+            "{:?}",
+            result
+        );
+        assert_eq!(get_unique_constraints(conn, "variables").unwrap().len(), 1);
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_get_unique_constraints_with_columns() {
+        // Arrange
+        let conn = &mut test_connection();
+
+        // Act
+        let result = get_unique_constraints_with_columns(conn, "variables").unwrap();
+
+        // Assert
+        assert_eq!(
+            result,
+            vec![(
+                get_unique_constraints(conn, "variables").unwrap()[0].clone(),
+                vec![
+                    "name".to_string(),
+                    "_attributes_hash".to_string(),
+                    "_concept_hash".to_string(),
+                    "_first_group".to_string(),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_describe_schema() {
+        // Arrange
+        let conn = &mut test_connection();
+
+        // Act
+        let result =
+            describe_schema(conn, &["variables", "api_paths_variables_association"]).unwrap();
+
+        // Assert
+        assert_eq!(
+            result,
+            vec![
+                TableConstraints {
+                    table: "variables".to_string(),
+                    primary_key: vec!["variables_pkey".to_string()],
+                    unique: get_unique_constraints(conn, "variables").unwrap(),
+                    foreign_keys: vec![],
+                    checks: vec!["variables_name_check".to_string()],
+                },
+                TableConstraints {
+                    table: "api_paths_variables_association".to_string(),
+                    primary_key: vec!["api_paths_variables_association_pkey".to_string()],
+                    unique: vec![
+                        "api_paths_variables_association_api_paths_id_variables_id_key".to_string()
+                    ],
+                    foreign_keys: vec![
+                        "api_paths_variables_association_api_paths_id_fkey".to_string(),
+                        "api_paths_variables_association_variables_id_fkey".to_string(),
+                    ],
+                    checks: vec![],
+                },
+            ]
+        );
+    }
+}
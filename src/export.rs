@@ -0,0 +1,293 @@
+//! Export what was ingested as CSV, for use outside Postgres.
+//!
+//! Array columns are flattened into strings in the format of the source JSON: a `label` is
+//! joined like `VariablesItem` serializes it, see `join_label`, and `group`, `attributes`, `requires` and `wildcard` with commas.
+use crate::parse_variables::join_label;
+use crate::query::{geographies_for, variables_for};
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::io::Write;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("Database error: {0}")]
+    Database(#[from] DieselError),
+
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// A row of the variables CSV. Columns are named after the fields of variables.json.
+#[derive(Serialize)]
+struct VariableRow<'a> {
+    name: &'a str,
+    label: String,
+    concept: Option<&'a str>,
+    required: Option<&'a str>,
+    #[serde(rename = "predicateType")]
+    predicate_type: Option<&'a str>,
+    group: Option<String>,
+    limit: Option<i32>,
+    #[serde(rename = "predicateOnly")]
+    predicate_only: Option<bool>,
+    attributes: Option<String>,
+}
+
+/// A row of the geography CSV. Columns are named after the fields of geography.json.
+#[derive(Serialize)]
+struct GeographyRow<'a> {
+    name: &'a str,
+    #[serde(rename = "geoLevelDisplay")]
+    geo_level_display: Option<&'a str>,
+    #[serde(rename = "geoLevelId")]
+    geo_level_id: Option<&'a str>,
+    #[serde(rename = "referenceDate")]
+    reference_date: Option<String>,
+    requires: Option<String>,
+    wildcard: Option<String>,
+    limit: Option<i32>,
+    #[serde(rename = "optionalWithWCFor")]
+    optional_with_wildcard_for: Option<&'a str>,
+}
+
+/// Join `values` with commas, as in the `group` and `attributes` fields of variables.json.
+fn join_comma(values: &Option<Vec<Cow<str>>>) -> Option<String> {
+    values.as_ref().map(|values| values.join(","))
+}
+
+/// Write the variables of the API paths of a dataset and vintage as CSV with a header row,
+/// ordered by name. See `variables_for`.
+///
+/// # Arguments
+///
+/// * `conn` - the connection to the database
+/// * `dataset` - an element of the API paths' `c_dataset`, e.g. "acs5"
+/// * `vintage` - the API paths' `c_vintage`
+/// * `writer` - where to write the CSV
+///
+/// # Returns
+///
+/// The number of variables written.
+pub fn export_variables_csv(
+    conn: &mut PgConnection,
+    dataset: &str,
+    vintage: i32,
+    writer: impl Write,
+) -> Result<usize, ExportError> {
+    let variables = variables_for(conn, dataset, vintage)?;
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for variable in &variables {
+        csv_writer.serialize(VariableRow {
+            name: &variable.name,
+            label: join_label(&variable.label),
+            concept: variable.concept.as_deref(),
+            required: variable.required.as_deref(),
+            predicate_type: variable.predicate_type.as_deref(),
+            group: join_comma(&variable.group),
+            limit: variable.limit,
+            predicate_only: variable.predicate_only,
+            attributes: join_comma(&variable.attributes),
+        })?;
+    }
+    csv_writer.flush()?;
+    Ok(variables.len())
+}
+
+/// Write the geographies of the API paths of a dataset and vintage as CSV with a header row,
+/// ordered by `geoLevelId`. See `geographies_for`.
+///
+/// # Arguments
+///
+/// * `conn` - the connection to the database
+/// * `dataset` - an element of the API paths' `c_dataset`, e.g. "acs5"
+/// * `vintage` - the API paths' `c_vintage`
+/// * `writer` - where to write the CSV
+///
+/// # Returns
+///
+/// The number of geographies written.
+pub fn export_geography_csv(
+    conn: &mut PgConnection,
+    dataset: &str,
+    vintage: i32,
+    writer: impl Write,
+) -> Result<usize, ExportError> {
+    let geographies = geographies_for(conn, dataset, vintage)?;
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for item in &geographies {
+        csv_writer.serialize(GeographyRow {
+            name: &item.name,
+            geo_level_display: item.geo_level_display.as_deref(),
+            geo_level_id: item.geo_level_id.as_deref(),
+            reference_date: item.reference_date().to_json_string(),
+            requires: join_comma(&item.requires),
+            wildcard: join_comma(&item.wildcard),
+            limit: item.limit,
+            optional_with_wildcard_for: item.optional_with_wildcard_for.as_deref(),
+        })?;
+    }
+    csv_writer.flush()?;
+    Ok(geographies.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse_geography::GeographyCollection;
+    use crate::parse_variables::VariablesCollection;
+    use crate::test_utils::{insert_api_paths, test_connection};
+
+    /// Parse `label` as the `label` field of variables.json.
+    fn parse_label(label: &str) -> Vec<String> {
+        let json = serde_json::json!({ "variables": { "X": { "label": label, "group": "N/A" } } })
+            .to_string();
+        let collection: VariablesCollection = serde_json::from_str(&json).unwrap();
+        collection.variables[0]
+            .label
+            .iter()
+            .map(|segment| segment.to_string())
+            .collect()
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_export_variables_csv() {
+        use crate::schema::{api_paths_variables_association, variables};
+
+        // Arrange
+        let conn = &mut test_connection();
+        let [api_path_id] = insert_api_paths(conn, [(2099, "acs/acs5")]);
+        let variables_json = r#"
+    {
+      "variables": {
+        "B99001_002E": {
+          "label": "Estimate!!Total:!!Male, \"all\" ages:",
+          "concept": "SEX BY AGE",
+          "predicateType": "int",
+          "group": "B99001",
+          "limit": 0,
+          "attributes": "B99001_002EA,B99001_002M"
+        }
+      }
+    }"#;
+        let collection: VariablesCollection =
+            serde_json::from_str(variables_json).expect("Error parsing JSON");
+        let variable_id: i32 = diesel::insert_into(variables::table)
+            .values(&collection.variables[0])
+            .returning(variables::id)
+            .get_result(conn)
+            .unwrap();
+        diesel::insert_into(api_paths_variables_association::table)
+            .values((
+                api_paths_variables_association::api_paths_id.eq(api_path_id),
+                api_paths_variables_association::variables_id.eq(variable_id),
+            ))
+            .execute(conn)
+            .unwrap();
+        let mut csv_bytes = Vec::new();
+
+        // Act
+        let written = export_variables_csv(conn, "acs5", 2099, &mut csv_bytes).unwrap();
+
+        // Assert
+        assert_eq!(written, 1);
+        let mut reader = csv::Reader::from_reader(csv_bytes.as_slice());
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec![
+                "name",
+                "label",
+                "concept",
+                "required",
+                "predicateType",
+                "group",
+                "limit",
+                "predicateOnly",
+                "attributes"
+            ]
+        );
+        let records: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(&record[0], "B99001_002E");
+        // The colon that ends a segment with children is restored, as in variables.json.
+        assert_eq!(&record[1], "Estimate!!Total:!!Male, \"all\" ages");
+        assert_eq!(parse_label(&record[1]), collection.variables[0].label);
+        assert_eq!(&record[5], "B99001");
+        assert_eq!(&record[8], "B99001_002EA,B99001_002M");
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_export_geography_csv() {
+        use crate::schema::{api_paths_geography_association, geography};
+
+        // Arrange
+        let conn = &mut test_connection();
+        let [api_path_id] = insert_api_paths(conn, [(2099, "acs/acs5")]);
+        let geography_json = r#"
+    {
+      "fips": [
+        {
+          "name": "tract",
+          "geoLevelDisplay": "140",
+          "referenceDate": "2099-01",
+          "requires": ["state", "county"],
+          "wildcard": ["county"],
+          "optionalWithWCFor": "county"
+        }
+      ]
+    }"#;
+        let collection: GeographyCollection =
+            serde_json::from_str(geography_json).expect("Error parsing JSON");
+        let geography_id: i32 = diesel::insert_into(geography::table)
+            .values(&collection.fips[0])
+            .returning(geography::id)
+            .get_result(conn)
+            .unwrap();
+        diesel::insert_into(api_paths_geography_association::table)
+            .values((
+                api_paths_geography_association::api_paths_id.eq(api_path_id),
+                api_paths_geography_association::geography_id.eq(geography_id),
+            ))
+            .execute(conn)
+            .unwrap();
+        let mut csv_bytes = Vec::new();
+
+        // Act
+        let written = export_geography_csv(conn, "acs5", 2099, &mut csv_bytes).unwrap();
+
+        // Assert
+        assert_eq!(written, 1);
+        let mut reader = csv::Reader::from_reader(csv_bytes.as_slice());
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec![
+                "name",
+                "geoLevelDisplay",
+                "geoLevelId",
+                "referenceDate",
+                "requires",
+                "wildcard",
+                "limit",
+                "optionalWithWCFor"
+            ]
+        );
+        let records: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(&record[0], "tract");
+        assert_eq!(&record[1], "140");
+        // Only the parts of the date given by its precision are written, as in geography.json.
+        assert_eq!(&record[3], "2099-01");
+        assert_eq!(&record[4], "state,county");
+        assert_eq!(&record[5], "county");
+        assert_eq!(&record[7], "county");
+    }
+}
@@ -1,7 +1,21 @@
-use reqwest::Client;
-use std::collections::VecDeque;
+use crate::cache::{CacheBackend, CachedBody, FileCache};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use futures::StreamExt;
+use md5::{Digest, Md5};
+use reqwest::header::{
+    HeaderMap, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, ETAG, LAST_MODIFIED,
+};
+use reqwest::{Client, RequestBuilder};
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::hash::{BuildHasher, Hasher};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use url::Url;
 
 #[derive(Debug, thiserror::Error)]
@@ -17,6 +31,58 @@ pub enum FetchError {
 
     #[error("Path error: {0}")]
     PathError(String),
+
+    #[error("Expected JSON but got an HTML page from {url}")]
+    UnexpectedContent { url: String },
+
+    #[error("HTTP status {status} from {url}")]
+    HttpStatus {
+        url: String,
+        status: reqwest::StatusCode,
+    },
+
+    #[error("Request to {url} timed out after {timeout:?}")]
+    Timeout { url: String, timeout: Duration },
+
+    #[error("{0} isn't cached and the client is offline")]
+    CacheMiss(Url),
+
+    #[error("Gave up on {url} after {retries} retries: {source}")]
+    RetriesExhausted {
+        url: String,
+        retries: u32,
+        source: Box<FetchError>,
+    },
+}
+
+impl FetchError {
+    /// Return whether the error is likely transient, so that the request is worth retrying:
+    /// timeouts, connection errors, `429 Too Many Requests` and 5xx responses, interrupted IO,
+    /// and HTML pages, which the Census API serves during outages.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            FetchError::RequestError(e) => {
+                e.is_timeout() || e.is_connect() || e.status().is_some_and(is_retryable_status)
+            }
+            FetchError::HttpStatus { status, .. } => is_retryable_status(*status),
+            FetchError::IoError(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::WouldBlock
+            ),
+            FetchError::UnexpectedContent { .. } | FetchError::Timeout { .. } => true,
+            FetchError::UrlParseError(_)
+            | FetchError::PathError(_)
+            | FetchError::RetriesExhausted { .. }
+            | FetchError::CacheMiss(_) => false,
+        }
+    }
+}
+
+/// Return whether a response with `status` is worth retrying: `429 Too Many Requests` and 5xx.
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
 }
 
 /// Path to a cache file.
@@ -31,26 +97,12 @@ impl CachePath {
     fn new(dir: PathBuf, file: String) -> Self {
         CachePath { dir, file }
     }
-    /// Create a new `CachePath` instance, validating that `dir` does not contain
-    /// a file extension and that `file` does.
-    fn try_new(dir: PathBuf, file: String) -> Result<Self, FetchError> {
-        if dir.extension().is_some() {
-            return Err(FetchError::PathError(format!(
-                "Directory name must not have an extension. Directory: {}",
-                dir.display()
-            )));
-        }
-        if Path::new(&file).extension().is_none() {
-            return Err(FetchError::PathError(format!(
-                "File name must have an extension but got: {}",
-                file
-            )));
-        }
-        Ok(CachePath { dir, file })
-    }
-
     /// Create child directory of `base_dir` from a URL's path.
     ///
+    /// If the URL has a query string, its MD5 hash is inserted before the file extension, e.g.
+    /// `variables.<hash>.json`, so that URLs that differ only by their query are cached
+    /// separately.
+    ///
     /// # Arguments
     ///
     /// * `url` - The URL to parse and create the path file from
@@ -60,7 +112,7 @@ impl CachePath {
     ///
     /// * `Ok(CachePath)` - The cache path
     /// * `Err(FetchError)` - An error if the URL contains no path elements or if the last path
-    ///     element does not seem to represent a file
+    ///   element does not seem to represent a file
     fn from_url(url: &Url, base_dir: &Path) -> Result<Self, FetchError> {
         let mut url_segments = match url.path_segments() {
             None => {
@@ -88,17 +140,76 @@ impl CachePath {
         for segment in url_segments {
             cache_dir.push(segment);
         }
+        let file = match url.query().filter(|query| !query.is_empty()) {
+            Some(query) => {
+                // The last segment contains a period, checked above.
+                let (stem, extension) = last_url_segment.rsplit_once('.').unwrap();
+                let query_hash = Md5::digest(query.as_bytes());
+                format!("{}.{:x}.{}", stem, query_hash, extension)
+            }
+            None => last_url_segment,
+        };
         Ok(CachePath {
             dir: cache_dir,
-            file: last_url_segment,
+            file,
         })
     }
 
+    /// Reconstruct the URL that `from_url` created this path from: the inverse of `from_url`.
+    ///
+    /// The query string of a URL is only kept as its hash, so the URL of a file cached with a
+    /// query is returned without it, e.g. `.../variables.<hash>.json`.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_dir` - The base directory passed to `from_url`
+    /// * `scheme_host` - The scheme and host to prepend, e.g. `https://api.census.gov`
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Url)` - The URL
+    /// * `Err(FetchError)` - An error if the directory isn't under `base_dir`, or if
+    ///   `scheme_host` isn't a URL that can have a path
+    fn to_url(&self, base_dir: &Path, scheme_host: &str) -> Result<Url, FetchError> {
+        let relative_dir = self.dir.strip_prefix(base_dir).map_err(|_| {
+            FetchError::PathError(format!(
+                "Cache directory '{}' is not under the base directory '{}'",
+                self.dir.display(),
+                base_dir.display()
+            ))
+        })?;
+        let mut segments = Vec::new();
+        for component in relative_dir.components() {
+            match component {
+                Component::Normal(segment) => segments.push(segment.to_string_lossy()),
+                _ => {
+                    return Err(FetchError::PathError(format!(
+                        "Cache directory '{}' contains a component that isn't a URL path segment",
+                        self.dir.display()
+                    )))
+                }
+            }
+        }
+        segments.push(Cow::Borrowed(self.file.as_str()));
+
+        let mut url = Url::parse(scheme_host)?;
+        if url.cannot_be_a_base() {
+            return Err(FetchError::PathError(format!(
+                "Expected a scheme and host, e.g. 'https://api.census.gov', but got: '{}'",
+                scheme_host
+            )));
+        }
+        url.set_path(&segments.join("/"));
+        Ok(url)
+    }
+
     /// Return the directory path.
+    #[cfg(test)]
     fn dir(&self) -> &Path {
         &self.dir
     }
     /// Return the file name without the directory.
+    #[cfg(test)]
     fn file(&self) -> &str {
         &self.file
     }
@@ -107,9 +218,9 @@ impl CachePath {
         self.dir.join(&self.file)
     }
 
-    /// Return whether the file exists.
-    fn exists(&self) -> bool {
-        self.path().exists()
+    /// Return the path of the file storing the response's `ETag` or `Last-Modified` header.
+    fn validator_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.etag", self.file))
     }
 
     /// Create the directory if it does not exist.
@@ -119,23 +230,308 @@ impl CachePath {
     }
 }
 
+/// The `reqwest::Client` used by a `CachedClient`, either borrowed or shared, or none for a
+/// client created with `CachedClient::offline`.
+#[derive(Debug, Clone)]
+enum ClientHandle<'a> {
+    Borrowed(&'a Client),
+    Shared(Arc<Client>),
+    Offline,
+}
+
+impl ClientHandle<'_> {
+    fn get(&self) -> Option<&Client> {
+        match self {
+            ClientHandle::Borrowed(client) => Some(client),
+            ClientHandle::Shared(client) => Some(client),
+            ClientHandle::Offline => None,
+        }
+    }
+}
+
+/// State shared by all clones of a `CachedClient`.
+#[derive(Debug, Clone)]
+struct SharedState {
+    base_cache_dir: PathBuf,
+    namespace: Option<String>,
+    backend: Arc<dyn CacheBackend>,
+    timeout: Option<Duration>,
+    ttl: Option<Duration>,
+    memory: Option<Arc<MemoryCache>>,
+    headers: HeaderMap,
+    api_key: Option<String>,
+    offline: bool,
+    base_url: Option<Url>,
+    counters: Arc<CacheCounters>,
+}
+
+/// The counters behind `CachedClient::stats`.
+#[derive(Debug, Default)]
+struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    bytes_fetched: AtomicU64,
+}
+
+/// How effective a `CachedClient`'s cache was, e.g. to tell a cold run from a warm one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of `fetch`es answered from memory or the cache backend.
+    pub hits: u64,
+    /// Number of `fetch`es that weren't cached, whether or not the request then succeeded.
+    pub misses: u64,
+    /// Total size of the response bodies fetched from the network, after decompression.
+    pub bytes_fetched: u64,
+}
+
+impl CacheStats {
+    /// Return the fraction of `fetch`es that were cache hits, or `None` before the first one.
+    pub fn hit_ratio(&self) -> Option<f64> {
+        match self.hits + self.misses {
+            0 => None,
+            total => Some(self.hits as f64 / total as f64),
+        }
+    }
+}
+
+/// Response bodies kept in memory by `CachedClient::with_memory_cache`, with when they were
+/// stored.
+type MemoryCache = Mutex<HashMap<Url, (Instant, String)>>;
+
+impl SharedState {
+    fn new(base_cache_dir: PathBuf) -> Self {
+        SharedState {
+            base_cache_dir,
+            namespace: None,
+            backend: Arc::new(FileCache),
+            timeout: None,
+            ttl: None,
+            memory: None,
+            headers: HeaderMap::new(),
+            api_key: None,
+            offline: false,
+            base_url: None,
+            counters: Arc::default(),
+        }
+    }
+}
+
 /// An HTTP client that caches responses.
+///
+/// Cloning is cheap: clones share the same cache directory and state. A client created with
+/// `CachedClient::shared` is `'static`, so its clones can be moved into spawned tasks.
+#[derive(Debug, Clone)]
 pub struct CachedClient<'a> {
-    base_cache_dir: PathBuf,
-    client: &'a Client,
+    client: ClientHandle<'a>,
+    state: Arc<SharedState>,
 }
 
 impl<'a> CachedClient<'a> {
+    /// Create a client that borrows `client`.
     pub fn new(base_cache_dir: PathBuf, client: &'a Client) -> Self {
         CachedClient {
-            base_cache_dir,
-            client,
+            client: ClientHandle::Borrowed(client),
+            state: Arc::new(SharedState::new(base_cache_dir)),
+        }
+    }
+
+    /// Create a client that borrows `client` and gives up on each request after `timeout`,
+    /// returning `FetchError::Timeout`.
+    pub fn with_timeout(base_cache_dir: PathBuf, client: &'a Client, timeout: Duration) -> Self {
+        let mut state = SharedState::new(base_cache_dir);
+        state.timeout = Some(timeout);
+        CachedClient {
+            client: ClientHandle::Borrowed(client),
+            state: Arc::new(state),
+        }
+    }
+
+    /// Store responses with `backend` instead of the default `FileCache`. Clones made before
+    /// calling this keep the previous backend.
+    pub fn with_backend(mut self, backend: Arc<dyn CacheBackend>) -> Self {
+        let mut state = SharedState::clone(&self.state);
+        state.backend = backend;
+        self.state = Arc::new(state);
+        self
+    }
+
+    /// Treat cached responses older than `ttl` as missing, so that `fetch` requests and caches
+    /// them again. A response's age is that of the file at its cache path. Clones made before
+    /// calling this keep the previous TTL.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        let mut state = SharedState::clone(&self.state);
+        state.ttl = Some(ttl);
+        self.state = Arc::new(state);
+        self
+    }
+
+    /// Whether to also keep responses in memory, so that fetching a URL again reads neither
+    /// the network nor the cache backend. Bodies are kept until the client and all its clones
+    /// are dropped, so leave this disabled, the default, for jobs that fetch many large files.
+    ///
+    /// A body in memory expires `ttl` after it was stored in memory. Clones made before calling
+    /// this keep their previous memory cache.
+    pub fn with_memory_cache(mut self, enabled: bool) -> Self {
+        let mut state = SharedState::clone(&self.state);
+        state.memory = enabled.then(|| Arc::new(MemoryCache::default()));
+        self.state = Arc::new(state);
+        self
+    }
+
+    /// Send `headers` with every request, e.g. those required by a gateway in front of the
+    /// Census API. Clones made before calling this keep the previous headers.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        let mut state = SharedState::clone(&self.state);
+        state.headers = headers;
+        self.state = Arc::new(state);
+        self
+    }
+
+    /// Append `key=<api_key>` to the query of the URLs that `fetch` requests, which raises the
+    /// Census API's rate limit. The key isn't part of the cache path, so responses cached with
+    /// one key, or none, are reused with another. Clones made before calling this keep the
+    /// previous key.
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        let mut state = SharedState::clone(&self.state);
+        state.api_key = Some(api_key.to_string());
+        self.state = Arc::new(state);
+        self
+    }
+
+    /// Whether to only read the cache and never send requests. `fetch` then returns
+    /// `FetchError::CacheMiss` for URLs that aren't cached, e.g. in CI runs against a
+    /// pre-populated cache. Clones made before calling this keep the previous setting.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        let mut state = SharedState::clone(&self.state);
+        state.offline = offline;
+        self.state = Arc::new(state);
+        self
+    }
+
+    /// Send the requests for Census API URLs, those whose host is `api.census.gov`, to the
+    /// scheme, host and port of `base_url` instead, e.g. a local mirror or a server of
+    /// recorded fixtures. The path and query of the URL are kept, and `base_url`'s path is
+    /// ignored. Responses are cached under the path of the original URL, so a cache filled
+    /// from a mirror is reused with the Census API and vice versa. Clones made before calling
+    /// this keep the previous base URL.
+    pub fn with_base_url(mut self, base_url: Url) -> Self {
+        let mut state = SharedState::clone(&self.state);
+        state.base_url = Some(base_url);
+        self.state = Arc::new(state);
+        self
+    }
+
+    /// Return the URL to request for `url`: `url` itself, or `url` on the base URL set with
+    /// `with_base_url` if it's a Census API URL.
+    fn request_url(&self, url: &Url) -> Url {
+        match &self.state.base_url {
+            Some(base_url) => rewrite_url(url, base_url),
+            None => url.clone(),
+        }
+    }
+
+    /// Return the `reqwest::Client` to send requests with, or `FetchError::CacheMiss` if the
+    /// client is offline.
+    fn http_client(&self, url: &Url) -> Result<&Client, FetchError> {
+        match self.client.get() {
+            Some(client) if !self.state.offline => Ok(client),
+            _ => Err(FetchError::CacheMiss(url.clone())),
+        }
+    }
+
+    /// Cache responses under `<base_cache_dir>/<namespace>/` instead of `<base_cache_dir>/`,
+    /// so that clients of different environments, e.g. a staging mirror and production, can
+    /// share a base directory without sharing responses. Clones made before calling this keep
+    /// the previous namespace.
+    ///
+    /// # Returns
+    ///
+    /// * `Err(FetchError::PathError)` - If `namespace` isn't a single directory name
+    pub fn with_namespace(mut self, namespace: &str) -> Result<Self, FetchError> {
+        let mut components = Path::new(namespace).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            return Err(FetchError::PathError(format!(
+                "Cache namespace must be a single directory name but got: '{}'",
+                namespace
+            )));
+        }
+        let mut state = SharedState::clone(&self.state);
+        state.namespace = Some(namespace.to_string());
+        self.state = Arc::new(state);
+        Ok(self)
+    }
+
+    /// Return the directory under which responses are cached, not including the namespace.
+    pub fn base_cache_dir(&self) -> &Path {
+        &self.state.base_cache_dir
+    }
+
+    /// Return the cache statistics of the `fetch`es of this client and its clones so far.
+    pub fn stats(&self) -> CacheStats {
+        let counters = &self.state.counters;
+        CacheStats {
+            hits: counters.hits.load(Ordering::Relaxed),
+            misses: counters.misses.load(Ordering::Relaxed),
+            bytes_fetched: counters.bytes_fetched.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Return the namespace set with `with_namespace`, if any.
+    pub fn namespace(&self) -> Option<&str> {
+        self.state.namespace.as_deref()
+    }
+
+    /// Return the directory that URL paths are appended to: the base cache directory, followed
+    /// by the namespace if there's one.
+    fn cache_root(&self) -> Cow<'_, Path> {
+        match self.namespace() {
+            Some(namespace) => Cow::Owned(self.base_cache_dir().join(namespace)),
+            None => Cow::Borrowed(self.base_cache_dir()),
+        }
+    }
+
+    /// Delete the cached response of `url`, if there's one, so that the next `fetch` requests
+    /// it again.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(bool)` - Whether a cached response was deleted
+    /// * `Err(FetchError)` - An error if `url` has no cache path or the file can't be deleted
+    pub fn invalidate(&self, url: &Url) -> Result<bool, FetchError> {
+        if let Some(memory) = &self.state.memory {
+            memory.lock().unwrap().remove(url);
+        }
+        let cache_path = CachePath::from_url(url, &self.cache_root())?;
+        match fs::remove_file(cache_path.path()) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Delete every file under the cache directory, i.e. under the namespace if there's one.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(usize)` - The number of files deleted
+    /// * `Err(FetchError)` - An error if a file or directory can't be deleted
+    pub fn clear(&self) -> Result<usize, FetchError> {
+        if let Some(memory) = &self.state.memory {
+            memory.lock().unwrap().clear();
+        }
+        match remove_dir_contents(&self.cache_root()) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            result => Ok(result?),
         }
     }
 
     /// Query the URL and return the response as a string.
     ///
-    /// If the response is already cached, return the cached response without querying.
+    /// If the response is already cached, in memory or by the cache backend, and not older
+    /// than the TTL set with `with_ttl`, return the cached response without querying.
     ///
     /// # Arguments
     ///
@@ -145,22 +541,506 @@ impl<'a> CachedClient<'a> {
     ///
     /// * `Ok(String)` - The response body as a string
     /// * `Err(FetchError)` - An error if the request fails or an error occured while creating the cache file or folder
+    /// * `Err(FetchError::HttpStatus)` - If the response status isn't 2xx. It isn't cached.
+    /// * `Err(FetchError::Timeout)` - If the client was created with `with_timeout` and the
+    ///   request took longer
+    /// * `Err(FetchError::CacheMiss)` - If the client is offline and the response isn't cached
+    /// * `Err(FetchError::UnexpectedContent)` - If the response is an HTML page, e.g. an error
+    ///   page sent with `200 OK`. It isn't cached.
+    #[tracing::instrument(level = "debug", skip_all, fields(%url))]
     pub async fn fetch(&self, url: &Url) -> Result<String, FetchError> {
-        let cache_path = CachePath::from_url(url, &self.base_cache_dir)?;
-        if cache_path.exists() {
-            return Ok(tokio::fs::read_to_string(&cache_path.path()).await?);
+        let Some(memory) = &self.state.memory else {
+            return self.fetch_from_backend(url).await;
+        };
+        if let Some((stored, body)) = memory.lock().unwrap().get(url) {
+            if self.state.ttl.map_or(true, |ttl| stored.elapsed() <= ttl) {
+                tracing::debug!(source = "memory", "cache hit");
+                self.state.counters.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(body.clone());
+            }
+        }
+        let body = self.fetch_from_backend(url).await?;
+        memory
+            .lock()
+            .unwrap()
+            .insert(url.clone(), (Instant::now(), body.clone()));
+        Ok(body)
+    }
+
+    /// Like `fetch`, but without the memory cache.
+    async fn fetch_from_backend(&self, url: &Url) -> Result<String, FetchError> {
+        let cache_path = CachePath::from_url(url, &self.cache_root())?.path();
+        if let Some(body) = self.read_cache(cache_path.clone()).await? {
+            tracing::debug!(source = "backend", "cache hit");
+            self.state.counters.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(body);
+        }
+        self.state.counters.misses.fetch_add(1, Ordering::Relaxed);
+        self.fetch_from_network(url, cache_path).await
+    }
+
+    /// Like `fetch`, but return a reader over the cached body instead of reading it into a
+    /// `String`, for responses too large to hold in memory twice, such as `variables.json`.
+    /// The memory cache isn't used.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to fetch
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(CachedBody)` - A reader over the cached response body
+    /// * `Err(FetchError)` - The same errors as `fetch`
+    #[tracing::instrument(level = "debug", skip_all, fields(%url))]
+    pub async fn fetch_reader(&self, url: &Url) -> Result<CachedBody, FetchError> {
+        let cache_path = CachePath::from_url(url, &self.cache_root())?.path();
+        if let Some(body) = self.open_cache(cache_path.clone(), self.state.ttl).await? {
+            tracing::debug!(source = "backend", "cache hit");
+            self.state.counters.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(body);
+        }
+        self.state.counters.misses.fetch_add(1, Ordering::Relaxed);
+        let body = self.fetch_from_network(url, cache_path.clone()).await?;
+        // A backend may not keep what it's given, in which case the body is read from memory.
+        Ok(self
+            .open_cache(cache_path, None)
+            .await?
+            .unwrap_or_else(|| CachedBody::from(body)))
+    }
+
+    /// Fetch `url` from the network and cache the body at `cache_path`.
+    async fn fetch_from_network(
+        &self,
+        url: &Url,
+        cache_path: PathBuf,
+    ) -> Result<String, FetchError> {
+        let http_client = self.http_client(url)?;
+        tracing::debug!("cache miss, fetching from the network");
+        let mut request_url = self.request_url(url);
+        if let Some(api_key) = &self.state.api_key {
+            request_url.query_pairs_mut().append_pair("key", api_key);
+        }
+        let response = self
+            .prepare_request(
+                http_client
+                    .get(request_url)
+                    .header(ACCEPT_ENCODING, "gzip, deflate"),
+            )
+            .send()
+            .await
+            .map_err(|e| self.request_error(url, e))?;
+        if !response.status().is_success() {
+            return Err(FetchError::HttpStatus {
+                url: url.to_string(),
+                status: response.status(),
+            });
+        }
+        let is_html_content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|content_type| content_type.contains("html"));
+        // `reqwest` decompresses the body and removes `Content-Encoding`, unless the client was
+        // built without gzip or deflate support.
+        let content_encoding = response
+            .headers()
+            .get(CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_ascii_lowercase);
+        let response = match content_encoding {
+            Some(content_encoding) => decode_body(
+                &response
+                    .bytes()
+                    .await
+                    .map_err(|e| self.request_error(url, e))?,
+                &content_encoding,
+            )?,
+            None => response
+                .text()
+                .await
+                .map_err(|e| self.request_error(url, e))?,
+        };
+        if is_html_content_type || response.trim_start().starts_with('<') {
+            return Err(FetchError::UnexpectedContent {
+                url: url.to_string(),
+            });
+        }
+        self.state
+            .counters
+            .bytes_fetched
+            .fetch_add(response.len() as u64, Ordering::Relaxed);
+        self.write_cache(cache_path, response).await
+    }
+
+    /// Fetch `urls` with up to `concurrency` requests at once and return the results in the
+    /// order of `urls`. A `concurrency` of `0` is treated as `1`.
+    ///
+    /// The Census API throttles clients that send many requests at once, particularly without
+    /// an API key (see `with_api_key`), so keep `concurrency` at about 16 or less.
+    ///
+    /// # Arguments
+    ///
+    /// * `urls` - The URLs to fetch
+    /// * `concurrency` - The maximum number of requests in flight
+    pub async fn fetch_all(
+        &self,
+        urls: &[Url],
+        concurrency: usize,
+    ) -> Vec<Result<String, FetchError>> {
+        let mut results: Vec<(usize, Result<String, FetchError>)> =
+            futures::stream::iter(urls.iter().enumerate())
+                .map(|(index, url)| async move { (index, self.fetch(url).await) })
+                .buffer_unordered(concurrency.max(1))
+                .collect()
+                .await;
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Like `fetch`, but retry errors for which `FetchError::is_retryable` is `true`, such as
+    /// connection errors and 5xx responses, up to `max_retries` times.
+    ///
+    /// The delay before the n-th retry is `base_delay * 2^(n - 1)`, plus up to half of that
+    /// again at random so that concurrent clients don't retry in lockstep.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL to fetch
+    /// * `max_retries` - The maximum number of requests after the first one
+    /// * `base_delay` - The delay before the first retry
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(String)` - The response body as a string
+    /// * `Err(FetchError::RetriesExhausted)` - If the last retry failed, too. `source` is its
+    ///   error.
+    /// * `Err(FetchError)` - The first error that isn't retryable
+    pub async fn fetch_with_retry(
+        &self,
+        url: &Url,
+        max_retries: u32,
+        base_delay: Duration,
+    ) -> Result<String, FetchError> {
+        let mut retries = 0;
+        loop {
+            match self.fetch(url).await {
+                Err(e) if e.is_retryable() => {
+                    if retries == max_retries {
+                        return Err(FetchError::RetriesExhausted {
+                            url: url.to_string(),
+                            retries,
+                            source: Box::new(e),
+                        });
+                    }
+                    let delay = backoff(base_delay, retries);
+                    tracing::warn!(%url, error = %e, retry = retries + 1, ?delay, "retrying");
+                    tokio::time::sleep(delay).await;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Apply the headers set with `with_headers` and the timeout set with `with_timeout`, if
+    /// any, to `request`.
+    fn prepare_request(&self, request: RequestBuilder) -> RequestBuilder {
+        let request = request.headers(self.state.headers.clone());
+        match self.state.timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        }
+    }
+
+    /// Convert an error of a request to `url`, distinguishing timeouts. The error's URL is
+    /// removed if it may contain the API key.
+    fn request_error(&self, url: &Url, error: reqwest::Error) -> FetchError {
+        match self.state.timeout {
+            Some(timeout) if error.is_timeout() => FetchError::Timeout {
+                url: url.to_string(),
+                timeout,
+            },
+            _ if self.state.api_key.is_some() => error.without_url().into(),
+            _ => error.into(),
         }
-        let response = self.client.get(url.clone()).send().await?.text().await?;
-        cache_path.create_dir()?;
-        tokio::fs::write(cache_path.path(), &response).await?;
-        Ok(response)
     }
+
+    /// Read the body cached at `path` on the blocking thread pool. Return `None` if it expired.
+    async fn read_cache(&self, path: PathBuf) -> Result<Option<String>, FetchError> {
+        let backend = Arc::clone(&self.state.backend);
+        let ttl = self.state.ttl;
+        Ok(tokio::task::spawn_blocking(move || {
+            if ttl.map_or(Ok(false), |ttl| is_expired(&path, ttl))? {
+                return Ok(None);
+            }
+            backend.read(&path)
+        })
+        .await
+        .map_err(std::io::Error::other)??)
+    }
+
+    /// Open the body cached at `path` on the blocking thread pool. Return `None` if it's older
+    /// than `ttl`.
+    async fn open_cache(
+        &self,
+        path: PathBuf,
+        ttl: Option<Duration>,
+    ) -> Result<Option<CachedBody>, FetchError> {
+        let backend = Arc::clone(&self.state.backend);
+        Ok(tokio::task::spawn_blocking(move || {
+            if ttl.map_or(Ok(false), |ttl| is_expired(&path, ttl))? {
+                return Ok(None);
+            }
+            backend.open(&path)
+        })
+        .await
+        .map_err(std::io::Error::other)??)
+    }
+
+    /// Cache `body` at `path` on the blocking thread pool, then return it.
+    async fn write_cache(&self, path: PathBuf, body: String) -> Result<String, FetchError> {
+        let backend = Arc::clone(&self.state.backend);
+        Ok(
+            tokio::task::spawn_blocking(move || backend.write(&path, &body).map(|_| body))
+                .await
+                .map_err(std::io::Error::other)??,
+        )
+    }
+}
+
+impl CachedClient<'static> {
+    /// Create a client that shares ownership of `client`. Unlike a client created with
+    /// `CachedClient::new`, it can be cloned into `tokio::spawn`ed tasks.
+    pub fn shared(base_cache_dir: PathBuf, client: Arc<Client>) -> Self {
+        CachedClient {
+            client: ClientHandle::Shared(client),
+            state: Arc::new(SharedState::new(base_cache_dir)),
+        }
+    }
+
+    /// Create a client that only reads the cache in `base_cache_dir`, without a
+    /// `reqwest::Client`. See `with_offline`.
+    pub fn offline(base_cache_dir: PathBuf) -> Self {
+        let mut state = SharedState::new(base_cache_dir);
+        state.offline = true;
+        CachedClient {
+            client: ClientHandle::Offline,
+            state: Arc::new(state),
+        }
+    }
+}
+
+/// The host of the Census API, whose URLs `CachedClient::with_base_url` rewrites.
+const CENSUS_API_HOST: &str = "api.census.gov";
+
+/// Return `url` with the scheme, host and port of `base_url` if its host is the Census API's,
+/// and `url` unchanged otherwise.
+fn rewrite_url(url: &Url, base_url: &Url) -> Url {
+    if url.host_str() != Some(CENSUS_API_HOST) {
+        return url.clone();
+    }
+    let mut rewritten = base_url.clone();
+    rewritten.set_path(url.path());
+    rewritten.set_query(url.query());
+    rewritten
+}
+
+/// Decompress a response body sent with `Content-Encoding: <content_encoding>`.
+fn decode_body(body: &[u8], content_encoding: &str) -> std::io::Result<String> {
+    let mut decoded = String::new();
+    match content_encoding {
+        "gzip" | "x-gzip" => GzDecoder::new(body).read_to_string(&mut decoded)?,
+        // HTTP's "deflate" is the zlib format.
+        "deflate" => ZlibDecoder::new(body).read_to_string(&mut decoded)?,
+        _ => return Ok(String::from_utf8_lossy(body).into_owned()),
+    };
+    Ok(decoded)
+}
+
+/// Recursively delete the contents of `dir` but not `dir` itself, and return the number of
+/// files deleted.
+fn remove_dir_contents(dir: &Path) -> std::io::Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            removed += remove_dir_contents(&entry.path())?;
+            fs::remove_dir(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Return whether the file at `path` was last modified more than `ttl` ago. A missing file
+/// hasn't expired.
+fn is_expired(path: &Path, ttl: Duration) -> std::io::Result<bool> {
+    let modified = match fs::metadata(path) {
+        Ok(metadata) => metadata.modified()?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    // A modification time in the future, e.g. after a clock change, counts as fresh.
+    Ok(modified.elapsed().is_ok_and(|age| age > ttl))
+}
+
+/// Return the delay before retry number `retries + 1`: `base_delay * 2^retries` plus a random
+/// jitter of up to half of that.
+fn backoff(base_delay: Duration, retries: u32) -> Duration {
+    let delay = base_delay.saturating_mul(2u32.saturating_pow(retries));
+    // `RandomState` is randomly seeded, which is random enough for jitter.
+    let random = RandomState::new().build_hasher().finish();
+    let jitter = delay.mul_f64((random % 1024) as f64 / 2048.0);
+    delay.saturating_add(jitter)
+}
+
+/// What `check_catalog` found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogCheck {
+    /// Whether the resource changed since the last recorded validator. `true` if none was
+    /// recorded, or if the server sends neither `ETag` nor `Last-Modified`.
+    pub changed: bool,
+    /// The response's `ETag`, or `Last-Modified` if there's no `ETag`. Pass it to
+    /// `record_catalog_validator` once the changed resource was processed.
+    pub validator: Option<String>,
+}
+
+/// Check whether the resource at `url`, e.g. https://api.census.gov/data.json, changed since
+/// the last check, and store its current validator for the next one.
+///
+/// Only the headers are requested. The response's `ETag`, or `Last-Modified` if there's no
+/// `ETag`, is compared against the value stored in the cache directory. Since the new value is
+/// stored right away, a change is reported once, even if processing the resource then fails.
+/// To record it only after processing, use `check_catalog` and `record_catalog_validator`.
+///
+/// # Arguments
+///
+/// * `client` - the client whose cache directory stores the previous `ETag`
+/// * `url` - the URL to check
+///
+/// # Returns
+///
+/// * `Ok(bool)` - whether the resource changed. `true` if it wasn't checked before, or if the
+///   server sends neither `ETag` nor `Last-Modified`.
+/// * `Err(FetchError)` - The same errors as `check_catalog`, or an error if the validator
+///   can't be stored
+pub async fn catalog_changed(client: &CachedClient<'_>, url: &Url) -> Result<bool, FetchError> {
+    let check = check_catalog(client, url).await?;
+    if let (true, Some(validator)) = (check.changed, &check.validator) {
+        record_catalog_validator(client, url, validator).await?;
+    }
+    Ok(check.changed)
+}
+
+/// Like `catalog_changed`, but without storing the validator, so that a change isn't lost if
+/// processing the resource fails: record it with `record_catalog_validator` after.
+///
+/// # Arguments
+///
+/// * `client` - the client whose cache directory stores the previous `ETag`
+/// * `url` - the URL to check
+///
+/// # Returns
+///
+/// * `Ok(CatalogCheck)` - whether the resource changed, and its current validator
+/// * `Err(FetchError::HttpStatus)` - If the response status isn't 2xx
+/// * `Err(FetchError)` - An error if the request fails or the stored value can't be read
+/// * `Err(FetchError::CacheMiss)` - If the client is offline
+pub async fn check_catalog(
+    client: &CachedClient<'_>,
+    url: &Url,
+) -> Result<CatalogCheck, FetchError> {
+    let cache_path = CachePath::from_url(url, &client.cache_root())?;
+    let response = client
+        .prepare_request(client.http_client(url)?.head(client.request_url(url)))
+        .send()
+        .await
+        .map_err(|e| client.request_error(url, e))?;
+    if !response.status().is_success() {
+        return Err(FetchError::HttpStatus {
+            url: url.to_string(),
+            status: response.status(),
+        });
+    }
+    let validator = match response
+        .headers()
+        .get(ETAG)
+        .or_else(|| response.headers().get(LAST_MODIFIED))
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(validator) => validator.to_string(),
+        None => {
+            return Ok(CatalogCheck {
+                changed: true,
+                validator: None,
+            })
+        }
+    };
+
+    let changed = match tokio::fs::read_to_string(cache_path.validator_path()).await {
+        Ok(previous) => previous != validator,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+        Err(e) => return Err(e.into()),
+    };
+    Ok(CatalogCheck {
+        changed,
+        validator: Some(validator),
+    })
+}
+
+/// Store `validator`, as returned by `check_catalog`, as the last seen version of the
+/// resource at `url`, so that the next check compares against it.
+///
+/// # Arguments
+///
+/// * `client` - the client whose cache directory stores the validator
+/// * `url` - the URL that was checked
+/// * `validator` - the `CatalogCheck::validator` of the check
+pub async fn record_catalog_validator(
+    client: &CachedClient<'_>,
+    url: &Url,
+    validator: &str,
+) -> Result<(), FetchError> {
+    let cache_path = CachePath::from_url(url, &client.cache_root())?;
+    cache_path.create_dir()?;
+    tokio::fs::write(cache_path.validator_path(), validator).await?;
+    Ok(())
+}
+
+/// Return the URL whose response is cached at `path`, e.g. to inspect a cache directory.
+///
+/// # Arguments
+///
+/// * `path` - a cached response under `base_dir`, e.g.
+///   `cache/data/2020/acs/acs5/variables.json`
+/// * `base_dir` - the cache directory, followed by the namespace if the client has one
+/// * `scheme_host` - the scheme and host to prepend, e.g. `https://api.census.gov`
+///
+/// # Returns
+///
+/// * `Ok(Url)` - the URL, without its query string if it had one. See `CachePath::to_url`.
+/// * `Err(FetchError)` - An error if `path` isn't a file under `base_dir`
+pub fn cached_url(path: &Path, base_dir: &Path, scheme_host: &str) -> Result<Url, FetchError> {
+    let (dir, file) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(file)) => (dir.to_path_buf(), file.to_string_lossy().into_owned()),
+        _ => {
+            return Err(FetchError::PathError(format!(
+                "Expected the path of a file but got: '{}'",
+                path.display()
+            )))
+        }
+    };
+    CachePath::new(dir, file).to_url(base_dir, scheme_host)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use rstest::rstest;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
     // Assert the result is an error whose message matches the given regex.
     macro_rules! assert_err {
@@ -237,6 +1117,48 @@ mod test {
                 );
             }
 
+            #[rstest]
+            #[case::different_queries(
+                "https://api.census.gov/data/2020/acs/acs5/data.json?get=NAME&for=state:*",
+                "https://api.census.gov/data/2020/acs/acs5/data.json?get=NAME&for=county:*"
+            )]
+            #[case::query_and_none(
+                "https://api.census.gov/data/2020/acs/acs5/variables.json?get=NAME",
+                "https://api.census.gov/data/2020/acs/acs5/variables.json"
+            )]
+            fn test_queries_are_distinct(#[case] first: &str, #[case] second: &str) {
+                // Arrange
+                let base_dir = Path::new(".");
+                let first = Url::parse(first).unwrap();
+                let second = Url::parse(second).unwrap();
+
+                // Act
+                let first_path = CachePath::from_url(&first, base_dir).unwrap();
+                let second_path = CachePath::from_url(&second, base_dir).unwrap();
+
+                // Assert
+                assert_ne!(first_path.path(), second_path.path());
+                assert_eq!(first_path.dir(), second_path.dir());
+            }
+
+            #[rstest]
+            #[case::query(
+                "variables.json?get=NAME",
+                "variables.c9f2507303a10cd366ac25d2e006f331.json"
+            )]
+            #[case::empty_query("variables.json?", "variables.json")]
+            #[case::no_query("variables.json", "variables.json")]
+            fn test_query_file_name(#[case] file: &str, #[case] expected: &str) {
+                // Arrange
+                let url = Url::parse(&format!("https://api.census.gov/data/{}", file)).unwrap();
+
+                // Act
+                let cache_path = CachePath::from_url(&url, Path::new(".")).unwrap();
+
+                // Assert
+                assert_eq!(cache_path.file(), expected);
+            }
+
             #[test]
             fn test_no_path() {
                 // Arrange
@@ -264,5 +1186,1108 @@ mod test {
                 assert_err!(result, Some(".*file extension.*"));
             }
         }
+
+        mod to_url {
+            use super::*;
+
+            #[rstest]
+            #[case::https("https://api.census.gov/data/2020/acs/acs5/variables.json")]
+            #[case::catalog("https://api.census.gov/data.json")]
+            #[case::localhost("http://localhost:8000/data/2020/acs/acs5/geography.json")]
+            fn test_round_trip(#[case] url_str: &str) {
+                // Arrange
+                let url = Url::parse(url_str).unwrap();
+                let base_dir = Path::new("/cache");
+                let scheme_host = &url[..url::Position::BeforePath];
+
+                // Act
+                let cache_path = CachePath::from_url(&url, base_dir).unwrap();
+                let result = cache_path.to_url(base_dir, scheme_host).unwrap();
+
+                // Assert
+                assert_eq!(result, url);
+            }
+
+            #[test]
+            fn test_not_under_base_dir() {
+                // Arrange
+                let url =
+                    Url::parse("https://api.census.gov/data/2020/acs/acs5/variables.json").unwrap();
+                let cache_path = CachePath::from_url(&url, Path::new("/cache")).unwrap();
+
+                // Act
+                let result = cache_path.to_url(Path::new("/elsewhere"), "https://api.census.gov");
+
+                // Assert
+                assert_err!(result, Some(".*not under the base directory.*"));
+            }
+
+            #[test]
+            fn test_cached_url() {
+                // Arrange
+                let path = Path::new("/cache/data/2020/acs/acs5/variables.json");
+
+                // Act
+                let result = cached_url(path, Path::new("/cache"), "https://api.census.gov");
+
+                // Assert
+                assert_eq!(
+                    result.unwrap().as_str(),
+                    "https://api.census.gov/data/2020/acs/acs5/variables.json"
+                );
+            }
+        }
+
+        mod namespace {
+            use super::*;
+
+            #[rstest]
+            #[case::none(None, "/cache/data/2020/acs/acs5/variables.json")]
+            #[case::staging(Some("staging"), "/cache/staging/data/2020/acs/acs5/variables.json")]
+            fn test_namespace_in_path(#[case] namespace: Option<&str>, #[case] expected: &str) {
+                // Arrange
+                let web_client = Client::new();
+                let mut client = CachedClient::new(PathBuf::from("/cache"), &web_client);
+                if let Some(namespace) = namespace {
+                    client = client.with_namespace(namespace).unwrap();
+                }
+                let url =
+                    Url::parse("https://api.census.gov/data/2020/acs/acs5/variables.json").unwrap();
+
+                // Act
+                let cache_path = CachePath::from_url(&url, &client.cache_root()).unwrap();
+
+                // Assert
+                assert_eq!(cache_path.path(), Path::new(expected));
+                assert_eq!(client.base_cache_dir(), Path::new("/cache"));
+            }
+
+            #[rstest]
+            #[case::empty("")]
+            #[case::nested("staging/v2")]
+            #[case::parent("..")]
+            #[case::absolute("/staging")]
+            fn test_invalid_namespace(#[case] namespace: &str) {
+                // Arrange
+                let web_client = Client::new();
+                let client = CachedClient::new(PathBuf::from("/cache"), &web_client);
+
+                // Act
+                let result = client.with_namespace(namespace);
+
+                // Assert
+                assert_err!(result, Some(".*single directory name.*"));
+            }
+        }
+    }
+
+    mod catalog_changed {
+        use super::*;
+
+        async fn mount_etag(server: &MockServer, etag: &str) {
+            server.reset().await;
+            Mock::given(method("HEAD"))
+                .and(path("/data.json"))
+                .respond_with(ResponseTemplate::new(200).insert_header("ETag", etag))
+                .mount(server)
+                .await;
+        }
+
+        #[tokio::test]
+        async fn test_etag_changes() {
+            // Arrange
+            let server = MockServer::start().await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+            let url = Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+
+            // Act & Assert
+            mount_etag(&server, "\"v1\"").await;
+            assert!(catalog_changed(&client, &url).await.unwrap());
+            assert!(!catalog_changed(&client, &url).await.unwrap());
+
+            mount_etag(&server, "\"v2\"").await;
+            assert!(catalog_changed(&client, &url).await.unwrap());
+            assert!(!catalog_changed(&client, &url).await.unwrap());
+        }
+
+        /// A change stays visible until its validator is recorded, e.g. after a failed sync.
+        #[tokio::test]
+        async fn test_unrecorded_change() {
+            // Arrange
+            let server = MockServer::start().await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+            let url = Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+            mount_etag(&server, "\"v1\"").await;
+
+            // Act
+            let first = check_catalog(&client, &url).await.unwrap();
+            let second = check_catalog(&client, &url).await.unwrap();
+
+            // Assert
+            assert_eq!(
+                first,
+                CatalogCheck {
+                    changed: true,
+                    validator: Some("\"v1\"".to_string()),
+                }
+            );
+            assert_eq!(second, first);
+            record_catalog_validator(&client, &url, "\"v1\"")
+                .await
+                .unwrap();
+            assert!(!check_catalog(&client, &url).await.unwrap().changed);
+        }
+
+        #[tokio::test]
+        async fn test_no_validator_is_changed() {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(method("HEAD"))
+                .and(path("/data.json"))
+                .respond_with(ResponseTemplate::new(200))
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+            let url = Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+
+            // Act & Assert
+            assert!(catalog_changed(&client, &url).await.unwrap());
+            assert!(catalog_changed(&client, &url).await.unwrap());
+        }
+
+        /// A non-2xx response is an `HttpStatus` error, classified like one of `fetch`.
+        #[tokio::test]
+        async fn test_http_status() {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(method("HEAD"))
+                .and(path("/data.json"))
+                .respond_with(ResponseTemplate::new(503))
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+            let url = Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+
+            // Act
+            let result = catalog_changed(&client, &url).await;
+
+            // Assert
+            match result {
+                Err(e @ FetchError::HttpStatus { status, .. }) => {
+                    assert_eq!(status.as_u16(), 503);
+                    assert!(e.is_retryable());
+                }
+                other => panic!("Expected FetchError::HttpStatus, got {:?}", other),
+            }
+        }
+    }
+
+    mod is_retryable {
+        use super::*;
+
+        /// Return the error of a GET request to the mock server's response with `status`.
+        async fn status_error(status: u16) -> FetchError {
+            let server = MockServer::start().await;
+            Mock::given(path("/data.json"))
+                .respond_with(ResponseTemplate::new(status))
+                .mount(&server)
+                .await;
+            let response = Client::new()
+                .get(format!("{}/data.json", server.uri()))
+                .send()
+                .await
+                .unwrap();
+            response.error_for_status().unwrap_err().into()
+        }
+
+        #[rstest]
+        #[case::server_error(503, true)]
+        #[case::too_many_requests(429, true)]
+        #[case::not_found(404, false)]
+        #[case::forbidden(403, false)]
+        #[tokio::test]
+        async fn test_status(#[case] status: u16, #[case] expected: bool) {
+            assert_eq!(status_error(status).await.is_retryable(), expected);
+        }
+
+        #[tokio::test]
+        async fn test_timeout() {
+            let server = MockServer::start().await;
+            Mock::given(path("/data.json"))
+                .respond_with(
+                    ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(5)),
+                )
+                .mount(&server)
+                .await;
+            let error: FetchError = Client::new()
+                .get(format!("{}/data.json", server.uri()))
+                .timeout(std::time::Duration::from_millis(10))
+                .send()
+                .await
+                .unwrap_err()
+                .into();
+
+            assert!(error.is_retryable());
+        }
+
+        #[tokio::test]
+        async fn test_connect() {
+            // Nothing listens on the port of a dropped listener.
+            let address = std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap();
+            let error: FetchError = Client::new()
+                .get(format!("http://{}/data.json", address))
+                .send()
+                .await
+                .unwrap_err()
+                .into();
+
+            assert!(error.is_retryable());
+        }
+
+        #[rstest]
+        #[case::url(Url::parse("not a url").unwrap_err().into(), false)]
+        #[case::path(FetchError::PathError("no file extension".to_string()), false)]
+        #[case::permission(
+            std::io::Error::from(std::io::ErrorKind::PermissionDenied).into(),
+            false
+        )]
+        #[case::interrupted(std::io::Error::from(std::io::ErrorKind::Interrupted).into(), true)]
+        #[case::html(FetchError::UnexpectedContent { url: "https://api.census.gov/data.json".to_string() }, true)]
+        #[case::http_status_server_error(FetchError::HttpStatus { url: "https://api.census.gov/data.json".to_string(), status: reqwest::StatusCode::SERVICE_UNAVAILABLE }, true)]
+        #[case::timeout(FetchError::Timeout { url: "https://api.census.gov/data.json".to_string(), timeout: Duration::from_secs(1) }, true)]
+        #[case::cache_miss(FetchError::CacheMiss(Url::parse("https://api.census.gov/data.json").unwrap()), false)]
+        #[case::retries_exhausted(FetchError::RetriesExhausted { url: "https://api.census.gov/data.json".to_string(), retries: 3, source: Box::new(FetchError::UnexpectedContent { url: "https://api.census.gov/data.json".to_string() }) }, false)]
+        #[case::http_status_not_found(FetchError::HttpStatus { url: "https://api.census.gov/data.json".to_string(), status: reqwest::StatusCode::NOT_FOUND }, false)]
+        fn test_other_variants(#[case] error: FetchError, #[case] expected: bool) {
+            assert_eq!(error.is_retryable(), expected);
+        }
+    }
+
+    mod cached_client {
+        use super::*;
+        use crate::cache::{ContentAddressedCache, DedupStats};
+
+        /// The first fetch of a URL is a miss and the second a hit.
+        #[tokio::test]
+        async fn test_stats() {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(path("/data/2020/geography.json"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"fips": []}"#))
+                .expect(1)
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+            let url = Url::parse(&format!("{}/data/2020/geography.json", server.uri())).unwrap();
+            assert_eq!(client.stats().hit_ratio(), None);
+
+            // Act
+            client.fetch(&url).await.unwrap();
+            client.clone().fetch(&url).await.unwrap();
+
+            // Assert
+            let stats = client.stats();
+            assert_eq!(
+                stats,
+                CacheStats {
+                    hits: 1,
+                    misses: 1,
+                    bytes_fetched: 12,
+                }
+            );
+            assert_eq!(stats.hit_ratio(), Some(0.5));
+        }
+
+        /// Identical bodies of different URLs are stored once.
+        #[tokio::test]
+        async fn test_content_addressed_cache() {
+            // Arrange
+            let server = MockServer::start().await;
+            for year in ["2019", "2020"] {
+                Mock::given(path(format!("/data/{}/geography.json", year)))
+                    .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"fips": []}"#))
+                    .expect(1)
+                    .mount(&server)
+                    .await;
+            }
+            let cache_dir = tempfile::tempdir().unwrap();
+            let backend = Arc::new(ContentAddressedCache::new(cache_dir.path().to_path_buf()));
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client)
+                .with_backend(backend.clone());
+            let urls = ["2019", "2020"].map(|year| {
+                Url::parse(&format!("{}/data/{}/geography.json", server.uri(), year)).unwrap()
+            });
+
+            // Act
+            for url in urls.iter().chain(urls.iter()) {
+                assert_eq!(client.fetch(url).await.unwrap(), r#"{"fips": []}"#);
+            }
+
+            // Assert
+            let objects: Vec<_> = fs::read_dir(cache_dir.path().join("objects"))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            // sha256 of `{"fips": []}`
+            let hash = "0c815b974ded5ed5f7372d7029273568df8e2ff2d54fd95a87d7cc4c21e9bab2";
+            assert_eq!(objects, [hash]);
+            let pointer =
+                fs::read_to_string(cache_dir.path().join("data/2019/geography.json")).unwrap();
+            assert_eq!(pointer, format!("sha256:{}", hash));
+            assert_eq!(
+                backend.stats(),
+                DedupStats {
+                    blobs_written: 1,
+                    duplicates: 1,
+                    bytes_saved: 12,
+                }
+            );
+        }
+
+        /// `fetch_reader` streams the body from the network on a miss and from the cache on a
+        /// hit, with either backend.
+        #[rstest]
+        #[case::file(false)]
+        #[case::content_addressed(true)]
+        #[tokio::test]
+        async fn test_fetch_reader(#[case] content_addressed: bool) {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(path("/data/2020/variables.json"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"variables": {}}"#))
+                .expect(1)
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let mut client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+            if content_addressed {
+                client = client.with_backend(Arc::new(ContentAddressedCache::new(
+                    cache_dir.path().to_path_buf(),
+                )));
+            }
+            let url = Url::parse(&format!("{}/data/2020/variables.json", server.uri())).unwrap();
+
+            // Act
+            let mut bodies = Vec::new();
+            for _ in 0..2 {
+                let mut reader = client.fetch_reader(&url).await.unwrap();
+                let mut body = String::new();
+                std::io::Read::read_to_string(&mut reader, &mut body).unwrap();
+                bodies.push((reader.len(), body));
+            }
+
+            // Assert
+            let expected = (17, r#"{"variables": {}}"#.to_string());
+            assert_eq!(bodies, [expected.clone(), expected]);
+            let stats = client.stats();
+            assert_eq!((stats.hits, stats.misses), (1, 1));
+        }
+
+        /// `gc` deletes the objects that no pointer file refers to, and only those.
+        #[tokio::test]
+        async fn test_content_addressed_cache_gc() {
+            // Arrange
+            let server = MockServer::start().await;
+            for (year, body) in [("2019", "{}"), ("2020", "{}"), ("2021", r#"{"fips": []}"#)] {
+                Mock::given(path(format!("/data/{}/geography.json", year)))
+                    .respond_with(ResponseTemplate::new(200).set_body_string(body))
+                    .expect(1)
+                    .mount(&server)
+                    .await;
+            }
+            let cache_dir = tempfile::tempdir().unwrap();
+            let backend = Arc::new(ContentAddressedCache::new(cache_dir.path().to_path_buf()));
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client)
+                .with_backend(backend.clone());
+            let urls = ["2019", "2020", "2021"].map(|year| {
+                Url::parse(&format!("{}/data/{}/geography.json", server.uri(), year)).unwrap()
+            });
+            for url in &urls {
+                client.fetch(url).await.unwrap();
+            }
+            let orphan = fs::read_to_string(cache_dir.path().join("data/2021/geography.json"))
+                .unwrap()
+                .trim_start_matches("sha256:")
+                .to_string();
+            client.invalidate(&urls[1]).unwrap();
+            client.invalidate(&urls[2]).unwrap();
+            // A file holding just a hash, e.g. a validator, isn't a pointer file.
+            fs::write(
+                cache_dir.path().join("data/2021/geography.json.etag"),
+                &orphan,
+            )
+            .unwrap();
+
+            // Act
+            // Not the canonical path, so that it differs textually from the objects directory's.
+            let relative_cache_dir = cache_dir.path().join("objects/..");
+            let deleted = backend.gc(&relative_cache_dir).unwrap();
+
+            // Assert
+            assert_eq!(deleted, 1);
+            assert_eq!(backend.objects_dir().read_dir().unwrap().count(), 1);
+            assert!(!backend.objects_dir().join(&orphan).exists());
+            assert_eq!(client.fetch(&urls[0]).await.unwrap(), "{}");
+        }
+
+        /// HTML error pages sent with `200 OK` are an error and aren't cached.
+        #[rstest]
+        #[case::html_body("text/plain", "<!DOCTYPE html><html><body>Error</body></html>")]
+        #[case::html_content_type("text/html; charset=utf-8", "Error")]
+        #[tokio::test]
+        async fn test_html_is_unexpected(#[case] content_type: &str, #[case] body: &str) {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(path("/data.json"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(body, content_type))
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+            let url = Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+
+            // Act
+            let result = client.fetch(&url).await;
+
+            // Assert
+            assert!(matches!(
+                result,
+                Err(FetchError::UnexpectedContent { url: ref error_url }) if *error_url == url.to_string()
+            ));
+            assert!(!cache_dir.path().join("data.json").exists());
+        }
+
+        /// Error responses are an error and aren't cached.
+        #[rstest]
+        #[case::server_error(500)]
+        #[case::not_found(404)]
+        #[tokio::test]
+        async fn test_error_status_is_not_cached(#[case] status: u16) {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(path("/data/2020/acs/acs5/variables.json"))
+                .respond_with(ResponseTemplate::new(status).set_body_raw("{}", "application/json"))
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+            let url = Url::parse(&format!(
+                "{}/data/2020/acs/acs5/variables.json",
+                server.uri()
+            ))
+            .unwrap();
+
+            // Act
+            let result = client.fetch(&url).await;
+
+            // Assert
+            assert!(matches!(
+                result,
+                Err(FetchError::HttpStatus { status: error_status, .. }) if error_status.as_u16() == status
+            ));
+            assert_eq!(fs::read_dir(cache_dir.path()).unwrap().count(), 0);
+        }
+
+        /// Return a client of the mock server and the URL of its `variables.json`.
+        fn retry_client<'c>(
+            server: &MockServer,
+            cache_dir: &Path,
+            web_client: &'c Client,
+        ) -> (CachedClient<'c>, Url) {
+            let url = Url::parse(&format!("{}/variables.json", server.uri())).unwrap();
+            (CachedClient::new(cache_dir.to_path_buf(), web_client), url)
+        }
+
+        /// Transient errors are retried until the response succeeds, which is cached.
+        #[tokio::test]
+        async fn test_retry_until_success() {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(path("/variables.json"))
+                .respond_with(ResponseTemplate::new(503))
+                .up_to_n_times(2)
+                .expect(2)
+                .mount(&server)
+                .await;
+            Mock::given(path("/variables.json"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("{}", "application/json"))
+                .expect(1)
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let (client, url) = retry_client(&server, cache_dir.path(), &web_client);
+
+            // Act
+            let body = client
+                .fetch_with_retry(&url, 3, Duration::from_millis(1))
+                .await
+                .unwrap();
+
+            // Assert
+            assert_eq!(body, "{}");
+            assert_eq!(client.fetch(&url).await.unwrap(), "{}");
+        }
+
+        #[rstest]
+        #[case::not_retryable(404, 1)]
+        #[case::exhausted(503, 3)]
+        #[tokio::test]
+        async fn test_retry_failure(#[case] status: u16, #[case] expected_requests: u64) {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(path("/variables.json"))
+                .respond_with(ResponseTemplate::new(status))
+                .expect(expected_requests)
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let (client, url) = retry_client(&server, cache_dir.path(), &web_client);
+
+            // Act
+            let result = client
+                .fetch_with_retry(&url, 2, Duration::from_millis(1))
+                .await;
+
+            // Assert
+            match result.unwrap_err() {
+                FetchError::RetriesExhausted {
+                    url: error_url,
+                    retries,
+                    source,
+                } => {
+                    assert_eq!(error_url, url.to_string());
+                    assert_eq!(retries, 2);
+                    assert!(matches!(*source, FetchError::HttpStatus { .. }));
+                }
+                FetchError::HttpStatus { status, .. } => {
+                    assert_eq!(expected_requests, 1);
+                    assert_eq!(status.as_u16(), 404);
+                }
+                e => panic!("Unexpected error: {}", e),
+            }
+        }
+
+        /// A response slower than the timeout is `FetchError::Timeout` and isn't cached.
+        #[tokio::test]
+        async fn test_timeout() {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(path("/data.json"))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_raw("{}", "application/json")
+                        .set_delay(Duration::from_secs(5)),
+                )
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let timeout = Duration::from_millis(50);
+            let client =
+                CachedClient::with_timeout(cache_dir.path().to_path_buf(), &web_client, timeout);
+            let url = Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+
+            // Act
+            let result = client.fetch(&url).await;
+
+            // Assert
+            match result {
+                Err(FetchError::Timeout {
+                    url: error_url,
+                    timeout: error_timeout,
+                }) => {
+                    assert_eq!(error_url, url.to_string());
+                    assert_eq!(error_timeout, timeout);
+                }
+                other => panic!("Expected a timeout but got {:?}", other),
+            }
+            assert!(!cache_dir.path().join("data.json").exists());
+        }
+
+        /// A cached response older than the TTL is fetched again; a newer one isn't.
+        #[rstest]
+        #[case::expired(Duration::from_secs(7200), 2, "new")]
+        #[case::fresh(Duration::from_secs(60), 1, "old")]
+        #[tokio::test]
+        async fn test_ttl(
+            #[case] age: Duration,
+            #[case] expected_requests: u64,
+            #[case] expected_body: &str,
+        ) {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(path("/data.json"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("old", "application/json"))
+                .up_to_n_times(1)
+                .mount(&server)
+                .await;
+            Mock::given(path("/data.json"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("new", "application/json"))
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client)
+                .with_ttl(Duration::from_secs(3600));
+            let url = Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+            client.fetch(&url).await.unwrap();
+            fs::File::options()
+                .write(true)
+                .open(cache_dir.path().join("data.json"))
+                .unwrap()
+                .set_modified(std::time::SystemTime::now() - age)
+                .unwrap();
+
+            // Act
+            let body = client.fetch(&url).await.unwrap();
+
+            // Assert
+            assert_eq!(body, expected_body);
+            assert_eq!(
+                server.received_requests().await.unwrap().len() as u64,
+                expected_requests
+            );
+            assert_eq!(
+                fs::read_to_string(cache_dir.path().join("data.json")).unwrap(),
+                expected_body
+            );
+        }
+
+        #[tokio::test]
+        async fn test_invalidate() {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(path("/data/2020/acs/acs5/variables.json"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("{}", "application/json"))
+                .expect(2)
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+            let url = Url::parse(&format!(
+                "{}/data/2020/acs/acs5/variables.json",
+                server.uri()
+            ))
+            .unwrap();
+            let other_url = Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+            client.fetch(&url).await.unwrap();
+
+            // Act
+            let removed = client.invalidate(&url).unwrap();
+            let removed_again = client.invalidate(&url).unwrap();
+            let removed_other = client.invalidate(&other_url).unwrap();
+
+            // Assert
+            assert_eq!(
+                (removed, removed_again, removed_other),
+                (true, false, false)
+            );
+            client.fetch(&url).await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_clear() {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(method("GET"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("{}", "application/json"))
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().join("cache"), &web_client);
+            let empty_result = client.clear().unwrap();
+            for url_path in [
+                "data.json",
+                "data/2020/acs/acs5/variables.json",
+                "data/2020/acs/acs5/geography.json",
+            ] {
+                let url = Url::parse(&format!("{}/{}", server.uri(), url_path)).unwrap();
+                client.fetch(&url).await.unwrap();
+            }
+
+            // Act
+            let removed = client.clear().unwrap();
+
+            // Assert
+            assert_eq!((empty_result, removed), (0, 3));
+            assert_eq!(fs::read_dir(client.base_cache_dir()).unwrap().count(), 0);
+            assert_eq!(client.clear().unwrap(), 0);
+        }
+
+        /// Return `body` compressed with `content_encoding`.
+        fn compress(body: &str, content_encoding: &str) -> Vec<u8> {
+            use flate2::write::{GzEncoder, ZlibEncoder};
+            use flate2::Compression;
+            use std::io::Write;
+
+            match content_encoding {
+                "gzip" => {
+                    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(body.as_bytes()).unwrap();
+                    encoder.finish().unwrap()
+                }
+                _ => {
+                    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(body.as_bytes()).unwrap();
+                    encoder.finish().unwrap()
+                }
+            }
+        }
+
+        /// Compressed responses are requested and cached decompressed, whether or not the
+        /// `reqwest::Client` decompresses them.
+        #[rstest]
+        #[case::gzip("gzip", Client::new())]
+        #[case::deflate("deflate", Client::new())]
+        #[case::gzip_without_client_support(
+            "gzip",
+            Client::builder().no_gzip().no_deflate().build().unwrap()
+        )]
+        #[case::deflate_without_client_support(
+            "deflate",
+            Client::builder().no_gzip().no_deflate().build().unwrap()
+        )]
+        #[tokio::test]
+        async fn test_compressed_response(
+            #[case] content_encoding: &str,
+            #[case] web_client: Client,
+        ) {
+            // Arrange
+            let body = r#"{"variables": {}}"#;
+            let server = MockServer::start().await;
+            Mock::given(path("/variables.json"))
+                .and(wiremock::matchers::header_regex(
+                    "accept-encoding",
+                    content_encoding,
+                ))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("content-encoding", content_encoding)
+                        .set_body_raw(compress(body, content_encoding), "application/json"),
+                )
+                .expect(1)
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+            let url = Url::parse(&format!("{}/variables.json", server.uri())).unwrap();
+
+            // Act
+            let fetched = client.fetch(&url).await.unwrap();
+
+            // Assert
+            assert_eq!(fetched, body);
+            assert_eq!(
+                fs::read_to_string(cache_dir.path().join("variables.json")).unwrap(),
+                body
+            );
+            assert_eq!(client.fetch(&url).await.unwrap(), body);
+        }
+
+        /// The API key and headers are sent, but the API key isn't part of the cache path.
+        #[tokio::test]
+        async fn test_api_key_and_headers() {
+            use wiremock::matchers::{header, query_param};
+
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(path("/data/2020/acs/acs5/variables.json"))
+                .and(query_param("key", "secret"))
+                .and(header("x-gateway-token", "token"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("{}", "application/json"))
+                .expect(1)
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let mut headers = HeaderMap::new();
+            headers.insert("x-gateway-token", "token".parse().unwrap());
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client)
+                .with_headers(headers)
+                .with_api_key("secret");
+            let url = Url::parse(&format!(
+                "{}/data/2020/acs/acs5/variables.json",
+                server.uri()
+            ))
+            .unwrap();
+
+            // Act
+            let body = client.fetch(&url).await.unwrap();
+
+            // Assert
+            assert_eq!(body, "{}");
+            let expected_path = CachePath::from_url(&url, cache_dir.path()).unwrap().path();
+            assert_eq!(fs::read_to_string(expected_path).unwrap(), "{}");
+            let without_key = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+            assert_eq!(without_key.fetch(&url).await.unwrap(), "{}");
+        }
+
+        /// Results are in the order of the URLs, not the order in which the responses arrived.
+        #[tokio::test]
+        async fn test_fetch_all_order() {
+            // Arrange
+            let server = MockServer::start().await;
+            let names = ["slow", "fast", "missing", "medium"];
+            for (name, delay) in [("slow", 300), ("fast", 0), ("medium", 100)] {
+                Mock::given(path(format!("/{}.json", name)))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .set_body_raw(name, "application/json")
+                            .set_delay(Duration::from_millis(delay)),
+                    )
+                    .expect(1)
+                    .mount(&server)
+                    .await;
+            }
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+            let urls: Vec<Url> = names
+                .iter()
+                .map(|name| Url::parse(&format!("{}/{}.json", server.uri(), name)).unwrap())
+                .collect();
+
+            // Act
+            let results = client.fetch_all(&urls, 4).await;
+
+            // Assert
+            let bodies: Vec<Option<&str>> = results
+                .iter()
+                .map(|result| result.as_ref().ok().map(String::as_str))
+                .collect();
+            assert_eq!(
+                bodies,
+                vec![Some("slow"), Some("fast"), None, Some("medium")]
+            );
+            assert!(matches!(
+                results[2],
+                Err(FetchError::HttpStatus { status, .. }) if status.as_u16() == 404
+            ));
+        }
+
+        /// An offline client returns cached responses and `CacheMiss` for the others.
+        #[tokio::test]
+        async fn test_offline() {
+            // Arrange
+            let cache_dir = tempfile::tempdir().unwrap();
+            let cached_dir = cache_dir.path().join("data/2020/acs/acs5");
+            fs::create_dir_all(&cached_dir).unwrap();
+            fs::write(cached_dir.join("variables.json"), "{}").unwrap();
+            let client = CachedClient::offline(cache_dir.path().to_path_buf());
+            let cached =
+                Url::parse("https://api.census.gov/data/2020/acs/acs5/variables.json").unwrap();
+            let missing =
+                Url::parse("https://api.census.gov/data/2020/acs/acs5/geography.json").unwrap();
+
+            // Act
+            let hit = client.fetch(&cached).await;
+            let miss = client.fetch(&missing).await;
+
+            // Assert
+            assert_eq!(hit.unwrap(), "{}");
+            assert!(matches!(miss, Err(FetchError::CacheMiss(url)) if url == missing));
+            assert!(!cache_dir
+                .path()
+                .join("data/2020/acs/acs5/geography.json")
+                .exists());
+        }
+
+        /// An online client made offline doesn't send requests.
+        #[tokio::test]
+        async fn test_with_offline() {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(method("GET"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("{}", "application/json"))
+                .expect(0)
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client =
+                CachedClient::new(cache_dir.path().to_path_buf(), &web_client).with_offline(true);
+            let url = Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+
+            // Act
+            let result = client.fetch(&url).await;
+
+            // Assert
+            assert!(matches!(result, Err(FetchError::CacheMiss(_))));
+        }
+
+        /// A `FileCache` that counts its reads.
+        #[derive(Debug, Default)]
+        struct CountingCache {
+            reads: std::sync::atomic::AtomicUsize,
+        }
+
+        impl CacheBackend for CountingCache {
+            fn read(&self, path: &Path) -> std::io::Result<Option<String>> {
+                self.reads
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                FileCache.read(path)
+            }
+
+            fn write(&self, path: &Path, body: &str) -> std::io::Result<()> {
+                FileCache.write(path, body)
+            }
+        }
+
+        /// With the memory cache, fetching a URL again doesn't read the cache backend.
+        #[rstest]
+        #[case::enabled(true, 1)]
+        #[case::disabled(false, 2)]
+        #[tokio::test]
+        async fn test_memory_cache(#[case] enabled: bool, #[case] expected_reads: usize) {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(path("/data.json"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("{}", "application/json"))
+                .expect(1)
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let backend = Arc::new(CountingCache::default());
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client)
+                .with_backend(backend.clone())
+                .with_memory_cache(enabled);
+            let url = Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+            client.fetch(&url).await.unwrap();
+
+            // Act
+            let body = client.fetch(&url).await.unwrap();
+
+            // Assert
+            assert_eq!(body, "{}");
+            assert_eq!(
+                backend.reads.load(std::sync::atomic::Ordering::Relaxed),
+                expected_reads
+            );
+        }
+
+        #[rstest]
+        #[case::census(
+            "https://api.census.gov/data/2020/acs/acs5/variables.json",
+            "http://localhost:8080/data/2020/acs/acs5/variables.json"
+        )]
+        #[case::query(
+            "http://api.census.gov/data/2020/acs/acs5?get=NAME&for=state:*",
+            "http://localhost:8080/data/2020/acs/acs5?get=NAME&for=state:*"
+        )]
+        #[case::other_host(
+            "https://www2.census.gov/data/api-documentation.json",
+            "https://www2.census.gov/data/api-documentation.json"
+        )]
+        fn test_rewrite_url(#[case] url: &str, #[case] expected: &str) {
+            let base_url = Url::parse("http://localhost:8080").unwrap();
+            let rewritten = rewrite_url(&Url::parse(url).unwrap(), &base_url);
+            assert_eq!(rewritten.as_str(), expected);
+        }
+
+        /// Census API URLs are fetched from the base URL but cached under their own path.
+        #[tokio::test]
+        async fn test_base_url() {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(path("/data/2020/acs/acs5/variables.json"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("{}", "application/json"))
+                .expect(1)
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client)
+                .with_base_url(Url::parse(&server.uri()).unwrap());
+            let url =
+                Url::parse("https://api.census.gov/data/2020/acs/acs5/variables.json").unwrap();
+
+            // Act
+            let body = client.fetch(&url).await.unwrap();
+
+            // Assert
+            assert_eq!(body, "{}");
+            let expected_path = cache_dir.path().join("data/2020/acs/acs5/variables.json");
+            assert_eq!(fs::read_to_string(expected_path).unwrap(), "{}");
+        }
+
+        /// Fetching a URL again logs a cache hit instead of fetching it from the network.
+        #[tokio::test]
+        #[tracing_test::traced_test]
+        async fn test_logs_cache_hit() {
+            // Arrange
+            let server = MockServer::start().await;
+            Mock::given(path("/data.json"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("{}", "application/json"))
+                .expect(1)
+                .mount(&server)
+                .await;
+            let cache_dir = tempfile::tempdir().unwrap();
+            let web_client = Client::new();
+            let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+            let url = Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+            client.fetch(&url).await.unwrap();
+            assert!(logs_contain("fetching from the network"));
+            assert!(!logs_contain("cache hit"));
+
+            // Act
+            client.fetch(&url).await.unwrap();
+
+            // Assert
+            assert!(logs_contain("cache hit"));
+        }
+
+        /// Clones of a shared client fetch concurrently from spawned tasks.
+        #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+        async fn test_shared_across_tasks() {
+            // Arrange
+            let server = MockServer::start().await;
+            for name in ["a", "b"] {
+                Mock::given(path(format!("/{}.json", name)))
+                    .respond_with(ResponseTemplate::new(200).set_body_string(name))
+                    .expect(1)
+                    .mount(&server)
+                    .await;
+            }
+            let cache_dir = tempfile::tempdir().unwrap();
+            let client =
+                CachedClient::shared(cache_dir.path().to_path_buf(), Arc::new(Client::new()));
+
+            // Act
+            let handles = ["a", "b"].map(|name| {
+                let client = client.clone();
+                let url = Url::parse(&format!("{}/{}.json", server.uri(), name)).unwrap();
+                tokio::spawn(async move { client.fetch(&url).await.unwrap() })
+            });
+            let [a, b] = handles;
+            let (a, b) = (a.await.unwrap(), b.await.unwrap());
+
+            // Assert
+            assert_eq!((a.as_str(), b.as_str()), ("a", "b"));
+            assert!(cache_dir.path().join("a.json").exists());
+            assert!(cache_dir.path().join("b.json").exists());
+        }
     }
 }
@@ -0,0 +1,3023 @@
+use crate::constraints::get_unique_constraints;
+use crate::fetch_api_metadata::CachedClient;
+use crate::fetch_api_metadata::FetchError;
+use crate::models::ApiPathsGeographyAssociation;
+use crate::models::{
+    ApiPaths, ApiPathsVariablesAssociation, CatalogError, UsCensusApisResponse, VariableValue,
+};
+use crate::parse_geography::{GeographyCollection, GeographyItem};
+use crate::parse_variables::{VariablesItem, VariablesStream};
+use diesel::dsl::sql;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_builder::{Query, QueryFragment, QueryId};
+use diesel::result::Error as DieselError;
+use diesel::sql_types::{Date, Integer, Nullable, Text};
+use diesel::upsert::{excluded, on_constraint};
+use futures::StreamExt;
+use std::borrow::Borrow;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::time::Instant;
+use thiserror::Error;
+use url::Url;
+
+#[cfg(feature = "async-db")]
+mod async_db;
+#[cfg(feature = "async-db")]
+pub use async_db::insert_variables_and_geography_for_api_path_async;
+
+#[derive(Debug, Error)]
+pub enum InsertError {
+    #[error("URL parsing error: {0}")]
+    UrlParse(#[from] url::ParseError),
+
+    #[error("Database error: {0}")]
+    Database(#[from] DieselError),
+
+    #[error("JSON deserialization error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Error fetching API spec from web: {0}")]
+    Http(#[from] FetchError), // Adjust based on your CachedClient's error type
+
+    #[error("Error reading the catalog: {0}")]
+    Catalog(#[from] CatalogError),
+
+    #[error("Expected exactly one unique key constraint for the `variables` table, found {0}")]
+    VariablesConstraint(usize),
+
+    #[error("API path '{title}' uses the same URL for its variables and geography: {link}")]
+    IdenticalLinks { title: String, link: String },
+
+    #[error("API path '{title}' has an invalid {field} '{link}': {reason}")]
+    InvalidLink {
+        title: String,
+        field: &'static str,
+        link: String,
+        reason: String,
+    },
+
+    #[error("Inserted the variables of API path '{title}' but not its geography: {source}")]
+    GeographyAfterCheckpoint {
+        title: String,
+        source: Box<InsertError>,
+    },
+
+    #[error(
+        "Batch size {batch_size} exceeds the maximum of {max}, above which a query would have \
+         more than 65535 parameters"
+    )]
+    BatchTooLarge { batch_size: usize, max: usize },
+
+    #[error("Deadline exceeded before {phase}")]
+    TimedOut { phase: &'static str },
+
+    #[error("Aborted ingestion after {failures} API paths failed. Last error: {last}")]
+    ErrorBudgetExhausted {
+        failures: usize,
+        last: Box<InsertError>,
+    },
+}
+
+/// What to do with an API path whose `c_geography_link` and `c_variables_link` are the same.
+///
+/// A few malformed entries in https://api.census.gov/data.json point both links at one URL.
+/// Ingesting such an endpoint would parse the same body as both variables and geography.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdenticalLinks {
+    /// Skip the endpoint and log a warning with `tracing`, which is only shown if a tracing
+    /// subscriber is installed.
+    Skip,
+    /// Return `InsertError::IdenticalLinks`.
+    #[default]
+    Fail,
+}
+
+/// Options for inserting API paths' variables and geography.
+#[derive(Debug, Clone, Default)]
+pub struct IngestOptions {
+    /// See `IdenticalLinks`.
+    pub identical_links: IdenticalLinks,
+    /// `ingest_api_paths` gives up once more than this many API paths failed, so that a
+    /// systemic outage fails fast. `None` tries every API path regardless of failures.
+    pub max_failures: Option<usize>,
+    /// Commit an API path's variables before fetching and inserting its geography, so that a
+    /// failure to insert the geography doesn't roll back the variables.
+    pub checkpoint_variables: bool,
+    /// See `FetchConcurrency`.
+    pub fetch_concurrency: FetchConcurrency,
+    /// `insert_variables_and_geography_for_api_path` returns `InsertError::TimedOut` if this
+    /// passes before it fetches, parses or inserts, or while it fetches.
+    pub deadline: Option<Instant>,
+    /// Leave an API path's geography untouched when the incoming geographies are exactly
+    /// the ones already associated with it, instead of rewriting them.
+    pub skip_unchanged_geography: bool,
+    /// Number of rows sent per INSERT statement. `None` uses `DEFAULT_BATCH_SIZE`. Larger
+    /// batches than `MAX_BATCH_SIZE` are rejected with `InsertError::BatchTooLarge`, and a
+    /// batch size of 0 is treated as 1.
+    pub batch_size: Option<usize>,
+    /// Fetch, parse and insert everything as usual, but roll back every transaction, so that
+    /// parse errors and the `InsertSummary` counts surface without changing the database.
+    pub dry_run: bool,
+    /// Skip the API paths that already have both variables and geography, e.g. from a
+    /// previous run, instead of fetching and upserting them again. Skipped API paths aren't
+    /// in the `IngestReport`.
+    pub only_missing: bool,
+}
+
+impl IngestOptions {
+    /// Return the batch size to insert with, or `InsertError::BatchTooLarge`.
+    fn checked_batch_size(&self) -> Result<usize, InsertError> {
+        match self.batch_size.unwrap_or(DEFAULT_BATCH_SIZE) {
+            batch_size if batch_size > MAX_BATCH_SIZE => Err(InsertError::BatchTooLarge {
+                batch_size,
+                max: MAX_BATCH_SIZE,
+            }),
+            batch_size => Ok(batch_size.max(1)),
+        }
+    }
+}
+
+/// How many variables.json and geography.json files `ingest_api_paths` fetches at once.
+///
+/// variables.json files are large and few, while geography.json files are small and
+/// numerous, so they're fetched in separate phases with separate limits. The responses are
+/// cached and then inserted one API path at a time. The default fetches each file right
+/// before inserting it, without a separate fetch phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FetchConcurrency {
+    pub variables: usize,
+    pub geography: usize,
+}
+
+impl Default for FetchConcurrency {
+    fn default() -> Self {
+        FetchConcurrency {
+            variables: 1,
+            geography: 1,
+        }
+    }
+}
+
+/// The file of an API path that's fetched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum FetchPhase {
+    Variables,
+    Geography,
+}
+
+/// Counts of what `insert_variables_and_geography_for_api_path` wrote to the database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InsertSummary {
+    /// Number of variables that weren't associated with the API path yet.
+    pub variables_inserted: usize,
+    /// Number of variables whose name was associated with the API path, but with different
+    /// content.
+    pub variables_updated: usize,
+    /// Number of variables that were already associated with the API path and weren't
+    /// written again.
+    pub variables_skipped: usize,
+    /// Number of variables that shared a unique key with another variable of the same endpoint
+    /// and were therefore merged into a single row.
+    pub variables_collapsed: usize,
+    /// Number of geographies newly associated with the API path.
+    pub geographies_inserted: usize,
+    /// Number of geographies no longer associated with the API path.
+    pub geographies_deleted: usize,
+    /// Number of geographies dropped because they repeated an earlier geography of the same
+    /// geography.json.
+    pub geographies_duplicated: usize,
+    /// Whether the geography was skipped because it matched the API path's existing
+    /// geography. See `IngestOptions::skip_unchanged_geography`.
+    pub geography_unchanged: bool,
+}
+
+impl fmt::Display for InsertSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} variables inserted, {} updated, {} skipped; ",
+            self.variables_inserted, self.variables_updated, self.variables_skipped
+        )?;
+        if self.geography_unchanged {
+            write!(f, "geography unchanged")
+        } else {
+            write!(
+                f,
+                "{} geographies inserted, {} deleted",
+                self.geographies_inserted, self.geographies_deleted
+            )
+        }
+    }
+}
+
+impl InsertSummary {
+    /// Return this summary of the variables, with the geography counts of `geography`.
+    fn with_geography(self, geography: InsertSummary) -> Self {
+        InsertSummary {
+            geographies_inserted: geography.geographies_inserted,
+            geographies_deleted: geography.geographies_deleted,
+            geographies_duplicated: geography.geographies_duplicated,
+            geography_unchanged: geography.geography_unchanged,
+            ..self
+        }
+    }
+
+    /// Return human-readable warnings about data that may have been lost during insertion.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.variables_collapsed > 0 {
+            warnings.push(format!("collapsed {} variables", self.variables_collapsed));
+        }
+        if self.geographies_duplicated > 0 {
+            warnings.push(format!(
+                "dropped {} duplicate geographies",
+                self.geographies_duplicated
+            ));
+        }
+        warnings
+    }
+}
+
+/// What `ingest_api_paths` did with each API path, keyed by `c_variables_link`.
+#[derive(Debug, Default)]
+pub struct IngestReport {
+    pub ingested: Vec<(String, InsertSummary)>,
+    pub failed: Vec<(String, InsertError)>,
+}
+
+/// Return whether the API path's variables and geography should be ingested, given how
+/// `behavior` says to treat identical links.
+fn check_links(api_path: &ApiPaths<'_>, behavior: IdenticalLinks) -> Result<bool, InsertError> {
+    if api_path.c_variables_link != api_path.c_geography_link {
+        return Ok(true);
+    }
+    match behavior {
+        IdenticalLinks::Skip => {
+            tracing::warn!(
+                title = %api_path.title,
+                link = %api_path.c_variables_link,
+                "skipping API path because its variables and geography links are identical"
+            );
+            Ok(false)
+        }
+        IdenticalLinks::Fail => Err(InsertError::IdenticalLinks {
+            title: api_path.title.to_string(),
+            link: api_path.c_variables_link.to_string(),
+        }),
+    }
+}
+
+/// Check that the API path's `c_variables_link` and `c_geography_link` are URLs of JSON files,
+/// so that a malformed catalog entry is rejected before it's inserted rather than failing when
+/// its variables are fetched.
+///
+/// # Returns
+///
+/// * `Ok(())` - if both links are valid
+/// * `Err(InsertError::InvalidLink)` - naming the API path and the first invalid link
+pub fn validate_api_path(api_path: &ApiPaths<'_>) -> Result<(), InsertError> {
+    for (field, link) in [
+        ("c_variables_link", &api_path.c_variables_link),
+        ("c_geography_link", &api_path.c_geography_link),
+    ] {
+        let reason = match Url::parse(link) {
+            Ok(url) if url.path().ends_with(".json") => continue,
+            Ok(_) => "the link isn't to a .json file".to_string(),
+            Err(e) => e.to_string(),
+        };
+        return Err(InsertError::InvalidLink {
+            title: api_path.title.to_string(),
+            field,
+            link: link.to_string(),
+            reason,
+        });
+    }
+    Ok(())
+}
+
+/// The batch size used unless `IngestOptions::batch_size` is set.
+pub const DEFAULT_BATCH_SIZE: usize = 5000;
+
+/// The largest batch size that doesn't exceed the Postgres maximum number of parameters in a
+/// single query (65535). A `variables` row, the widest inserted, binds 9 parameters.
+pub const MAX_BATCH_SIZE: usize = 65535 / 9;
+
+/// Insert variables and geography for a given API path into the database.
+///
+/// By default the variables and geography are inserted in a single transaction. With
+/// `options.checkpoint_variables`, the variables are committed before the geography is
+/// fetched, and a failure to insert the geography returns
+/// `InsertError::GeographyAfterCheckpoint`. Retry with `insert_geography_for_api_path`.
+///
+/// # Arguments
+///
+/// * `conn` - connection to the datbase
+/// * `client` - the client to use for fetching the API metadata (JSON)
+/// * `api_path_metadata` - the API paths whose variables and geography to insert
+/// * `variables_unique_key_constraint` - the unique key constraint for the variables table
+/// * `options` - see `IngestOptions`
+pub async fn insert_variables_and_geography_for_api_path(
+    conn: &mut PgConnection,
+    client: &CachedClient<'_>,
+    api_path_metadata: &ApiPaths<'_>,
+    variables_unique_key_constraint: &str,
+    options: &IngestOptions,
+) -> Result<InsertSummary, InsertError> {
+    ingest_api_path(
+        conn,
+        client,
+        api_path_metadata,
+        variables_unique_key_constraint,
+        options,
+        &mut |_| {},
+    )
+    .await
+}
+
+/// A callback given the size in bytes of each response fetched, or read from the cache, while
+/// ingesting an API path.
+type OnFetched<'f> = &'f mut (dyn FnMut(usize) + Send);
+
+/// `insert_variables_and_geography_for_api_path`, calling `on_fetched` after each fetch.
+#[tracing::instrument(
+    name = "insert_variables_and_geography_for_api_path",
+    skip_all,
+    fields(id = api_path_metadata.id, title = %api_path_metadata.title)
+)]
+async fn ingest_api_path(
+    conn: &mut PgConnection,
+    client: &CachedClient<'_>,
+    api_path_metadata: &ApiPaths<'_>,
+    variables_unique_key_constraint: &str,
+    options: &IngestOptions,
+    on_fetched: OnFetched<'_>,
+) -> Result<InsertSummary, InsertError> {
+    tracing::info!(link = %api_path_metadata.c_variables_link, "ingesting API path");
+    let result = insert_api_path(
+        conn,
+        client,
+        api_path_metadata,
+        variables_unique_key_constraint,
+        options,
+        on_fetched,
+    )
+    .await;
+    match &result {
+        Ok(summary) => tracing::info!(
+            variables_inserted = summary.variables_inserted,
+            variables_updated = summary.variables_updated,
+            variables_skipped = summary.variables_skipped,
+            geographies_inserted = summary.geographies_inserted,
+            geographies_deleted = summary.geographies_deleted,
+            "ingested API path"
+        ),
+        Err(e) => tracing::warn!(error = %e, "failed to ingest API path"),
+    }
+    result
+}
+
+/// `ingest_api_path`, without logging.
+async fn insert_api_path(
+    conn: &mut PgConnection,
+    client: &CachedClient<'_>,
+    api_path_metadata: &ApiPaths<'_>,
+    variables_unique_key_constraint: &str,
+    options: &IngestOptions,
+    on_fetched: OnFetched<'_>,
+) -> Result<InsertSummary, InsertError> {
+    let batch_size = options.checked_batch_size()?;
+    if !check_links(api_path_metadata, options.identical_links)? {
+        return Ok(InsertSummary::default());
+    }
+
+    let variables_url = Url::parse(api_path_metadata.c_variables_link.as_ref())?;
+    let variables_response = fetch_before(
+        client.fetch_reader(&variables_url),
+        options.deadline,
+        "fetching variables",
+    )
+    .await?;
+    on_fetched(variables_response.len() as usize);
+    // The variables are parsed from the cache file while they're inserted, so only one batch
+    // of them is in memory at a time.
+    let variables = VariablesStream::new(variables_response);
+
+    if options.checkpoint_variables {
+        check_deadline(options.deadline, "inserting variables")?;
+        let summary = transaction(conn, options.dry_run, |conn| {
+            insert_variables_in_batches(
+                conn,
+                variables,
+                api_path_metadata.id,
+                variables_unique_key_constraint,
+                batch_size,
+            )
+        })?;
+        let geography_summary =
+            insert_geography(conn, client, api_path_metadata, options, on_fetched)
+                .await
+                .map_err(|e| InsertError::GeographyAfterCheckpoint {
+                    title: api_path_metadata.title.to_string(),
+                    source: Box::new(e),
+                })?;
+        return Ok(summary.with_geography(geography_summary));
+    }
+
+    let geography_url = Url::parse(api_path_metadata.c_geography_link.as_ref())?;
+    let geography_response = fetch_before(
+        client.fetch(&geography_url),
+        options.deadline,
+        "fetching geography",
+    )
+    .await?;
+    on_fetched(geography_response.len());
+    check_deadline(options.deadline, "parsing geography")?;
+    let parsed_geography_response: GeographyCollection = serde_json::from_str(&geography_response)?;
+
+    // Use a single transaction per endpoint such that all variable and geography parameters
+    // are rolled back.
+    check_deadline(options.deadline, "inserting")?;
+    let summary = transaction::<_, InsertError>(conn, options.dry_run, |conn| {
+        let summary = insert_variables_in_batches(
+            conn,
+            variables,
+            api_path_metadata.id,
+            variables_unique_key_constraint,
+            batch_size,
+        )?;
+        let geography_summary = insert_geographies_in_batches(
+            conn,
+            &parsed_geography_response.fips,
+            api_path_metadata.id,
+            options.skip_unchanged_geography,
+            batch_size,
+        )?;
+        Ok(summary.with_geography(geography_summary))
+    })?;
+    Ok(summary)
+}
+
+/// Return `InsertError::TimedOut` if `deadline` passed before `phase`.
+fn check_deadline(deadline: Option<Instant>, phase: &'static str) -> Result<(), InsertError> {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => Err(InsertError::TimedOut { phase }),
+        _ => Ok(()),
+    }
+}
+
+/// Run `fetch`, returning `InsertError::TimedOut` if `deadline` passes before or during
+/// `phase`.
+async fn fetch_before<T>(
+    fetch: impl Future<Output = Result<T, FetchError>>,
+    deadline: Option<Instant>,
+    phase: &'static str,
+) -> Result<T, InsertError> {
+    check_deadline(deadline, phase)?;
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), fetch)
+            .await
+            .map_err(|_| InsertError::TimedOut { phase })?
+            .map_err(InsertError::from),
+        None => Ok(fetch.await?),
+    }
+}
+
+/// Insert only the geography of a given API path into the database, in its own transaction.
+///
+/// This retries an API path whose variables were committed by
+/// `insert_variables_and_geography_for_api_path` with `options.checkpoint_variables` but
+/// whose geography failed.
+///
+/// # Arguments
+///
+/// * `conn` - connection to the database
+/// * `client` - the client to use for fetching the API metadata (JSON)
+/// * `api_path_metadata` - the API path whose geography to insert
+/// * `options` - see `IngestOptions`; only `skip_unchanged_geography`, `batch_size`,
+///   `deadline` and `dry_run` apply
+///
+/// # Returns
+///
+/// The geography counts of an `InsertSummary`.
+pub async fn insert_geography_for_api_path(
+    conn: &mut PgConnection,
+    client: &CachedClient<'_>,
+    api_path_metadata: &ApiPaths<'_>,
+    options: &IngestOptions,
+) -> Result<InsertSummary, InsertError> {
+    insert_geography(conn, client, api_path_metadata, options, &mut |_| {}).await
+}
+
+/// `insert_geography_for_api_path`, calling `on_fetched` after fetching the geography.
+async fn insert_geography(
+    conn: &mut PgConnection,
+    client: &CachedClient<'_>,
+    api_path_metadata: &ApiPaths<'_>,
+    options: &IngestOptions,
+    on_fetched: OnFetched<'_>,
+) -> Result<InsertSummary, InsertError> {
+    let batch_size = options.checked_batch_size()?;
+    let geography_url = Url::parse(api_path_metadata.c_geography_link.as_ref())?;
+    let geography_response = fetch_before(
+        client.fetch(&geography_url),
+        options.deadline,
+        "fetching geography",
+    )
+    .await?;
+    on_fetched(geography_response.len());
+    check_deadline(options.deadline, "parsing geography")?;
+    let parsed_geography_response: GeographyCollection = serde_json::from_str(&geography_response)?;
+
+    check_deadline(options.deadline, "inserting geography")?;
+    transaction::<_, InsertError>(conn, options.dry_run, |conn| {
+        insert_geographies_in_batches(
+            conn,
+            &parsed_geography_response.fips,
+            api_path_metadata.id,
+            options.skip_unchanged_geography,
+            batch_size,
+        )
+    })
+}
+
+/// Run `f` in a transaction. With `dry_run`, roll the transaction back even if `f` succeeds,
+/// and return what `f` returned.
+fn transaction<T, E: From<DieselError>>(
+    conn: &mut PgConnection,
+    dry_run: bool,
+    f: impl FnOnce(&mut PgConnection) -> Result<T, E>,
+) -> Result<T, E> {
+    if !dry_run {
+        return conn.transaction(f);
+    }
+    let mut output = None;
+    let result = conn.transaction::<(), E, _>(|conn| {
+        output = Some(f(conn)?);
+        Err(DieselError::RollbackTransaction.into())
+    });
+    match (output, result) {
+        (Some(output), _) => Ok(output),
+        (None, Err(e)) => Err(e),
+        (None, Ok(())) => unreachable!("the dry run transaction always fails"),
+    }
+}
+
+/// Insert variables in batches of `batch_size` as they're parsed, e.g. from a
+/// `VariablesStream`. Meant to run inside a transaction.
+///
+/// Variables whose name and hashed content match a variable already associated with the API
+/// path are skipped. A variable whose name is associated but whose content changed replaces
+/// the associations of the outdated variables of that name.
+fn insert_variables_in_batches<'v, B: Borrow<VariablesItem<'v>>>(
+    conn: &mut PgConnection,
+    items: impl IntoIterator<Item = Result<B, serde_json::Error>>,
+    api_path_id: i32,
+    unique_key_constraint: &str,
+    batch_size: usize,
+) -> Result<InsertSummary, InsertError> {
+    let mut diff = VariablesChanges::new(associated_variables(conn, api_path_id)?);
+    let mut changed = Vec::new();
+    for item in items {
+        let item = item?;
+        if diff.is_changed(item.borrow()) {
+            changed.push(item);
+            if changed.len() == batch_size {
+                insert_variables(&changed, conn, api_path_id, unique_key_constraint)?;
+                changed.clear();
+            }
+        }
+    }
+    if !changed.is_empty() {
+        insert_variables(&changed, conn, api_path_id, unique_key_constraint)?;
+    }
+
+    let outdated_ids = diff.outdated_ids();
+    if !outdated_ids.is_empty() {
+        delete_variable_associations_statement(api_path_id, outdated_ids).execute(conn)?;
+    }
+    Ok(diff.summary)
+}
+
+/// Return the statement that deletes the API path's associations with the variables
+/// `variable_ids`, run by both `insert_variables_in_batches` and its async counterpart.
+fn delete_variable_associations_statement(
+    api_path_id: i32,
+    variable_ids: Vec<i32>,
+) -> impl QueryFragment<Pg> + QueryId + RunQueryDsl<PgConnection> + Send {
+    use crate::schema::api_paths_variables_association::dsl::*;
+
+    diesel::delete(api_paths_variables_association)
+        .filter(api_paths_id.eq(api_path_id))
+        .filter(variables_id.eq_any(variable_ids))
+}
+
+/// Tells the variables of an API path that must be written from those that are already
+/// associated with it, as `insert_variables_in_batches` parses them, and counts them.
+struct VariablesChanges {
+    /// The id and content of each variable associated with the API path.
+    existing: Vec<(i32, VariableContent)>,
+    existing_contents: HashSet<VariableContent>,
+    existing_names: HashSet<String>,
+    /// The content of each variable seen so far.
+    contents: HashSet<VariableContent>,
+    /// The unique key of each variable seen so far, to count the variables that collapse
+    /// into an earlier one, wherever their batches are.
+    keys: HashSet<VariableKey>,
+    /// The names of the variables whose content changed.
+    updated_names: HashSet<String>,
+    summary: InsertSummary,
+}
+
+impl VariablesChanges {
+    fn new(existing: Vec<(i32, VariableContent)>) -> Self {
+        VariablesChanges {
+            existing_contents: existing
+                .iter()
+                .map(|(_, content)| content.clone())
+                .collect(),
+            existing_names: existing
+                .iter()
+                .map(|(_, (name, ..))| name.clone())
+                .collect(),
+            existing,
+            contents: HashSet::new(),
+            keys: HashSet::new(),
+            updated_names: HashSet::new(),
+            summary: InsertSummary::default(),
+        }
+    }
+
+    /// Count the variable as collapsed, skipped, updated or inserted, and return whether it
+    /// must be written, i.e. it's neither collapsed nor skipped.
+    fn is_changed(&mut self, item: &VariablesItem) -> bool {
+        let (key_name, concept, attributes_hash, first_group) = variables_unique_key(item);
+        let key = (
+            key_name.to_string(),
+            concept.to_string(),
+            attributes_hash,
+            first_group.map(str::to_string),
+        );
+        if !self.keys.insert(key) {
+            self.summary.variables_collapsed += 1;
+            return false;
+        }
+        let content = variable_content(item);
+        let changed = !self.existing_contents.contains(&content);
+        if !changed {
+            self.summary.variables_skipped += 1;
+        } else if self.existing_names.contains(content.0.as_str()) {
+            self.summary.variables_updated += 1;
+            self.updated_names.insert(content.0.clone());
+        } else {
+            self.summary.variables_inserted += 1;
+        }
+        self.contents.insert(content);
+        changed
+    }
+
+    /// Return the ids of the associated variables whose name was updated to a different
+    /// content. The variables of a name may be spread over several batches, so the outdated
+    /// ones are only known once every variable is seen.
+    fn outdated_ids(&self) -> Vec<i32> {
+        self.existing
+            .iter()
+            .filter(|(_, content)| {
+                self.updated_names.contains(&content.0) && !self.contents.contains(content)
+            })
+            .map(|(variable_id, _)| *variable_id)
+            .collect()
+    }
+}
+
+/// Upsert geographies in batches of `batch_size`, then replace the API path's
+/// geography associations. Meant to run inside a transaction.
+///
+/// With `skip_unchanged`, write nothing if the API path is already associated with exactly
+/// these geographies. Return the geography counts of an `InsertSummary`.
+fn insert_geographies_in_batches(
+    conn: &mut PgConnection,
+    items: &[GeographyItem],
+    api_path_id: i32,
+    skip_unchanged: bool,
+    batch_size: usize,
+) -> Result<InsertSummary, InsertError> {
+    let unique_items = unique_geographies(items);
+    let geographies_duplicated = items.len() - unique_items.len();
+
+    if skip_unchanged && associated_geographies_match(conn, items, api_path_id)? {
+        return Ok(InsertSummary {
+            geographies_duplicated,
+            geography_unchanged: true,
+            ..InsertSummary::default()
+        });
+    }
+    let mut geography_ids = Vec::with_capacity(unique_items.len());
+    for chunk in unique_items.chunks(batch_size) {
+        geography_ids.extend(upsert_geographies(chunk, conn)?);
+    }
+    let (geographies_inserted, geographies_deleted) =
+        associate_geographies(conn, api_path_id, &geography_ids, batch_size)?;
+    Ok(InsertSummary {
+        geographies_inserted,
+        geographies_deleted,
+        geographies_duplicated,
+        ..InsertSummary::default()
+    })
+}
+
+/// Return the geographies with distinct natural keys. geography.json sometimes lists a
+/// geography twice. Keep the first, so that each is upserted once even if the duplicates fall
+/// in different batches. Geographies with different reference dates are different rows, so
+/// they're all kept.
+fn unique_geographies<'i, 'a>(items: &'i [GeographyItem<'a>]) -> Vec<&'i GeographyItem<'a>> {
+    let mut seen_keys = HashSet::new();
+    items
+        .iter()
+        .filter(|item| seen_keys.insert(item.natural_key()))
+        .collect()
+}
+
+/// The name, `geo_level_id` and `reference_date` of a geography row.
+type GeographyKey = (String, Option<String>, Option<chrono::NaiveDate>);
+
+/// Return whether `existing`, the natural keys of the geographies associated with an API path,
+/// are exactly those of `items`.
+fn geographies_match(existing: &HashSet<GeographyKey>, items: &[GeographyItem]) -> bool {
+    let incoming: HashSet<_> = items.iter().map(GeographyItem::natural_key).collect();
+    incoming.len() == existing.len()
+        && existing
+            .iter()
+            .all(|(name, level, date)| incoming.contains(&(name.as_str(), level.as_deref(), *date)))
+}
+
+/// Return whether the natural keys of the geographies associated with the API path are
+/// exactly those of `items`.
+fn associated_geographies_match(
+    conn: &mut PgConnection,
+    items: &[GeographyItem],
+    api_path_id: i32,
+) -> Result<bool, DieselError> {
+    let existing: HashSet<GeographyKey> = associated_geographies_query(api_path_id)
+        .load(conn)?
+        .into_iter()
+        .collect();
+    Ok(geographies_match(&existing, items))
+}
+
+/// Return the query of the natural keys of the geographies associated with the API path, run
+/// by both `associated_geographies_match` and the async `insert_geographies_in_batches`.
+fn associated_geographies_query(
+    api_path_id: i32,
+) -> impl Query<SqlType = (Text, Nullable<Text>, Nullable<Date>)>
+       + QueryFragment<Pg>
+       + QueryId
+       + RunQueryDsl<PgConnection>
+       + Send {
+    use crate::schema::{api_paths_geography_association, geography};
+
+    api_paths_geography_association::table
+        .inner_join(geography::table)
+        .filter(api_paths_geography_association::api_paths_id.eq(api_path_id))
+        .select((
+            geography::name,
+            geography::geo_level_id,
+            geography::reference_date,
+        ))
+}
+
+/// Insert variables and geography for each of the API paths into the database.
+///
+/// An API path that fails to ingest is recorded in the report and doesn't stop the others
+/// unless more than `options.max_failures` API paths failed.
+///
+/// API paths whose dataset the catalog marks as unavailable are skipped, and aren't in the
+/// report. See `ApiPaths::is_available`.
+///
+/// Each API path that's ingested, unless `options.dry_run`, is marked complete in the
+/// `ingest_state` table, so that `pending_api_paths` can resume an interrupted run.
+///
+/// # Arguments
+///
+/// * `conn` - connection to the database
+/// * `client` - the client to use for fetching the API metadata (JSON)
+/// * `api_paths` - the API paths whose variables and geography to insert
+/// * `variables_unique_key_constraint` - the unique key constraint for the variables table
+/// * `options` - see `IngestOptions`
+///
+/// # Returns
+///
+/// * `Ok(IngestReport)` - what happened to each API path
+/// * `Err(InsertError::ErrorBudgetExhausted)` - if more than `options.max_failures` API paths failed
+pub async fn ingest_api_paths(
+    conn: &mut PgConnection,
+    client: &CachedClient<'_>,
+    api_paths: &[ApiPaths<'_>],
+    variables_unique_key_constraint: &str,
+    options: &IngestOptions,
+) -> Result<IngestReport, InsertError> {
+    ingest_datasets(
+        conn,
+        client,
+        api_paths,
+        variables_unique_key_constraint,
+        options,
+        None,
+    )
+    .await
+}
+
+/// A step of `ingest_datasets`, reported to its progress callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// Ingesting the API path with this title started.
+    EndpointStarted { title: String },
+    /// A response of `bytes` bytes was fetched, or read from the cache, for the API path being
+    /// ingested: first its variables, then its geography.
+    Fetched { bytes: usize },
+    /// The API path was ingested.
+    EndpointFinished { summary: InsertSummary },
+    /// The API path failed to ingest. The error is in the `IngestReport`.
+    EndpointFailed { title: String },
+}
+
+/// Like `ingest_api_paths`, but report each step to `progress`, e.g. to show progress in a
+/// GUI. With `progress` `None`, this is `ingest_api_paths`.
+///
+/// # Arguments
+///
+/// * `conn` - connection to the database
+/// * `client` - the client to use for fetching the API metadata (JSON)
+/// * `api_paths` - the API paths whose variables and geography to insert
+/// * `variables_unique_key_constraint` - the unique key constraint for the variables table
+/// * `options` - see `IngestOptions`
+/// * `progress` - called with each `ProgressEvent`, in order
+pub async fn ingest_datasets(
+    conn: &mut PgConnection,
+    client: &CachedClient<'_>,
+    api_paths: &[ApiPaths<'_>],
+    variables_unique_key_constraint: &str,
+    options: &IngestOptions,
+    mut progress: Option<&mut (dyn FnMut(ProgressEvent) + Send)>,
+) -> Result<IngestReport, InsertError> {
+    let mut emit = |event| {
+        if let Some(progress) = progress.as_mut() {
+            progress(event);
+        }
+    };
+    let ingested = if options.only_missing {
+        ingested_api_path_ids(conn, api_paths)?
+    } else {
+        HashSet::new()
+    };
+    let api_paths: Vec<&ApiPaths> = api_paths
+        .iter()
+        .filter(|api_path| should_ingest(api_path, &ingested))
+        .collect();
+    if options.fetch_concurrency != FetchConcurrency::default() {
+        prefetch(client, &api_paths, options.fetch_concurrency).await;
+    }
+    tracing::info!(api_paths = api_paths.len(), "ingesting API paths");
+    let mut report = IngestReport::default();
+    for api_path in api_paths {
+        let link = api_path.c_variables_link.to_string();
+        emit(ProgressEvent::EndpointStarted {
+            title: api_path.title.to_string(),
+        });
+        let result = ingest_api_path(
+            conn,
+            client,
+            api_path,
+            variables_unique_key_constraint,
+            options,
+            &mut |bytes| emit(ProgressEvent::Fetched { bytes }),
+        )
+        .await;
+        // A dry run commits nothing, so there's nothing to resume from.
+        let result = match result {
+            Ok(summary) if !options.dry_run => mark_completed(conn, api_path.id)
+                .map(|()| summary)
+                .map_err(InsertError::from),
+            result => result,
+        };
+        match result {
+            Ok(summary) => {
+                emit(ProgressEvent::EndpointFinished {
+                    summary: summary.clone(),
+                });
+                report.ingested.push((link, summary));
+            }
+            Err(e) => {
+                emit(ProgressEvent::EndpointFailed {
+                    title: api_path.title.to_string(),
+                });
+                report.failed.push((link, e));
+            }
+        }
+        if options
+            .max_failures
+            .is_some_and(|max_failures| report.failed.len() > max_failures)
+        {
+            let failures = report.failed.len();
+            tracing::error!(failures, "too many API paths failed, stopping");
+            let (_, last) = report.failed.pop().expect("at least one API path failed");
+            return Err(InsertError::ErrorBudgetExhausted {
+                failures,
+                last: Box::new(last),
+            });
+        }
+    }
+    tracing::info!(
+        ingested = report.ingested.len(),
+        failed = report.failed.len(),
+        "ingested API paths"
+    );
+    Ok(report)
+}
+
+/// Return whether `ingest_datasets` should ingest the API path, logging why not.
+fn should_ingest(api_path: &ApiPaths<'_>, ingested: &HashSet<i32>) -> bool {
+    if !api_path.is_available() {
+        tracing::info!(
+            title = %api_path.title,
+            "skipping API path because its dataset is unavailable"
+        );
+        return false;
+    }
+    if ingested.contains(&api_path.id) {
+        tracing::info!(
+            title = %api_path.title,
+            "skipping API path because it already has variables and geography"
+        );
+        return false;
+    }
+    true
+}
+
+/// Return the ids of the API paths that have both variables and geography associated with
+/// them, for `IngestOptions::only_missing`.
+fn ingested_api_path_ids(
+    conn: &mut PgConnection,
+    api_paths: &[ApiPaths<'_>],
+) -> Result<HashSet<i32>, DieselError> {
+    use crate::schema::{
+        api_paths, api_paths_geography_association, api_paths_variables_association,
+    };
+
+    let ids: Vec<i32> = api_paths.iter().map(|api_path| api_path.id).collect();
+    let ingested: Vec<i32> = api_paths::table
+        .filter(api_paths::id.eq_any(ids))
+        .filter(diesel::dsl::exists(
+            api_paths_variables_association::table
+                .filter(api_paths_variables_association::api_paths_id.eq(api_paths::id)),
+        ))
+        .filter(diesel::dsl::exists(
+            api_paths_geography_association::table
+                .filter(api_paths_geography_association::api_paths_id.eq(api_paths::id)),
+        ))
+        .select(api_paths::id)
+        .load(conn)?;
+    Ok(ingested.into_iter().collect())
+}
+
+/// Record in the `ingest_state` table that the API path was ingested, so that
+/// `pending_api_paths` no longer returns it.
+fn mark_completed(conn: &mut PgConnection, api_path_id: i32) -> Result<(), DieselError> {
+    use crate::schema::ingest_state::dsl::*;
+
+    diesel::insert_into(ingest_state)
+        .values(api_paths_id.eq(api_path_id))
+        .on_conflict(api_paths_id)
+        .do_update()
+        .set(completed_at.eq(excluded(completed_at)))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// The top-level catalog of the US Census API endpoints.
+pub const CATALOG_URL: &str = "https://api.census.gov/data.json";
+
+/// Ingest every endpoint of a survey, e.g. "acs/acs5", listed in https://api.census.gov/data.json.
+///
+/// See `ingest_survey_from`, which this calls with `CATALOG_URL` and default options.
+pub async fn ingest_survey(
+    conn: &mut PgConnection,
+    client: &CachedClient<'_>,
+    survey: &str,
+    vintage: Option<i32>,
+) -> Result<IngestReport, InsertError> {
+    let catalog_url = Url::parse(CATALOG_URL)?;
+    ingest_survey_from(
+        conn,
+        client,
+        &catalog_url,
+        survey,
+        vintage,
+        &IngestOptions::default(),
+    )
+    .await
+}
+
+/// Fetch the catalog, insert the API paths of a survey that aren't in the database yet, and
+/// ingest their variables and geography.
+///
+/// # Arguments
+///
+/// * `conn` - connection to the database
+/// * `client` - the client to use for fetching the catalog and API metadata (JSON)
+/// * `catalog_url` - the URL of the catalog, usually `CATALOG_URL`
+/// * `survey` - the `/`-separated `c_dataset` of the survey, e.g. "acs/acs5" for the URL path
+///   /data/2020/acs/acs5
+/// * `vintage` - the `c_vintage` to ingest, or `None` to ingest every vintage
+/// * `options` - see `IngestOptions`
+pub async fn ingest_survey_from(
+    conn: &mut PgConnection,
+    client: &CachedClient<'_>,
+    catalog_url: &Url,
+    survey: &str,
+    vintage: Option<i32>,
+    options: &IngestOptions,
+) -> Result<IngestReport, InsertError> {
+    use crate::schema::api_paths::dsl::*;
+
+    let dataset: Vec<Option<&str>> = survey.split('/').map(Some).collect();
+    let matches = |api_path: &ApiPaths| {
+        api_path
+            .c_dataset
+            .iter()
+            .map(Option::as_deref)
+            .eq(dataset.iter().copied())
+            && vintage.map_or(true, |vintage| api_path.c_vintage == Some(vintage))
+    };
+
+    let catalog_text = client.fetch(catalog_url).await?;
+    let mut catalog = UsCensusApisResponse::from_slice(catalog_text.as_bytes())?;
+    catalog.dataset.retain(|api_path| matches(api_path));
+    upsert_api_paths(conn, &catalog.dataset)?;
+
+    let mut query = api_paths
+        .filter(c_dataset.eq(&dataset))
+        .select(ApiPaths::as_select())
+        .order(id)
+        .into_boxed();
+    if let Some(vintage) = vintage {
+        query = query.filter(c_vintage.eq(vintage));
+    }
+    let to_ingest = query.load::<ApiPaths>(conn)?;
+
+    let constraints = get_unique_constraints(conn, "variables")?;
+    if constraints.len() != 1 {
+        return Err(InsertError::VariablesConstraint(constraints.len()));
+    }
+    ingest_api_paths(conn, client, &to_ingest, &constraints[0], options).await
+}
+
+/// Fetch the catalog at `CATALOG_URL` and insert the API paths that aren't in the database yet.
+///
+/// See `sync_api_paths_from`.
+pub async fn sync_api_paths(
+    conn: &mut PgConnection,
+    client: &CachedClient<'_>,
+) -> Result<usize, InsertError> {
+    let catalog_url = Url::parse(CATALOG_URL)?;
+    sync_api_paths_from(conn, client, &catalog_url).await
+}
+
+/// Fetch the catalog and insert the API paths that aren't in the database yet, so that
+/// datasets published since the last sync are added. The other API paths are updated. See
+/// `upsert_api_paths`.
+///
+/// # Arguments
+///
+/// * `conn` - connection to the database
+/// * `client` - the client to use for fetching the catalog
+/// * `catalog_url` - the URL of the catalog, usually `CATALOG_URL`
+///
+/// # Returns
+///
+/// The number of API paths inserted.
+pub async fn sync_api_paths_from(
+    conn: &mut PgConnection,
+    client: &CachedClient<'_>,
+    catalog_url: &Url,
+) -> Result<usize, InsertError> {
+    let catalog_text = client.fetch(catalog_url).await?;
+    let catalog = UsCensusApisResponse::from_slice(catalog_text.as_bytes())?;
+    let inserted = upsert_api_paths(conn, &catalog.dataset)?;
+    tracing::info!(
+        inserted,
+        catalog = catalog.dataset.len(),
+        "synced the API paths of the catalog"
+    );
+    Ok(inserted)
+}
+
+/// Insert the API paths whose `(c_vintage, c_dataset)` isn't in the `api_paths` table yet,
+/// and update the links, title and description of the others, so that upserting a catalog
+/// again doesn't duplicate its API paths. Of the API paths sharing a `(c_vintage, c_dataset)`,
+/// only the first one is upserted.
+///
+/// # Arguments
+///
+/// * `conn` - connection to the database
+/// * `catalog` - the API paths to upsert, e.g. the `dataset` of a `UsCensusApisResponse`
+///
+/// # Returns
+///
+/// * `Ok(usize)` - the number of API paths inserted, not counting those updated
+/// * `Err(InsertError::InvalidLink)` - if an API path fails `validate_api_path`, in which case
+///   none are upserted
+pub fn upsert_api_paths(
+    conn: &mut PgConnection,
+    catalog: &[ApiPaths<'_>],
+) -> Result<usize, InsertError> {
+    use crate::schema::api_paths::dsl::*;
+
+    catalog.iter().try_for_each(validate_api_path)?;
+
+    // A single INSERT can't update the same row twice.
+    let mut seen = HashSet::new();
+    let unique_api_paths: Vec<&ApiPaths> = catalog
+        .iter()
+        .filter(|api_path| seen.insert((api_path.c_vintage, &api_path.c_dataset)))
+        .collect();
+    conn.transaction(|conn| {
+        let mut inserted = 0;
+        for chunk in unique_api_paths.chunks(DEFAULT_BATCH_SIZE) {
+            let is_inserted: Vec<bool> = diesel::insert_into(api_paths)
+                .values(chunk.to_vec())
+                .on_conflict(on_constraint(API_PATHS_UNIQUE_KEY_CONSTRAINT))
+                .do_update()
+                .set((
+                    c_geography_link.eq(excluded(c_geography_link)),
+                    c_variables_link.eq(excluded(c_variables_link)),
+                    title.eq(excluded(title)),
+                    description.eq(excluded(description)),
+                    c_is_aggregate.eq(excluded(c_is_aggregate)),
+                    c_is_available.eq(excluded(c_is_available)),
+                    spatial.eq(excluded(spatial)),
+                    temporal.eq(excluded(temporal)),
+                    modified.eq(excluded(modified)),
+                ))
+                // `xmax` is 0 for a row inserted by this statement, and the id of the
+                // updating transaction for an updated row.
+                .returning(sql::<diesel::sql_types::Bool>("xmax = 0"))
+                .get_results(conn)?;
+            inserted += is_inserted.into_iter().filter(|&is_new| is_new).count();
+        }
+        Ok::<_, DieselError>(inserted)
+    })
+    .map_err(InsertError::from)
+}
+
+/// The unique constraint of the `api_paths` table, made `NULLS NOT DISTINCT` by migration 010.
+const API_PATHS_UNIQUE_KEY_CONSTRAINT: &str = "api_paths_c_vintage_c_dataset_key";
+
+/// Fetch the API paths' variables and geography into `client`'s cache. See `prefetch_with`.
+async fn prefetch(
+    client: &CachedClient<'_>,
+    api_paths: &[&ApiPaths<'_>],
+    concurrency: FetchConcurrency,
+) {
+    prefetch_with(api_paths, concurrency, |_, link| async move {
+        if let Ok(url) = Url::parse(&link) {
+            // The API path fails when it's fetched again to be inserted.
+            let _ = client.fetch(&url).await;
+        }
+    })
+    .await
+}
+
+/// Call `fetch` on every variables link, at most `concurrency.variables` at a time, then on
+/// every geography link, at most `concurrency.geography` at a time.
+async fn prefetch_with<F, Fut>(api_paths: &[&ApiPaths<'_>], concurrency: FetchConcurrency, fetch: F)
+where
+    F: Fn(FetchPhase, String) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    for (phase, limit) in [
+        (FetchPhase::Variables, concurrency.variables),
+        (FetchPhase::Geography, concurrency.geography),
+    ] {
+        let links = api_paths.iter().map(|api_path| match phase {
+            FetchPhase::Variables => api_path.c_variables_link.to_string(),
+            FetchPhase::Geography => api_path.c_geography_link.to_string(),
+        });
+        futures::stream::iter(links)
+            .for_each_concurrent(limit.max(1), |link| fetch(phase, link))
+            .await;
+    }
+}
+
+/// The name, `_concept_hash`, `_attributes_hash` and `_first_group` of a variable.
+type VariableContent = (String, Option<String>, Option<String>, Option<String>);
+
+/// Return the name and hashed content of the variable, as the `variables` table stores them.
+fn variable_content(item: &VariablesItem) -> VariableContent {
+    (
+        item.name.to_string(),
+        Some(item.concept_hash()),
+        item.attributes_hash(),
+        item.first_group().map(str::to_string),
+    )
+}
+
+/// Return the id and hashed content of each variable associated with the API path.
+fn associated_variables(
+    conn: &mut PgConnection,
+    api_path_id: i32,
+) -> Result<Vec<(i32, VariableContent)>, DieselError> {
+    associated_variables_query(api_path_id).load(conn)
+}
+
+/// The SQL type of a variable's id and `VariableContent`.
+type VariableRowSql = (
+    Integer,
+    (Text, Nullable<Text>, Nullable<Text>, Nullable<Text>),
+);
+
+/// Return the query of the id and hashed content of each variable associated with the API
+/// path, run by both `associated_variables` and its async counterpart.
+fn associated_variables_query(
+    api_path_id: i32,
+) -> impl Query<SqlType = VariableRowSql> + QueryFragment<Pg> + QueryId + RunQueryDsl<PgConnection> + Send
+{
+    use crate::schema::{api_paths_variables_association, variables};
+
+    variables::table
+        .inner_join(api_paths_variables_association::table)
+        .filter(api_paths_variables_association::api_paths_id.eq(api_path_id))
+        .select((
+            variables::id,
+            (
+                variables::name,
+                variables::_concept_hash,
+                variables::_attributes_hash,
+                variables::_first_group,
+            ),
+        ))
+}
+
+/// The values compared by the unique constraint of the `variables` table.
+type VariableKey = (String, String, Option<String>, Option<String>);
+
+/// Return the values compared by the unique constraint of the `variables` table.
+fn variables_unique_key<'b>(
+    item: &'b VariablesItem,
+) -> (&'b str, &'b str, Option<String>, Option<&'b str>) {
+    (
+        item.name.as_ref(),
+        item.concept.as_deref().unwrap_or(""),
+        item.attributes_hash(),
+        item.first_group(),
+    )
+}
+
+/// Insert variables into the `variables` table. Of the variables sharing a unique key, only
+/// the first is inserted.
+fn insert_variables<'v>(
+    items: &[impl Borrow<VariablesItem<'v>>],
+    conn: &mut PgConnection,
+    api_path_id: i32,
+    unique_key_constraint: &str,
+) -> Result<(), InsertError> {
+    let unique_items = unique_variables(items);
+    let upserted: Vec<(i32, VariableContent)> =
+        upsert_variables_statement(unique_items.clone(), unique_key_constraint).load(conn)?;
+    let variable_ids = variable_associations(api_path_id, &upserted);
+    insert_variable_associations_statement(&variable_ids).execute(conn)?;
+    upsert_variable_values(conn, &unique_items, &upserted)?;
+    Ok(())
+}
+
+/// Return the statement that upserts `items` into the `variables` table and returns the id
+/// and content of each row, run by both `insert_variables` and its async counterpart.
+fn upsert_variables_statement<'q>(
+    items: Vec<&'q VariablesItem<'q>>,
+    unique_key_constraint: &'q str,
+) -> impl Query<SqlType = VariableRowSql>
+       + QueryFragment<Pg>
+       + QueryId
+       + RunQueryDsl<PgConnection>
+       + Send
+       + 'q {
+    use crate::schema::variables;
+
+    diesel::insert_into(variables::table)
+        .values(items)
+        .on_conflict(on_constraint(unique_key_constraint))
+        // UPDATE command is only executed in order to return the `id` column. No value
+        // needs to be updated. In other words, `.do_nothing()` only doesn't work because
+        // it's not compatible with a RETURNING clause.
+        .do_update()
+        .set(variables::name.eq(sql("EXCLUDED.name")))
+        .returning((
+            variables::id,
+            (
+                variables::name,
+                variables::_concept_hash,
+                variables::_attributes_hash,
+                variables::_first_group,
+            ),
+        ))
+}
+
+/// Return the statement that inserts the `associations` of an API path with its variables,
+/// run by both `insert_variables` and its async counterpart.
+fn insert_variable_associations_statement(
+    associations: &[ApiPathsVariablesAssociation],
+) -> impl QueryFragment<Pg> + QueryId + RunQueryDsl<PgConnection> + Send + '_ {
+    use crate::schema::api_paths_variables_association;
+
+    diesel::insert_into(api_paths_variables_association::table)
+        .values(associations)
+        .on_conflict_do_nothing()
+}
+
+/// Return the first of the variables sharing a unique key. Postgres refuses to upsert the same
+/// row twice in one statement, so only those are sent.
+fn unique_variables<'i, 'v>(
+    items: &'i [impl Borrow<VariablesItem<'v>>],
+) -> Vec<&'i VariablesItem<'v>> {
+    let mut seen_keys = HashSet::new();
+    items
+        .iter()
+        .map(Borrow::borrow)
+        .filter(|item| seen_keys.insert(variables_unique_key(item)))
+        .collect()
+}
+
+/// Return the associations of the API path with the `upserted` variables.
+fn variable_associations(
+    api_path_id: i32,
+    upserted: &[(i32, VariableContent)],
+) -> Vec<ApiPathsVariablesAssociation> {
+    upserted
+        .iter()
+        .map(|(variable_id, _)| ApiPathsVariablesAssociation {
+            // Use a dummy value; otherwise the code won't compile. The postgres database
+            // will ignore the dummy and assign its own.
+            id: 0,
+            api_paths_id: api_path_id,
+            variables_id: *variable_id,
+        })
+        .collect()
+}
+
+/// Return the `variable_values` rows of the coded values of `items`. `upserted` holds the id
+/// and content of each variable row, as returned by inserting `items`.
+fn variable_value_rows<'i>(
+    items: &[&'i VariablesItem],
+    upserted: &[(i32, VariableContent)],
+) -> Vec<VariableValue<'i>> {
+    let ids: HashMap<&VariableContent, i32> = upserted
+        .iter()
+        .map(|(variable_id, content)| (content, *variable_id))
+        .collect();
+    items
+        .iter()
+        .filter_map(|item| Some((*ids.get(&variable_content(item))?, item.values.as_ref()?)))
+        .flat_map(|(variables_id, values)| {
+            values.iter().map(move |(code, label)| VariableValue {
+                // Use a dummy value, like for the associations.
+                id: 0,
+                variables_id,
+                code: Cow::Borrowed(code.as_ref()),
+                label: Cow::Borrowed(label.as_ref()),
+            })
+        })
+        .collect()
+}
+
+/// Upsert the coded values of `items` into the `variable_values` table. `upserted` holds the
+/// id and content of each variable row, as returned by inserting `items`.
+fn upsert_variable_values(
+    conn: &mut PgConnection,
+    items: &[&VariablesItem],
+    upserted: &[(i32, VariableContent)],
+) -> Result<(), InsertError> {
+    let rows = variable_value_rows(items, upserted);
+    // Each row binds 3 parameters, fewer than a `variables` row.
+    for chunk in rows.chunks(MAX_BATCH_SIZE) {
+        upsert_variable_values_statement(chunk).execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Return the statement that upserts `rows` into the `variable_values` table, run by both
+/// `upsert_variable_values` and its async counterpart. A coded value that's already stored
+/// gets the new label.
+fn upsert_variable_values_statement<'q>(
+    rows: &'q [VariableValue<'q>],
+) -> impl QueryFragment<Pg> + QueryId + RunQueryDsl<PgConnection> + Send + 'q {
+    use crate::schema::variable_values;
+
+    diesel::insert_into(variable_values::table)
+        .values(rows)
+        .on_conflict((variable_values::variables_id, variable_values::code))
+        .do_update()
+        .set(variable_values::label.eq(excluded(variable_values::label)))
+}
+
+/// Upsert geographies into the `geography` table and return their ids. Postgres refuses to
+/// upsert the same row twice in one statement, so `items` must have distinct natural keys.
+///
+/// A geography row is shared by every API path with the same `name`, `geo_level_id` and
+/// `reference_date`, so an existing row keeps its id. Its other columns get the latest values,
+/// since upstream may change e.g. the `requires` or `limit` of the same geography.
+fn upsert_geographies(
+    items: &[&GeographyItem],
+    conn: &mut PgConnection,
+) -> Result<Vec<i32>, InsertError> {
+    Ok(upsert_geographies_statement(items.to_vec()).get_results(conn)?)
+}
+
+/// Return the statement that upserts `items` into the `geography` table and returns their ids,
+/// run by both `upsert_geographies` and the async `insert_geographies_in_batches`.
+fn upsert_geographies_statement<'q>(
+    items: Vec<&'q GeographyItem<'q>>,
+) -> impl Query<SqlType = Integer> + QueryFragment<Pg> + QueryId + RunQueryDsl<PgConnection> + Send + 'q
+{
+    use crate::schema::geography::dsl::*;
+
+    diesel::insert_into(geography)
+        .values(items)
+        .on_conflict(on_constraint(GEOGRAPHY_UNIQUE_KEY_CONSTRAINT))
+        .do_update()
+        .set((
+            geo_level_display.eq(excluded(geo_level_display)),
+            requires.eq(excluded(requires)),
+            wildcard.eq(excluded(wildcard)),
+            limit.eq(excluded(limit)),
+            optional_with_wildcard_for.eq(excluded(optional_with_wildcard_for)),
+            reference_date_precision.eq(excluded(reference_date_precision)),
+        ))
+        .returning(id)
+}
+
+/// The unique constraint of the `geography` table, added by migration 003.
+const GEOGRAPHY_UNIQUE_KEY_CONSTRAINT: &str = "geography_name_geo_level_id_reference_date_key";
+
+/// Return the associations of the API path with the geographies.
+fn geography_associations(
+    api_path_id: i32,
+    geography_ids: &[i32],
+) -> Vec<ApiPathsGeographyAssociation> {
+    geography_ids
+        .iter()
+        .map(|&geo_id| ApiPathsGeographyAssociation {
+            // Use a dummy value; otherwise the code won't compile. The postgres database
+            // will ignore the dummy and assign its own.
+            id: 0,
+            api_paths_id: api_path_id,
+            geography_id: geo_id,
+        })
+        .collect()
+}
+
+/// Associate exactly the given geographies with the API path, deleting its other
+/// geography associations. The geography rows themselves are left alone, because other
+/// API paths may share them. Return the number of associations inserted and deleted.
+///
+/// The delete and the inserts run in their own transaction, a savepoint if there's already
+/// one, so that if an insert fails the API path keeps its previous associations.
+fn associate_geographies(
+    conn: &mut PgConnection,
+    api_path_id: i32,
+    geography_ids: &[i32],
+    batch_size: usize,
+) -> Result<(usize, usize), InsertError> {
+    conn.transaction(|conn| {
+        let deleted =
+            delete_geography_associations_statement(api_path_id, geography_ids).execute(conn)?;
+        let associations = geography_associations(api_path_id, geography_ids);
+        let mut inserted = 0;
+        for chunk in associations.chunks(batch_size) {
+            inserted += insert_geography_associations_statement(chunk).execute(conn)?;
+        }
+        Ok((inserted, deleted))
+    })
+}
+
+/// Return the statement that deletes the API path's associations with geographies other than
+/// `geography_ids`, run by both `associate_geographies` and its async counterpart.
+fn delete_geography_associations_statement(
+    api_path_id: i32,
+    geography_ids: &[i32],
+) -> impl QueryFragment<Pg> + QueryId + RunQueryDsl<PgConnection> + Send + '_ {
+    use crate::schema::api_paths_geography_association::dsl::*;
+
+    diesel::delete(api_paths_geography_association)
+        .filter(api_paths_id.eq(api_path_id))
+        .filter(diesel::dsl::not(geography_id.eq_any(geography_ids)))
+}
+
+/// Return the statement that inserts the `associations` of an API path with geographies, run by
+/// both `associate_geographies` and its async counterpart.
+fn insert_geography_associations_statement(
+    associations: &[ApiPathsGeographyAssociation],
+) -> impl QueryFragment<Pg> + QueryId + RunQueryDsl<PgConnection> + Send + '_ {
+    use crate::schema::api_paths_geography_association;
+
+    diesel::insert_into(api_paths_geography_association::table)
+        .values(associations)
+        .on_conflict_do_nothing()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{insert_api_path, test_connection};
+    use reqwest::Client;
+    use rstest::rstest;
+    use std::borrow::Cow;
+    use std::time::Duration;
+    use wiremock::matchers::path;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn api_path_with_links<'a>(variables_link: &'a str, geography_link: &'a str) -> ApiPaths<'a> {
+        ApiPaths {
+            id: 0,
+            c_vintage: Some(2020),
+            c_dataset: vec![Some(Cow::from("acs")), Some(Cow::from("acs5"))],
+            c_geography_link: Cow::from(geography_link),
+            c_variables_link: Cow::from(variables_link),
+            title: Cow::from("ACS 5-Year Estimates"),
+            description: Cow::from(""),
+            c_is_aggregate: None,
+            c_is_available: None,
+            spatial: None,
+            temporal: None,
+            modified: None,
+        }
+    }
+
+    #[test]
+    fn test_distinct_links_are_ingested() {
+        let api_path = api_path_with_links(
+            "http://api.census.gov/data/2020/acs/acs5/variables.json",
+            "http://api.census.gov/data/2020/acs/acs5/geography.json",
+        );
+        assert!(check_links(&api_path, IdenticalLinks::Fail).unwrap());
+        assert!(check_links(&api_path, IdenticalLinks::Skip).unwrap());
+    }
+
+    #[test]
+    fn test_identical_links_fail() {
+        let link = "http://api.census.gov/data/2020/acs/acs5/variables.json";
+        let api_path = api_path_with_links(link, link);
+
+        let result = check_links(&api_path, IdenticalLinks::Fail);
+
+        match result {
+            Err(InsertError::IdenticalLinks {
+                title,
+                link: err_link,
+            }) => {
+                assert_eq!(title, "ACS 5-Year Estimates");
+                assert_eq!(err_link, link);
+            }
+            other => panic!("Expected InsertError::IdenticalLinks, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_identical_links_skip() {
+        let link = "http://api.census.gov/data/2020/acs/acs5/variables.json";
+        let api_path = api_path_with_links(link, link);
+        assert!(!check_links(&api_path, IdenticalLinks::Skip).unwrap());
+    }
+
+    #[rstest]
+    #[case::not_a_url("not a url", "relative URL without a base")]
+    #[case::not_json(
+        "http://api.census.gov/data/2020/acs/acs5/variables.html",
+        "the link isn't to a .json file"
+    )]
+    fn test_validate_api_path(#[case] variables_link: &str, #[case] expected_reason: &str) {
+        // Arrange
+        let catalog_json = format!(
+            r#"
+    {{
+      "dataset": [
+        {{
+          "c_vintage": 2020,
+          "c_dataset": ["acs", "acs5"],
+          "c_geographyLink": "http://api.census.gov/data/2020/acs/acs5/geography.json",
+          "c_variablesLink": "{}",
+          "title": "ACS 5-Year Estimates",
+          "description": ""
+        }}
+      ]
+    }}"#,
+            variables_link
+        );
+        let catalog: UsCensusApisResponse =
+            serde_json::from_str(&catalog_json).expect("Error parsing JSON");
+
+        // Act
+        let result = validate_api_path(&catalog.dataset[0]);
+
+        // Assert
+        match result {
+            Err(e @ InsertError::InvalidLink { .. }) => assert_eq!(
+                e.to_string(),
+                format!(
+                    "API path 'ACS 5-Year Estimates' has an invalid c_variables_link '{}': {}",
+                    variables_link, expected_reason
+                )
+            ),
+            other => panic!("Expected InsertError::InvalidLink, got {:?}", other),
+        }
+    }
+
+    fn variable<'a>(name: &'a str, label: &'a str) -> VariablesItem<'a> {
+        VariablesItem {
+            id: 0,
+            name: Cow::from(name),
+            label: vec![Cow::from(label)],
+            concept: Some(Cow::from("SEX BY AGE")),
+            required: None,
+            predicate_type: Some(Cow::from("int")),
+            group: Some(vec![Cow::from("B01001")]),
+            limit: Some(0),
+            predicate_only: None,
+            attributes: None,
+            values: None,
+        }
+    }
+
+    #[test]
+    fn test_summary_warns_about_collapsed_variables() {
+        let summary = InsertSummary {
+            variables_collapsed: 2,
+            ..InsertSummary::default()
+        };
+        assert_eq!(summary.warnings(), vec!["collapsed 2 variables"]);
+        assert!(InsertSummary::default().warnings().is_empty());
+    }
+
+    #[test]
+    fn test_summary_display() {
+        let summary = InsertSummary {
+            variables_inserted: 3,
+            variables_skipped: 1,
+            geographies_inserted: 2,
+            ..InsertSummary::default()
+        };
+        let unchanged = InsertSummary {
+            geography_unchanged: true,
+            ..InsertSummary::default()
+        };
+        assert_eq!(
+            summary.to_string(),
+            "3 variables inserted, 0 updated, 1 skipped; 2 geographies inserted, 0 deleted"
+        );
+        assert_eq!(
+            unchanged.to_string(),
+            "0 variables inserted, 0 updated, 0 skipped; geography unchanged"
+        );
+    }
+
+    /// Variables sharing a unique key are inserted as one row and counted as collapsed, and
+    /// not as inserted, even if they're in different batches. Variables that only share a
+    /// name don't collapse.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_insert_variables_counts_collapsed() {
+        // Arrange
+        let conn = &mut test_connection();
+        let api_path_id = insert_api_path(conn, &api_path_with_links("v.json", "g.json"));
+        let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+        let with_attributes = |label: &'static str, attributes: &[&'static str]| {
+            let mut item = variable("B01001_001E", label);
+            item.attributes = Some(attributes.iter().map(|a| Cow::from(*a)).collect());
+            item
+        };
+        let mut other_concept =
+            with_attributes("Estimate!!Total", &["B01001_001EA", "B01001_001M"]);
+        other_concept.concept = Some(Cow::from("SEX BY AGE (TOTAL)"));
+        let mut other_group = with_attributes("Estimate!!Total", &["B01001_001EA", "B01001_001M"]);
+        other_group.group = Some(vec![Cow::from("B01001A")]);
+        let items = [
+            with_attributes("Estimate!!Total", &["B01001_001EA", "B01001_001M"]),
+            other_concept,
+            // The same key as the first: the label isn't part of it and the attributes are
+            // hashed regardless of their order.
+            with_attributes("Estimate!!Total:", &["B01001_001M", "B01001_001EA"]),
+            other_group,
+        ];
+
+        // Act
+        let summary =
+            insert_variables_in_batches(conn, items.iter().map(Ok), api_path_id, constraint, 1)
+                .unwrap();
+
+        // Assert
+        assert_eq!(
+            (summary.variables_inserted, summary.variables_collapsed),
+            (3, 1)
+        );
+        let associated: i64 = crate::schema::api_paths_variables_association::table
+            .filter(crate::schema::api_paths_variables_association::api_paths_id.eq(api_path_id))
+            .count()
+            .get_result(conn)
+            .unwrap();
+        assert_eq!(associated, 3);
+    }
+
+    /// The database fills `_concept_hash` with the same hash as `VariablesItem::concept_hash`.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_concept_hash_column() {
+        use crate::schema::variables;
+
+        // Arrange
+        let conn = &mut test_connection();
+        let api_path_id = insert_api_path(conn, &api_path_with_links("v.json", "g.json"));
+        let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+        let mut other_concept = variable("B01002_001E", "Estimate!!Median age");
+        other_concept.concept = Some(Cow::from("MEDIAN AGE BY SEX"));
+        let mut no_concept = variable("NAME", "Geographic Area Name");
+        no_concept.concept = None;
+        let items = vec![
+            variable("B01001_001E", "Estimate!!Total"),
+            variable("B01001_002E", "Estimate!!Total!!Male"),
+            other_concept,
+            no_concept,
+        ];
+
+        // Act
+        insert_variables(&items, conn, api_path_id, constraint).unwrap();
+
+        // Assert
+        let hashes: Vec<Option<String>> = variables::table
+            .filter(variables::name.eq_any(items.iter().map(|item| item.name.as_ref())))
+            .order(variables::name)
+            .select(variables::_concept_hash)
+            .load(conn)
+            .unwrap();
+        let mut expected: Vec<(&str, String)> = items
+            .iter()
+            .map(|item| (item.name.as_ref(), item.concept_hash()))
+            .collect();
+        expected.sort();
+        assert_eq!(
+            hashes,
+            expected
+                .into_iter()
+                .map(|(_, hash)| Some(hash))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(hashes[0], hashes[1]);
+        assert_ne!(hashes[0], hashes[2]);
+    }
+
+    /// The database fills `_attributes_hash` with the same hash as
+    /// `VariablesItem::attributes_hash`, so attributes in another order are the same variable.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_attributes_hash_column() {
+        use crate::schema::variables;
+
+        // Arrange
+        let conn = &mut test_connection();
+        let api_path_id = insert_api_path(conn, &api_path_with_links("v.json", "g.json"));
+        let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+        let with_attributes = |attributes: &[&'static str]| {
+            let mut item = variable("B01001_001E", "Estimate!!Total");
+            item.attributes = Some(attributes.iter().map(|a| Cow::from(*a)).collect());
+            item
+        };
+        let items = vec![
+            with_attributes(&["B01001_001EA", "B01001_001M"]),
+            with_attributes(&["B01001_001M", "B01001_001EA", "B01001_001M"]),
+            variable("NAME", "Geographic Area Name"),
+        ];
+
+        // Act
+        let summary = insert_variables_in_batches(
+            conn,
+            items.iter().map(Ok),
+            api_path_id,
+            constraint,
+            DEFAULT_BATCH_SIZE,
+        )
+        .unwrap();
+        insert_variables(&items, conn, api_path_id, constraint).unwrap();
+
+        // Assert
+        assert_eq!(summary.variables_collapsed, 1);
+        let hashes: Vec<(String, Option<String>)> = variables::table
+            .filter(variables::name.eq_any(["B01001_001E", "NAME"]))
+            .order(variables::name)
+            .select((variables::name, variables::_attributes_hash))
+            .load(conn)
+            .unwrap();
+        assert_eq!(
+            hashes,
+            vec![
+                ("B01001_001E".to_string(), items[0].attributes_hash()),
+                ("NAME".to_string(), None),
+            ]
+        );
+    }
+
+    /// Coded values are stored in the `variable_values` table and read back with the variable.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_variable_values() {
+        use crate::schema::{variable_values, variables};
+
+        // Arrange
+        let conn = &mut test_connection();
+        let api_path_id = insert_api_path(conn, &api_path_with_links("v.json", "g.json"));
+        let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+        let mut race = variable("RAC1P", "Recoded detailed race code");
+        race.values = Some(
+            [
+                ("1", "White alone"),
+                ("2", "Black or African American alone"),
+            ]
+            .into_iter()
+            .map(|(code, label)| (Cow::from(code), Cow::from(label)))
+            .collect(),
+        );
+        let items = vec![race, variable("NAME", "Geographic Area Name")];
+
+        // Act
+        insert_variables(&items, conn, api_path_id, constraint).unwrap();
+        insert_variables(&items, conn, api_path_id, constraint).unwrap();
+
+        // Assert
+        let codes: Vec<(String, String)> = variable_values::table
+            .inner_join(variables::table)
+            .filter(variables::name.eq("RAC1P"))
+            .order(variable_values::code)
+            .select((variable_values::code, variable_values::label))
+            .load(conn)
+            .unwrap();
+        assert_eq!(
+            codes,
+            vec![
+                ("1".to_string(), "White alone".to_string()),
+                (
+                    "2".to_string(),
+                    "Black or African American alone".to_string()
+                ),
+            ]
+        );
+        let loaded: Vec<VariablesItem<'static>> = variables::table
+            .filter(variables::name.eq_any(["RAC1P", "NAME"]))
+            .order(variables::name.desc())
+            .select(VariablesItem::as_select())
+            .load(conn)
+            .unwrap();
+        assert_eq!(loaded[0].values, items[0].values);
+        assert_eq!(loaded[1].values, None);
+    }
+
+    /// The database fills `_first_group` with the first element of `group`, so variables that
+    /// only differ in their first group get separate rows.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_first_group_column() {
+        use crate::schema::variables;
+
+        // Arrange
+        let conn = &mut test_connection();
+        let api_path_id = insert_api_path(conn, &api_path_with_links("v.json", "g.json"));
+        let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+        let with_group = |group: Option<&[&'static str]>| {
+            let mut item = variable("B01001_001E", "Estimate!!Total");
+            item.group = group.map(|group| group.iter().map(|g| Cow::from(*g)).collect());
+            item
+        };
+        let items = vec![
+            with_group(Some(&["g1", "g2", "g3"])),
+            with_group(Some(&["g2"])),
+            with_group(None),
+        ];
+
+        // Act
+        let summary = insert_variables_in_batches(
+            conn,
+            items.iter().map(Ok),
+            api_path_id,
+            constraint,
+            DEFAULT_BATCH_SIZE,
+        )
+        .unwrap();
+        insert_variables(&items, conn, api_path_id, constraint).unwrap();
+
+        // Assert
+        assert_eq!(summary.variables_collapsed, 0);
+        let first_groups: Vec<Option<String>> = variables::table
+            .filter(variables::name.eq("B01001_001E"))
+            .order(variables::_first_group.asc().nulls_last())
+            .select(variables::_first_group)
+            .load(conn)
+            .unwrap();
+        assert_eq!(
+            first_groups,
+            vec![Some("g1".to_string()), Some("g2".to_string()), None]
+        );
+    }
+
+    /// The run stops once more API paths failed than the budget allows.
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database"]
+    async fn test_ingest_api_paths_error_budget() {
+        // Arrange
+        // The mock server responds 404 with an empty body to everything, so every API
+        // path fails to fetch.
+        let server = MockServer::start().await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        let web_client = Client::new();
+        let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+        let links: Vec<(String, String)> = (0..5)
+            .map(|i| {
+                (
+                    format!("{}/data/{}/variables.json", server.uri(), i),
+                    format!("{}/data/{}/geography.json", server.uri(), i),
+                )
+            })
+            .collect();
+        let api_paths: Vec<ApiPaths> = links
+            .iter()
+            .map(|(variables_link, geography_link)| {
+                api_path_with_links(variables_link, geography_link)
+            })
+            .collect();
+        let options = IngestOptions {
+            max_failures: Some(2),
+            ..Default::default()
+        };
+        let conn = &mut test_connection();
+
+        // Act
+        let result = ingest_api_paths(conn, &client, &api_paths, "unused", &options).await;
+
+        // Assert
+        match result {
+            Err(InsertError::ErrorBudgetExhausted { failures, .. }) => assert_eq!(failures, 3),
+            other => panic!(
+                "Expected InsertError::ErrorBudgetExhausted, got {:?}",
+                other
+            ),
+        }
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    /// API paths whose dataset is unavailable are neither fetched nor reported.
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database"]
+    async fn test_ingest_api_paths_skips_unavailable() {
+        // Arrange
+        let server = MockServer::start().await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        let web_client = Client::new();
+        let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+        let variables_link = format!("{}/data/variables.json", server.uri());
+        let geography_link = format!("{}/data/geography.json", server.uri());
+        let mut api_path = api_path_with_links(&variables_link, &geography_link);
+        api_path.c_is_available = Some(false);
+        let options = IngestOptions {
+            fetch_concurrency: FetchConcurrency {
+                variables: 2,
+                geography: 2,
+            },
+            ..Default::default()
+        };
+        let conn = &mut test_connection();
+
+        // Act
+        let report = ingest_api_paths(conn, &client, &[api_path], "unused", &options)
+            .await
+            .unwrap();
+
+        // Assert
+        assert!(report.ingested.is_empty());
+        assert!(report.failed.is_empty());
+        assert!(server.received_requests().await.unwrap().is_empty());
+    }
+
+    /// With `only_missing`, an API path that already has variables and geography isn't
+    /// fetched again, while one without them is ingested.
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database"]
+    async fn test_ingest_api_paths_only_missing() {
+        // Arrange
+        let server = MockServer::start().await;
+        for dataset in ["done", "new"] {
+            // Each file is fetched once: "done" by the first run, "new" by the second.
+            Mock::given(path(format!("/data/{}/variables.json", dataset)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(
+                    r#"{"variables": {"B01001_001E": {"label": "Estimate!!Total", "group": "B01001"}}}"#,
+                ))
+                .expect(1)
+                .mount(&server)
+                .await;
+            Mock::given(path(format!("/data/{}/geography.json", dataset)))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_string(r#"{"fips": [{"name": "us"}]}"#),
+                )
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let links: Vec<(String, String)> = ["done", "new"]
+            .iter()
+            .map(|dataset| {
+                (
+                    format!("{}/data/{}/variables.json", server.uri(), dataset),
+                    format!("{}/data/{}/geography.json", server.uri(), dataset),
+                )
+            })
+            .collect();
+        let conn = &mut test_connection();
+        let mut api_paths: Vec<ApiPaths> = links
+            .iter()
+            .map(|(variables_link, geography_link)| {
+                api_path_with_links(variables_link, geography_link)
+            })
+            .collect();
+        api_paths[1].c_vintage = Some(2021);
+        for api_path in api_paths.iter_mut() {
+            api_path.id = insert_api_path(conn, api_path);
+        }
+        let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+        let cache_dir = tempfile::tempdir().unwrap();
+        let web_client = Client::new();
+        let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+        ingest_api_paths(
+            conn,
+            &client,
+            &api_paths[..1],
+            constraint,
+            &IngestOptions::default(),
+        )
+        .await
+        .unwrap();
+        // A fresh cache, so that "done" would be requested again if it weren't skipped.
+        let cache_dir = tempfile::tempdir().unwrap();
+        let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+        let options = IngestOptions {
+            only_missing: true,
+            ..Default::default()
+        };
+
+        // Act
+        let report = ingest_api_paths(conn, &client, &api_paths, constraint, &options)
+            .await
+            .unwrap();
+
+        // Assert
+        let ingested: Vec<&str> = report
+            .ingested
+            .iter()
+            .map(|(link, _)| link.as_str())
+            .collect();
+        assert_eq!(ingested, vec![links[1].0.as_str()]);
+        assert!(report.failed.is_empty());
+        assert_eq!(count_associations(conn, api_paths[1].id), (1, 1));
+    }
+
+    fn count_associations(conn: &mut PgConnection, api_path_id: i32) -> (i64, i64) {
+        use crate::schema::{api_paths_geography_association, api_paths_variables_association};
+
+        let variables: i64 = api_paths_variables_association::table
+            .filter(api_paths_variables_association::api_paths_id.eq(api_path_id))
+            .count()
+            .get_result(conn)
+            .unwrap();
+        let geographies: i64 = api_paths_geography_association::table
+            .filter(api_paths_geography_association::api_paths_id.eq(api_path_id))
+            .count()
+            .get_result(conn)
+            .unwrap();
+        (variables, geographies)
+    }
+
+    #[rstest]
+    #[case::default(None, Some(DEFAULT_BATCH_SIZE))]
+    #[case::zero(Some(0), Some(1))]
+    #[case::max(Some(MAX_BATCH_SIZE), Some(MAX_BATCH_SIZE))]
+    #[case::too_large(Some(MAX_BATCH_SIZE + 1), None)]
+    fn test_checked_batch_size(#[case] batch_size: Option<usize>, #[case] expected: Option<usize>) {
+        let options = IngestOptions {
+            batch_size,
+            ..Default::default()
+        };
+        match options.checked_batch_size() {
+            Ok(batch_size) => assert_eq!(Some(batch_size), expected),
+            Err(e) => {
+                assert_eq!(expected, None);
+                assert!(matches!(e, InsertError::BatchTooLarge { max, .. } if max == 7281));
+            }
+        }
+    }
+
+    /// A dry run reports what it would have inserted, but leaves every table unchanged.
+    #[rstest]
+    #[case::single_transaction(false)]
+    #[case::checkpoint_variables(true)]
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database"]
+    async fn test_dry_run(#[case] checkpoint_variables: bool) {
+        use crate::schema::{geography, variables};
+
+        // Arrange
+        let server = MockServer::start().await;
+        Mock::given(path("/data/2020/variables.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"variables": {"B01001_001E": {"label": "Estimate!!Total", "group": "B01001"}}}"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(path("/data/2020/geography.json"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"{"fips": [{"name": "us"}]}"#),
+            )
+            .mount(&server)
+            .await;
+        let web_client = Client::new();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+        let variables_link = format!("{}/data/2020/variables.json", server.uri());
+        let geography_link = format!("{}/data/2020/geography.json", server.uri());
+        let mut api_path = api_path_with_links(&variables_link, &geography_link);
+        let conn = &mut test_connection();
+        api_path.id = insert_api_path(conn, &api_path);
+        let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+        let options = IngestOptions {
+            checkpoint_variables,
+            dry_run: true,
+            ..Default::default()
+        };
+        let count_rows = |conn: &mut PgConnection| -> (i64, i64) {
+            (
+                variables::table.count().get_result(conn).unwrap(),
+                geography::table.count().get_result(conn).unwrap(),
+            )
+        };
+        let rows_before = count_rows(conn);
+
+        // Act
+        let summary = insert_variables_and_geography_for_api_path(
+            conn, &client, &api_path, constraint, &options,
+        )
+        .await
+        .unwrap();
+
+        // Assert
+        assert_eq!(
+            (summary.variables_inserted, summary.geographies_inserted),
+            (1, 1)
+        );
+        assert_eq!(count_rows(conn), rows_before);
+        assert_eq!(count_associations(conn, api_path.id), (0, 0));
+    }
+
+    /// A batch size that's too large is rejected before anything is fetched or inserted.
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database"]
+    async fn test_batch_too_large() {
+        // Arrange
+        let server = MockServer::start().await;
+        let web_client = Client::new();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+        let variables_link = format!("{}/data/2020/variables.json", server.uri());
+        let geography_link = format!("{}/data/2020/geography.json", server.uri());
+        let mut api_path = api_path_with_links(&variables_link, &geography_link);
+        let conn = &mut test_connection();
+        api_path.id = insert_api_path(conn, &api_path);
+        let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+        let options = IngestOptions {
+            batch_size: Some(10_000),
+            ..Default::default()
+        };
+
+        // Act
+        let result = insert_variables_and_geography_for_api_path(
+            conn, &client, &api_path, constraint, &options,
+        )
+        .await;
+
+        // Assert
+        assert!(matches!(
+            result,
+            Err(InsertError::BatchTooLarge {
+                batch_size: 10_000,
+                ..
+            })
+        ));
+        assert!(server.received_requests().await.unwrap().is_empty());
+        assert_eq!(count_associations(conn, api_path.id), (0, 0));
+    }
+
+    /// With checkpointing, a geography failure keeps the variables, and retrying the
+    /// geography doesn't fetch or insert the variables again.
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database"]
+    async fn test_checkpoint_variables() {
+        // Arrange
+        let server = MockServer::start().await;
+        Mock::given(path("/data/2020/variables.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"variables": {"B01001_001E": {"label": "Estimate!!Total", "group": "B01001"}}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let web_client = Client::new();
+        // The mock server responds 404 to the geography request, which fails as
+        // `FetchError::HttpStatus` before anything is parsed.
+        let failing_cache_dir = tempfile::tempdir().unwrap();
+        let failing_client = CachedClient::new(failing_cache_dir.path().to_path_buf(), &web_client);
+        let variables_link = format!("{}/data/2020/variables.json", server.uri());
+        let geography_link = format!("{}/data/2020/geography.json", server.uri());
+        let mut api_path = api_path_with_links(&variables_link, &geography_link);
+        let conn = &mut test_connection();
+        api_path.id = insert_api_path(conn, &api_path);
+        let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+        let options = IngestOptions {
+            checkpoint_variables: true,
+            ..Default::default()
+        };
+
+        // Act
+        let result = insert_variables_and_geography_for_api_path(
+            conn,
+            &failing_client,
+            &api_path,
+            constraint,
+            &options,
+        )
+        .await;
+
+        // Assert
+        match result {
+            Err(InsertError::GeographyAfterCheckpoint { source, .. }) => assert!(matches!(
+                *source,
+                InsertError::Http(FetchError::HttpStatus {
+                    status: reqwest::StatusCode::NOT_FOUND,
+                    ..
+                })
+            )),
+            other => panic!(
+                "Expected InsertError::GeographyAfterCheckpoint, got {:?}",
+                other
+            ),
+        }
+        assert_eq!(count_associations(conn, api_path.id), (1, 0));
+
+        // Arrange
+        Mock::given(path("/data/2020/geography.json"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"{"fips": [{"name": "us"}]}"#),
+            )
+            .mount(&server)
+            .await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+
+        // Act
+        insert_geography_for_api_path(conn, &client, &api_path, &IngestOptions::default())
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(count_associations(conn, api_path.id), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_respects_concurrency_per_phase() {
+        use std::collections::HashMap;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Mutex;
+
+        // Arrange
+        let links: Vec<(String, String)> = (0..8)
+            .map(|i| {
+                (
+                    format!("http://localhost/{}/variables.json", i),
+                    format!("http://localhost/{}/geography.json", i),
+                )
+            })
+            .collect();
+        let api_paths: Vec<ApiPaths> = links
+            .iter()
+            .map(|(variables_link, geography_link)| {
+                api_path_with_links(variables_link, geography_link)
+            })
+            .collect();
+        let api_paths: Vec<&ApiPaths> = api_paths.iter().collect();
+        let concurrency = FetchConcurrency {
+            variables: 2,
+            geography: 5,
+        };
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight: Mutex<HashMap<FetchPhase, usize>> = Mutex::new(HashMap::new());
+
+        // Act
+        prefetch_with(&api_paths, concurrency, |phase, _| {
+            let (in_flight, max_in_flight) = (&in_flight, &max_in_flight);
+            async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                {
+                    let mut max_in_flight = max_in_flight.lock().unwrap();
+                    let max = max_in_flight.entry(phase).or_default();
+                    *max = (*max).max(current);
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            }
+        })
+        .await;
+
+        // Assert
+        let max_in_flight = max_in_flight.into_inner().unwrap();
+        assert_eq!(max_in_flight[&FetchPhase::Variables], 2);
+        assert_eq!(max_in_flight[&FetchPhase::Geography], 5);
+    }
+
+    /// A deadline in the past times out before fetching anything.
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database"]
+    async fn test_past_deadline() {
+        // Arrange
+        let server = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        let web_client = Client::new();
+        let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+        let variables_link = format!("{}/data/2020/variables.json", server.uri());
+        let geography_link = format!("{}/data/2020/geography.json", server.uri());
+        let api_path = api_path_with_links(&variables_link, &geography_link);
+        let conn = &mut test_connection();
+        let options = IngestOptions {
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        };
+
+        // Act
+        let result = insert_variables_and_geography_for_api_path(
+            conn,
+            &client,
+            &api_path,
+            "variables_name__attributes_hash__concept_hash__first_group_key",
+            &options,
+        )
+        .await;
+
+        // Assert
+        assert!(matches!(
+            result,
+            Err(InsertError::TimedOut {
+                phase: "fetching variables"
+            })
+        ));
+    }
+
+    /// With `checkpoint_variables`, a slow geography fetch still times out at the deadline,
+    /// after the variables were committed.
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database"]
+    async fn test_checkpoint_variables_deadline() {
+        // Arrange
+        let server = MockServer::start().await;
+        Mock::given(path("/data/2020/variables.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"variables": {"B01001_001E": {"label": "Estimate!!Total", "group": "B01001"}}}"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(path("/data/2020/geography.json"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"fips": [{"name": "us"}]}"#)
+                    .set_delay(Duration::from_secs(5)),
+            )
+            .mount(&server)
+            .await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        let web_client = Client::new();
+        let client = CachedClient::new(cache_dir.path().to_path_buf(), &web_client);
+        let variables_link = format!("{}/data/2020/variables.json", server.uri());
+        let geography_link = format!("{}/data/2020/geography.json", server.uri());
+        let mut api_path = api_path_with_links(&variables_link, &geography_link);
+        let conn = &mut test_connection();
+        api_path.id = insert_api_path(conn, &api_path);
+        let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+        let options = IngestOptions {
+            checkpoint_variables: true,
+            deadline: Some(Instant::now() + Duration::from_secs(1)),
+            ..Default::default()
+        };
+
+        // Act
+        let result = insert_variables_and_geography_for_api_path(
+            conn, &client, &api_path, constraint, &options,
+        )
+        .await;
+
+        // Assert
+        match result {
+            Err(InsertError::GeographyAfterCheckpoint { source, .. }) => assert!(matches!(
+                *source,
+                InsertError::TimedOut {
+                    phase: "fetching geography"
+                }
+            )),
+            other => panic!(
+                "Expected InsertError::GeographyAfterCheckpoint, got {:?}",
+                other
+            ),
+        }
+        assert_eq!(count_associations(conn, api_path.id), (1, 0));
+    }
+
+    fn geography_ids(conn: &mut PgConnection, api_path_id: i32) -> Vec<i32> {
+        use crate::schema::api_paths_geography_association::dsl::*;
+
+        api_paths_geography_association
+            .filter(api_paths_id.eq(api_path_id))
+            .select(geography_id)
+            .order(geography_id)
+            .load(conn)
+            .unwrap()
+    }
+
+    /// Re-ingesting unchanged variables skips them.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_reinsert_unchanged_variables() {
+        // Arrange
+        let conn = &mut test_connection();
+        let api_path_id = insert_api_path(conn, &api_path_with_links("v.json", "g.json"));
+        let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+        let items = [
+            variable("B01001_001E", "Estimate!!Total"),
+            variable("NAME", "Geographic Area Name"),
+        ];
+        let first = insert_variables_in_batches(
+            conn,
+            items.iter().map(Ok),
+            api_path_id,
+            constraint,
+            DEFAULT_BATCH_SIZE,
+        )
+        .unwrap();
+
+        // Act
+        let second = insert_variables_in_batches(
+            conn,
+            items.iter().map(Ok),
+            api_path_id,
+            constraint,
+            DEFAULT_BATCH_SIZE,
+        )
+        .unwrap();
+
+        // Assert
+        assert_eq!((first.variables_inserted, first.variables_skipped), (2, 0));
+        assert_eq!(
+            (
+                second.variables_inserted,
+                second.variables_updated,
+                second.variables_skipped
+            ),
+            (0, 0, 2)
+        );
+    }
+
+    /// A variable whose content changed replaces the API path's association with the outdated
+    /// variable.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_reinsert_changed_variables() {
+        use crate::schema::{api_paths_variables_association, variables};
+
+        // Arrange
+        let conn = &mut test_connection();
+        let api_path_id = insert_api_path(conn, &api_path_with_links("v.json", "g.json"));
+        let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+        let mut items = [
+            variable("B01001_001E", "Estimate!!Total"),
+            variable("NAME", "Geographic Area Name"),
+        ];
+        insert_variables_in_batches(
+            conn,
+            items.iter().map(Ok),
+            api_path_id,
+            constraint,
+            DEFAULT_BATCH_SIZE,
+        )
+        .unwrap();
+        items[0].concept = Some(Cow::from("SEX BY AGE (TOTAL)"));
+
+        // Act
+        let summary = insert_variables_in_batches(
+            conn,
+            items.iter().map(Ok),
+            api_path_id,
+            constraint,
+            DEFAULT_BATCH_SIZE,
+        )
+        .unwrap();
+
+        // Assert
+        assert_eq!(
+            (
+                summary.variables_inserted,
+                summary.variables_updated,
+                summary.variables_skipped
+            ),
+            (0, 1, 1)
+        );
+        let concepts: Vec<Option<String>> = variables::table
+            .inner_join(api_paths_variables_association::table)
+            .filter(api_paths_variables_association::api_paths_id.eq(api_path_id))
+            .order(variables::name)
+            .select(variables::concept)
+            .load(conn)
+            .unwrap();
+        assert_eq!(
+            concepts,
+            vec![
+                Some("SEX BY AGE (TOTAL)".to_string()),
+                Some("SEX BY AGE".to_string())
+            ]
+        );
+    }
+
+    /// The variables of a name that land in different batches are all kept associated, and
+    /// only the outdated one is replaced.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_reinsert_changed_variables_across_batches() {
+        use crate::schema::{api_paths_variables_association, variables};
+
+        // Arrange
+        let conn = &mut test_connection();
+        let api_path_id = insert_api_path(conn, &api_path_with_links("v.json", "g.json"));
+        let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+        let mut items = [
+            variable("B01001_001E", "Estimate!!Total"),
+            variable("B01001_001E", "Estimate!!Total"),
+        ];
+        items[1].group = Some(vec![Cow::from("B01001A")]);
+        insert_variables_in_batches(
+            conn,
+            items.iter().map(Ok),
+            api_path_id,
+            constraint,
+            DEFAULT_BATCH_SIZE,
+        )
+        .unwrap();
+        items[1].concept = Some(Cow::from("SEX BY AGE (WHITE ALONE)"));
+        items.reverse();
+
+        // Act
+        let summary =
+            insert_variables_in_batches(conn, items.iter().map(Ok), api_path_id, constraint, 1)
+                .unwrap();
+
+        // Assert
+        assert_eq!(
+            (summary.variables_updated, summary.variables_skipped),
+            (1, 1)
+        );
+        let concepts: Vec<Option<String>> = variables::table
+            .inner_join(api_paths_variables_association::table)
+            .filter(api_paths_variables_association::api_paths_id.eq(api_path_id))
+            .order(variables::_first_group)
+            .select(variables::concept)
+            .load(conn)
+            .unwrap();
+        assert_eq!(
+            concepts,
+            vec![
+                Some("SEX BY AGE".to_string()),
+                Some("SEX BY AGE (WHITE ALONE)".to_string())
+            ]
+        );
+    }
+
+    /// Re-ingesting identical geographies keeps their ids and doesn't duplicate associations.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_reinsert_geographies() {
+        // Arrange
+        let conn = &mut test_connection();
+        let mut api_path = api_path_with_links(
+            "http://api.census.gov/data/2020/acs/acs5/variables.json",
+            "http://api.census.gov/data/2020/acs/acs5/geography.json",
+        );
+        api_path.id = insert_api_path(conn, &api_path);
+        // `county` appears twice, as it sometimes does in geography.json.
+        let geography_json = r#"
+    {
+      "fips": [
+        {"name": "us", "geoLevelDisplay": "010"},
+        {"name": "county", "geoLevelDisplay": "050", "requires": ["state"]},
+        {"name": "county", "geoLevelDisplay": "050", "requires": ["state"]}
+      ]
+    }"#;
+        let collection: GeographyCollection =
+            serde_json::from_str(geography_json).expect("Error parsing JSON");
+        insert_geographies_in_batches(
+            conn,
+            &collection.fips,
+            api_path.id,
+            false,
+            DEFAULT_BATCH_SIZE,
+        )
+        .unwrap();
+        let first_ids = geography_ids(conn, api_path.id);
+
+        // Act
+        insert_geographies_in_batches(
+            conn,
+            &collection.fips,
+            api_path.id,
+            false,
+            DEFAULT_BATCH_SIZE,
+        )
+        .unwrap();
+
+        // Assert
+        assert_eq!(first_ids.len(), 2);
+        assert_eq!(geography_ids(conn, api_path.id), first_ids);
+    }
+
+    /// If inserting the new associations fails after the outdated ones were deleted, the
+    /// delete is rolled back, too.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_associate_geographies_is_atomic() {
+        // Arrange
+        let conn = &mut test_connection();
+        let mut api_path = api_path_with_links(
+            "http://api.census.gov/data/2020/acs/acs5/variables.json",
+            "http://api.census.gov/data/2020/acs/acs5/geography.json",
+        );
+        api_path.id = insert_api_path(conn, &api_path);
+        let geography_json = r#"
+    {
+      "fips": [
+        {"name": "us", "geoLevelDisplay": "010"},
+        {"name": "state", "geoLevelDisplay": "040"}
+      ]
+    }"#;
+        let collection: GeographyCollection =
+            serde_json::from_str(geography_json).expect("Error parsing JSON");
+        insert_geographies_in_batches(
+            conn,
+            &collection.fips,
+            api_path.id,
+            false,
+            DEFAULT_BATCH_SIZE,
+        )
+        .unwrap();
+        let original_ids = geography_ids(conn, api_path.id);
+        // Keep the first geography, so the second one's association is deleted, then fail on
+        // the foreign key of a geography that doesn't exist.
+        let missing_id = -1;
+
+        // Act
+        let result = associate_geographies(
+            conn,
+            api_path.id,
+            &[original_ids[0], missing_id],
+            DEFAULT_BATCH_SIZE,
+        );
+
+        // Assert
+        assert!(
+            matches!(
+                result,
+                Err(InsertError::Database(DieselError::DatabaseError(..)))
+            ),
+            "{:?}",
+            result
+        );
+        assert_eq!(geography_ids(conn, api_path.id), original_ids);
+    }
+
+    /// A geography listed twice is inserted once, even when the duplicates fall in different
+    /// batches, but geographies with different reference dates are all inserted.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_duplicate_geographies() {
+        // Arrange
+        let conn = &mut test_connection();
+        let api_path_id = insert_api_path(conn, &api_path_with_links("v.json", "g.json"));
+        let geography_json = r#"
+    {
+      "fips": [
+        {"name": "county", "geoLevelDisplay": "050", "referenceDate": "2020-01-01"},
+        {"name": "us", "geoLevelDisplay": "010"},
+        {"name": "county", "geoLevelDisplay": "050", "referenceDate": "2020-01-01"},
+        {"name": "county", "geoLevelDisplay": "050", "referenceDate": "2021-01-01"}
+      ]
+    }"#;
+        let collection: GeographyCollection =
+            serde_json::from_str(geography_json).expect("Error parsing JSON");
+
+        // Act
+        let summary =
+            insert_geographies_in_batches(conn, &collection.fips, api_path_id, false, 1).unwrap();
+
+        // Assert
+        assert_eq!(
+            (summary.geographies_inserted, summary.geographies_duplicated),
+            (3, 1)
+        );
+        assert_eq!(summary.warnings(), vec!["dropped 1 duplicate geographies"]);
+        assert_eq!(geography_ids(conn, api_path_id).len(), 3);
+    }
+
+    /// The precision of a reference date is stored alongside it.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_reference_date_precision_column() {
+        use crate::parse_geography::{DatePrecision, ReferenceDate};
+        use crate::schema::geography;
+
+        // Arrange
+        let conn = &mut test_connection();
+        let api_path_id = insert_api_path(conn, &api_path_with_links("v.json", "g.json"));
+        let geography_json = r#"
+    {
+      "fips": [
+        {"name": "us", "geoLevelDisplay": "010", "referenceDate": "2099"},
+        {"name": "state", "geoLevelDisplay": "040", "referenceDate": "2099-01-01"}
+      ]
+    }"#;
+        let collection: GeographyCollection =
+            serde_json::from_str(geography_json).expect("Error parsing JSON");
+
+        // Act
+        insert_geographies_in_batches(
+            conn,
+            &collection.fips,
+            api_path_id,
+            false,
+            DEFAULT_BATCH_SIZE,
+        )
+        .unwrap();
+
+        // Assert
+        let loaded: Vec<GeographyItem<'static>> = geography::table
+            .filter(geography::id.eq_any(geography_ids(conn, api_path_id)))
+            .order(geography::name.desc())
+            .select(GeographyItem::as_select())
+            .load(conn)
+            .unwrap();
+        let reference_dates: Vec<ReferenceDate> =
+            loaded.iter().map(GeographyItem::reference_date).collect();
+        let date = chrono::NaiveDate::from_ymd_opt(2099, 1, 1);
+        assert_eq!(
+            reference_dates,
+            vec![
+                ReferenceDate {
+                    date,
+                    precision: Some(DatePrecision::Year),
+                },
+                ReferenceDate {
+                    date,
+                    precision: Some(DatePrecision::Day),
+                },
+            ]
+        );
+    }
+
+    /// Re-ingesting a geography whose metadata changed upstream updates its shared row.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_reinsert_changed_geography() {
+        use crate::schema::geography;
+
+        // Arrange
+        let conn = &mut test_connection();
+        let api_path_id = insert_api_path(conn, &api_path_with_links("v.json", "g.json"));
+        let original_json = r#"
+    {
+      "fips": [
+        {"name": "county", "geoLevelDisplay": "050", "requires": ["state"], "limit": "10"}
+      ]
+    }"#;
+        let changed_json = r#"
+    {
+      "fips": [
+        {
+          "name": "county",
+          "geoLevelDisplay": "050",
+          "requires": ["state", "county subdivision"],
+          "wildcard": ["state"],
+          "limit": "20"
+        }
+      ]
+    }"#;
+        let original: GeographyCollection =
+            serde_json::from_str(original_json).expect("Error parsing JSON");
+        let changed: GeographyCollection =
+            serde_json::from_str(changed_json).expect("Error parsing JSON");
+        insert_geographies_in_batches(conn, &original.fips, api_path_id, false, DEFAULT_BATCH_SIZE)
+            .unwrap();
+        let original_ids = geography_ids(conn, api_path_id);
+
+        // Act
+        insert_geographies_in_batches(conn, &changed.fips, api_path_id, false, DEFAULT_BATCH_SIZE)
+            .unwrap();
+
+        // Assert
+        assert_eq!(geography_ids(conn, api_path_id), original_ids);
+        let loaded: GeographyItem<'static> = geography::table
+            .find(original_ids[0])
+            .select(GeographyItem::as_select())
+            .get_result(conn)
+            .unwrap();
+        assert_eq!(loaded.limit, Some(20));
+        assert_eq!(loaded.requires, changed.fips[0].requires);
+        assert_eq!(loaded.wildcard, changed.fips[0].wildcard);
+    }
+
+    /// Re-ingesting one API path only rewrites its own associations, never the geography rows
+    /// or associations of another API path sharing them.
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_reinsert_shared_geographies() {
+        use crate::schema::geography;
+
+        // Arrange
+        let conn = &mut test_connection();
+        let mut acs5 = api_path_with_links(
+            "http://api.census.gov/data/2020/acs/acs5/variables.json",
+            "http://api.census.gov/data/2020/acs/acs5/geography.json",
+        );
+        acs5.id = insert_api_path(conn, &acs5);
+        let mut acs1 = api_path_with_links(
+            "http://api.census.gov/data/2020/acs/acs1/variables.json",
+            "http://api.census.gov/data/2020/acs/acs1/geography.json",
+        );
+        acs1.c_dataset[1] = Some(Cow::from("acs1"));
+        acs1.id = insert_api_path(conn, &acs1);
+        let all_json = r#"
+    {
+      "fips": [
+        {"name": "us", "geoLevelDisplay": "010"},
+        {"name": "state", "geoLevelDisplay": "040"},
+        {"name": "county", "geoLevelDisplay": "050", "requires": ["state"]}
+      ]
+    }"#;
+        let fewer_json = r#"
+    {
+      "fips": [
+        {"name": "us", "geoLevelDisplay": "010"},
+        {"name": "state", "geoLevelDisplay": "040"}
+      ]
+    }"#;
+        let all: GeographyCollection = serde_json::from_str(all_json).expect("Error parsing JSON");
+        let fewer: GeographyCollection =
+            serde_json::from_str(fewer_json).expect("Error parsing JSON");
+        let first =
+            insert_geographies_in_batches(conn, &all.fips, acs5.id, false, DEFAULT_BATCH_SIZE)
+                .unwrap();
+        insert_geographies_in_batches(conn, &all.fips, acs1.id, false, DEFAULT_BATCH_SIZE).unwrap();
+        let shared_ids = geography_ids(conn, acs5.id);
+
+        // Act
+        let summary =
+            insert_geographies_in_batches(conn, &fewer.fips, acs1.id, false, DEFAULT_BATCH_SIZE)
+                .unwrap();
+
+        // Assert
+        assert_eq!(
+            (first.geographies_inserted, first.geographies_deleted),
+            (3, 0)
+        );
+        assert_eq!(
+            (summary.geographies_inserted, summary.geographies_deleted),
+            (0, 1)
+        );
+        assert_eq!(shared_ids.len(), 3);
+        assert_eq!(geography_ids(conn, acs5.id), shared_ids);
+        let acs1_ids = geography_ids(conn, acs1.id);
+        assert_eq!(acs1_ids.len(), 2);
+        assert!(acs1_ids.iter().all(|id| shared_ids.contains(id)));
+        let remaining_rows: i64 = geography::table
+            .filter(geography::id.eq_any(&shared_ids))
+            .count()
+            .get_result(conn)
+            .unwrap();
+        assert_eq!(remaining_rows, 3);
+    }
+
+    /// Return the number of rows inserted, updated or deleted in the geography tables by the
+    /// current transaction.
+    fn geography_writes(conn: &mut PgConnection) -> i64 {
+        diesel::select(sql::<diesel::sql_types::BigInt>(
+            "(SELECT COALESCE(SUM(n_tup_ins + n_tup_upd + n_tup_del), 0)::BIGINT \
+             FROM pg_stat_xact_user_tables \
+             WHERE relname IN ('geography', 'api_paths_geography_association'))",
+        ))
+        .get_result(conn)
+        .unwrap()
+    }
+
+    #[rstest]
+    #[case::skip_unchanged(true, false)]
+    #[case::rewrite(false, true)]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_skip_unchanged_geographies(
+        #[case] skip_unchanged: bool,
+        #[case] expected_written: bool,
+    ) {
+        // Arrange
+        let conn = &mut test_connection();
+        let mut api_path = api_path_with_links(
+            "http://api.census.gov/data/2020/acs/acs5/variables.json",
+            "http://api.census.gov/data/2020/acs/acs5/geography.json",
+        );
+        api_path.id = insert_api_path(conn, &api_path);
+        let geography_json = r#"
+    {
+      "fips": [
+        {"name": "us", "geoLevelDisplay": "010"},
+        {"name": "state", "geoLevelDisplay": "040"}
+      ]
+    }"#;
+        let collection: GeographyCollection =
+            serde_json::from_str(geography_json).expect("Error parsing JSON");
+        insert_geographies_in_batches(
+            conn,
+            &collection.fips,
+            api_path.id,
+            skip_unchanged,
+            DEFAULT_BATCH_SIZE,
+        )
+        .unwrap();
+        let writes_before = geography_writes(conn);
+
+        // Act
+        let summary = insert_geographies_in_batches(
+            conn,
+            &collection.fips,
+            api_path.id,
+            skip_unchanged,
+            DEFAULT_BATCH_SIZE,
+        )
+        .unwrap();
+
+        // Assert
+        assert_eq!(summary.geography_unchanged, !expected_written);
+        assert_eq!(geography_writes(conn) > writes_before, expected_written);
+    }
+
+    #[rstest]
+    #[case::vintage(Some(2099))]
+    #[case::time_series(None)]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_upsert_api_paths(#[case] vintage: Option<i32>) {
+        use crate::schema::api_paths::dsl::*;
+
+        // Arrange
+        let conn = &mut test_connection();
+        let mut api_path = api_path_with_links(
+            "http://api.census.gov/data/test/upsert/variables.json",
+            "http://api.census.gov/data/test/upsert/geography.json",
+        );
+        api_path.c_vintage = vintage;
+        api_path.c_dataset = vec![Some(Cow::from("test")), Some(Cow::from("upsert"))];
+        let inserted = upsert_api_paths(conn, std::slice::from_ref(&api_path)).unwrap();
+        api_path.title = Cow::from("Renamed");
+        api_path.c_is_available = Some(true);
+
+        // Act
+        let inserted_again = upsert_api_paths(conn, &[api_path]).unwrap();
+
+        // Assert
+        assert_eq!((inserted, inserted_again), (1, 0));
+        let titles: Vec<(String, Option<bool>)> = api_paths
+            .filter(c_dataset.eq(vec!["test", "upsert"]))
+            .select((title, c_is_available))
+            .load(conn)
+            .unwrap();
+        assert_eq!(titles, vec![("Renamed".to_string(), Some(true))]);
+        assert_eq!(
+            get_unique_constraints(conn, "api_paths").unwrap(),
+            vec![API_PATHS_UNIQUE_KEY_CONSTRAINT]
+        );
+    }
+}
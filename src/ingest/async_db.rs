@@ -0,0 +1,371 @@
+//! `insert_variables_and_geography_for_api_path` on an `AsyncPgConnection`, so that inserting
+//! doesn't block the async runtime and the fetches and inserts of different API paths, each
+//! on its own connection, overlap.
+//!
+//! The queries are those of the parent module, run with `diesel_async::RunQueryDsl`.
+use super::{
+    associated_geographies_query, associated_variables_query, check_deadline, check_links,
+    delete_geography_associations_statement, delete_variable_associations_statement, fetch_before,
+    geographies_match, geography_associations, insert_geography_associations_statement,
+    insert_variable_associations_statement, unique_geographies, unique_variables,
+    upsert_geographies_statement, upsert_variable_values_statement, upsert_variables_statement,
+    variable_associations, variable_value_rows, GeographyKey, IngestOptions, InsertError,
+    InsertSummary, VariableContent, VariablesChanges, MAX_BATCH_SIZE,
+};
+use crate::cache::CachedBody;
+use crate::fetch_api_metadata::CachedClient;
+use crate::models::ApiPaths;
+use crate::parse_geography::{GeographyCollection, GeographyItem};
+use crate::parse_variables::{VariablesItem, VariablesStream};
+use diesel::result::Error as DieselError;
+use diesel_async::scoped_futures::{ScopedBoxFuture, ScopedFutureExt};
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use std::collections::HashSet;
+use url::Url;
+
+/// Like `insert_variables_and_geography_for_api_path`, but on an `AsyncPgConnection`. Every
+/// option applies.
+///
+/// # Arguments
+///
+/// * `conn` - async connection to the database. See `establish_async_database_connection`.
+/// * `client` - the client to use for fetching the API metadata (JSON)
+/// * `api_path_metadata` - the API paths whose variables and geography to insert
+/// * `variables_unique_key_constraint` - the unique key constraint for the variables table
+/// * `options` - see `IngestOptions`
+#[tracing::instrument(
+    skip_all,
+    fields(id = api_path_metadata.id, title = %api_path_metadata.title)
+)]
+pub async fn insert_variables_and_geography_for_api_path_async(
+    conn: &mut AsyncPgConnection,
+    client: &CachedClient<'_>,
+    api_path_metadata: &ApiPaths<'_>,
+    variables_unique_key_constraint: &str,
+    options: &IngestOptions,
+) -> Result<InsertSummary, InsertError> {
+    tracing::info!(link = %api_path_metadata.c_variables_link, "ingesting API path");
+    let result = insert_api_path(
+        conn,
+        client,
+        api_path_metadata,
+        variables_unique_key_constraint,
+        options,
+    )
+    .await;
+    match &result {
+        Ok(summary) => tracing::info!(
+            variables_inserted = summary.variables_inserted,
+            variables_updated = summary.variables_updated,
+            variables_skipped = summary.variables_skipped,
+            geographies_inserted = summary.geographies_inserted,
+            geographies_deleted = summary.geographies_deleted,
+            "ingested API path"
+        ),
+        Err(e) => tracing::warn!(error = %e, "failed to ingest API path"),
+    }
+    result
+}
+
+/// `insert_variables_and_geography_for_api_path_async`, without logging.
+async fn insert_api_path(
+    conn: &mut AsyncPgConnection,
+    client: &CachedClient<'_>,
+    api_path_metadata: &ApiPaths<'_>,
+    variables_unique_key_constraint: &str,
+    options: &IngestOptions,
+) -> Result<InsertSummary, InsertError> {
+    let batch_size = options.checked_batch_size()?;
+    if !check_links(api_path_metadata, options.identical_links)? {
+        return Ok(InsertSummary::default());
+    }
+
+    let variables_url = Url::parse(api_path_metadata.c_variables_link.as_ref())?;
+    let variables_response = fetch_before(
+        client.fetch_reader(&variables_url),
+        options.deadline,
+        "fetching variables",
+    )
+    .await?;
+    let api_path_id = api_path_metadata.id;
+
+    if options.checkpoint_variables {
+        check_deadline(options.deadline, "inserting variables")?;
+        let summary = transaction(conn, options.dry_run, |conn| {
+            async move {
+                insert_variables_in_batches(
+                    conn,
+                    VariablesStream::new(variables_response),
+                    api_path_id,
+                    variables_unique_key_constraint,
+                    batch_size,
+                )
+                .await
+            }
+            .scope_boxed()
+        })
+        .await?;
+        let geography_summary = insert_geography(conn, client, api_path_metadata, options)
+            .await
+            .map_err(|e| InsertError::GeographyAfterCheckpoint {
+                title: api_path_metadata.title.to_string(),
+                source: Box::new(e),
+            })?;
+        return Ok(summary.with_geography(geography_summary));
+    }
+
+    let geography_url = Url::parse(api_path_metadata.c_geography_link.as_ref())?;
+    let geography_response = fetch_before(
+        client.fetch(&geography_url),
+        options.deadline,
+        "fetching geography",
+    )
+    .await?;
+    check_deadline(options.deadline, "parsing geography")?;
+    let parsed_geography_response: GeographyCollection = serde_json::from_str(&geography_response)?;
+    let geographies = &parsed_geography_response.fips;
+
+    // A single transaction per endpoint, like the synchronous version.
+    check_deadline(options.deadline, "inserting")?;
+    let summary = transaction::<_, InsertError, _>(conn, options.dry_run, |conn| {
+        async move {
+            let summary = insert_variables_in_batches(
+                conn,
+                VariablesStream::new(variables_response),
+                api_path_id,
+                variables_unique_key_constraint,
+                batch_size,
+            )
+            .await?;
+            let geography_summary = insert_geographies_in_batches(
+                conn,
+                geographies,
+                api_path_id,
+                options.skip_unchanged_geography,
+                batch_size,
+            )
+            .await?;
+            Ok(summary.with_geography(geography_summary))
+        }
+        .scope_boxed()
+    })
+    .await?;
+    Ok(summary)
+}
+
+/// Like `insert_geography_for_api_path`, but on an `AsyncPgConnection`.
+async fn insert_geography(
+    conn: &mut AsyncPgConnection,
+    client: &CachedClient<'_>,
+    api_path_metadata: &ApiPaths<'_>,
+    options: &IngestOptions,
+) -> Result<InsertSummary, InsertError> {
+    let batch_size = options.checked_batch_size()?;
+    let geography_url = Url::parse(api_path_metadata.c_geography_link.as_ref())?;
+    let geography_response = fetch_before(
+        client.fetch(&geography_url),
+        options.deadline,
+        "fetching geography",
+    )
+    .await?;
+    check_deadline(options.deadline, "parsing geography")?;
+    let parsed_geography_response: GeographyCollection = serde_json::from_str(&geography_response)?;
+    let geographies = &parsed_geography_response.fips;
+    let api_path_id = api_path_metadata.id;
+
+    check_deadline(options.deadline, "inserting geography")?;
+    transaction(conn, options.dry_run, |conn| {
+        async move {
+            insert_geographies_in_batches(
+                conn,
+                geographies,
+                api_path_id,
+                options.skip_unchanged_geography,
+                batch_size,
+            )
+            .await
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// The outcome of the transaction of a dry run, which is always rolled back.
+enum DryRun<T, E> {
+    Output(T),
+    Error(E),
+}
+
+impl<T, E: From<DieselError>> From<DieselError> for DryRun<T, E> {
+    fn from(error: DieselError) -> Self {
+        DryRun::Error(error.into())
+    }
+}
+
+/// Like the parent module's `transaction`: run `f` in a transaction, and with `dry_run`, roll
+/// the transaction back even if `f` succeeds and return what `f` returned.
+async fn transaction<'a, T, E, F>(conn: &mut AsyncPgConnection, dry_run: bool, f: F) -> Result<T, E>
+where
+    F: for<'r> FnOnce(&'r mut AsyncPgConnection) -> ScopedBoxFuture<'a, 'r, Result<T, E>>
+        + Send
+        + 'a,
+    T: Send + 'a,
+    E: From<DieselError> + Send + 'a,
+{
+    if !dry_run {
+        return conn.transaction(f).await;
+    }
+    let result = conn
+        .transaction::<(), DryRun<T, E>, _>(|conn| {
+            async move {
+                match f(conn).await {
+                    Ok(output) => Err(DryRun::Output(output)),
+                    Err(e) => Err(DryRun::Error(e)),
+                }
+            }
+            .scope_boxed()
+        })
+        .await;
+    match result {
+        Err(DryRun::Output(output)) => Ok(output),
+        Err(DryRun::Error(e)) => Err(e),
+        Ok(()) => unreachable!("the dry run transaction always fails"),
+    }
+}
+
+/// Like the parent module's `insert_variables_in_batches`.
+async fn insert_variables_in_batches(
+    conn: &mut AsyncPgConnection,
+    items: VariablesStream<CachedBody>,
+    api_path_id: i32,
+    unique_key_constraint: &str,
+    batch_size: usize,
+) -> Result<InsertSummary, InsertError> {
+    let mut diff = VariablesChanges::new(associated_variables(conn, api_path_id).await?);
+    let mut changed = Vec::new();
+    for item in items {
+        let item = item?;
+        if diff.is_changed(&item) {
+            changed.push(item);
+            if changed.len() == batch_size {
+                insert_variables(&changed, conn, api_path_id, unique_key_constraint).await?;
+                changed.clear();
+            }
+        }
+    }
+    if !changed.is_empty() {
+        insert_variables(&changed, conn, api_path_id, unique_key_constraint).await?;
+    }
+
+    let outdated_ids = diff.outdated_ids();
+    if !outdated_ids.is_empty() {
+        delete_variable_associations_statement(api_path_id, outdated_ids)
+            .execute(conn)
+            .await?;
+    }
+    Ok(diff.summary)
+}
+
+/// Like the parent module's `associated_variables`.
+async fn associated_variables(
+    conn: &mut AsyncPgConnection,
+    api_path_id: i32,
+) -> Result<Vec<(i32, VariableContent)>, DieselError> {
+    associated_variables_query(api_path_id).load(conn).await
+}
+
+/// Like the parent module's `insert_variables`.
+async fn insert_variables(
+    items: &[VariablesItem<'static>],
+    conn: &mut AsyncPgConnection,
+    api_path_id: i32,
+    unique_key_constraint: &str,
+) -> Result<(), InsertError> {
+    let unique_items = unique_variables(items);
+    let upserted: Vec<(i32, VariableContent)> =
+        upsert_variables_statement(unique_items.clone(), unique_key_constraint)
+            .load(conn)
+            .await?;
+    let variable_ids = variable_associations(api_path_id, &upserted);
+    insert_variable_associations_statement(&variable_ids)
+        .execute(conn)
+        .await?;
+
+    let rows = variable_value_rows(&unique_items, &upserted);
+    for chunk in rows.chunks(MAX_BATCH_SIZE) {
+        upsert_variable_values_statement(chunk)
+            .execute(conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Like the parent module's `insert_geographies_in_batches`.
+async fn insert_geographies_in_batches(
+    conn: &mut AsyncPgConnection,
+    items: &[GeographyItem<'_>],
+    api_path_id: i32,
+    skip_unchanged: bool,
+    batch_size: usize,
+) -> Result<InsertSummary, InsertError> {
+    let unique_items = unique_geographies(items);
+    let geographies_duplicated = items.len() - unique_items.len();
+
+    if skip_unchanged {
+        let existing: HashSet<GeographyKey> = associated_geographies_query(api_path_id)
+            .load(conn)
+            .await?
+            .into_iter()
+            .collect();
+        if geographies_match(&existing, items) {
+            return Ok(InsertSummary {
+                geographies_duplicated,
+                geography_unchanged: true,
+                ..InsertSummary::default()
+            });
+        }
+    }
+
+    let mut geography_ids: Vec<i32> = Vec::with_capacity(unique_items.len());
+    for chunk in unique_items.chunks(batch_size) {
+        let ids: Vec<i32> = upsert_geographies_statement(chunk.to_vec())
+            .get_results(conn)
+            .await?;
+        geography_ids.extend(ids);
+    }
+
+    let (geographies_inserted, geographies_deleted) =
+        associate_geographies(conn, api_path_id, &geography_ids, batch_size).await?;
+    Ok(InsertSummary {
+        geographies_inserted,
+        geographies_deleted,
+        geographies_duplicated,
+        ..InsertSummary::default()
+    })
+}
+
+/// Like the parent module's `associate_geographies`.
+async fn associate_geographies(
+    conn: &mut AsyncPgConnection,
+    api_path_id: i32,
+    geography_ids: &[i32],
+    batch_size: usize,
+) -> Result<(usize, usize), InsertError> {
+    conn.transaction(|conn| {
+        async move {
+            let deleted = delete_geography_associations_statement(api_path_id, geography_ids)
+                .execute(conn)
+                .await?;
+            let associations = geography_associations(api_path_id, geography_ids);
+            let mut inserted = 0;
+            for chunk in associations.chunks(batch_size) {
+                inserted += insert_geography_associations_statement(chunk)
+                    .execute(conn)
+                    .await?;
+            }
+            Ok((inserted, deleted))
+        }
+        .scope_boxed()
+    })
+    .await
+}
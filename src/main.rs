@@ -1,39 +1,125 @@
-use diesel::dsl::sql;
+use clap::Parser;
+use diesel::connection::{AnsiTransactionManager, TransactionManager};
 use diesel::prelude::*;
-use diesel::sql_types::Bool;
 use reqwest::Client;
-use std::path::Path;
-use std::str::FromStr;
-use url::Url;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tracing_subscriber::EnvFilter;
 use us_census::constraints::get_unique_constraints;
 use us_census::fetch_api_metadata::CachedClient;
-use us_census::models::{ApiPaths, UsCensusApisResponse};
-use us_census::{establish_database_connection, insert_variables_and_geography_for_api_path};
+use us_census::models::UsCensusApisResponse;
+use us_census::query::{filter_api_paths, pending_api_paths};
+use us_census::{
+    establish_database_connection, ingest_api_paths, sync_api_paths, upsert_api_paths,
+    FetchConcurrency, IdenticalLinks, IngestOptions,
+};
+
+/// Download the metadata of the US Census API and insert the variables and geography of the
+/// matching API paths into the database.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// A local snapshot of the catalog to read instead of https://api.census.gov/data.json,
+    /// e.g. data.json.gz. Gzipped snapshots are decompressed transparently.
+    catalog: Option<PathBuf>,
+
+    /// Only ingest the datasets matching this case-insensitive glob, e.g. "acs/acs5" or
+    /// "acs/*". `*` matches any characters, and `?` a single one.
+    #[arg(long, default_value = "acs/acs?")]
+    dataset: String,
+
+    /// Only ingest this vintage, e.g. 2020. By default, every vintage is ingested.
+    #[arg(long)]
+    vintage: Option<i32>,
+
+    /// Where to cache the fetched metadata.
+    #[arg(long, default_value = ".")]
+    cache_dir: PathBuf,
+
+    /// The database URL. By default, it's read from DATABASE_URL, which may be set in
+    /// .local.env.
+    #[arg(long)]
+    database_url: Option<String>,
+
+    /// Fetch, parse and insert everything, but roll back every change to the database. The
+    /// API paths of the catalog are rolled back, too, so only those already in the database
+    /// are ingested.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Also ingest the API paths that a previous run already ingested. By default, only the
+    /// pending ones are, so that a run that was interrupted resumes where it stopped.
+    #[arg(long)]
+    reingest: bool,
+
+    /// Skip the API paths that already have variables and geography in the database, instead
+    /// of fetching and upserting them again.
+    #[arg(long)]
+    only_missing: bool,
+}
+
+/// Translate a glob into a `LIKE` pattern for `filter_api_paths`.
+fn glob_to_like(glob: &str) -> String {
+    glob.chars()
+        .map(|c| match c {
+            '*' => "%".to_string(),
+            '?' => "_".to_string(),
+            '%' | '_' | '\\' => format!("\\{}", c),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Upsert the API paths of the catalog at `catalog_path`, or else of the Census API's catalog.
+async fn sync_catalog(
+    conn: &mut PgConnection,
+    client: &CachedClient<'_>,
+    catalog_path: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match catalog_path {
+        Some(catalog_path) => {
+            let us_census_apis = UsCensusApisResponse::from_path(catalog_path)?;
+            let inserted = upsert_api_paths(conn, &us_census_apis.dataset)?;
+            tracing::info!(inserted, "inserted the new API paths of the catalog");
+        }
+        None => {
+            sync_api_paths(conn, client).await?;
+        }
+    }
+    Ok(())
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    use us_census::schema::api_paths::dsl::api_paths as dsl_api_paths;
+    let args = Args::parse();
+    // Log to stderr, at the levels set by RUST_LOG, e.g. `RUST_LOG=us_census=debug`. Warnings
+    // and errors are logged by default.
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(tracing::Level::WARN.into())
+                .from_env_lossy(),
+        )
+        .with_writer(std::io::stderr)
+        .init();
 
-    let conn = &mut establish_database_connection(None, None)?;
+    let conn = &mut establish_database_connection(args.database_url, None)?;
 
     let web_client = Client::new();
-    let base_cache_dir = Path::new(".").canonicalize()?;
+    let base_cache_dir = args.cache_dir.canonicalize()?;
 
     let client_with_cache = CachedClient::new(base_cache_dir.to_path_buf(), &web_client);
 
-    // Assume that if there's one API path, we've already added all of them to the database.
-    let one_api_path = dsl_api_paths
-        .limit(1)
-        .select(ApiPaths::as_select())
-        .load(conn)?;
-    if one_api_path.len() == 0 {
-        let api_paths_url = Url::from_str("https://api.census.gov/data.json")?;
-        let response_text = client_with_cache.fetch(&api_paths_url).await?;
-        let us_census_apis: UsCensusApisResponse = serde_json::from_str(&response_text)?;
-        diesel::insert_into(dsl_api_paths)
-            .values(&us_census_apis.dataset)
-            .execute(conn)?;
+    // Insert the API paths published since the last run. An optional argument points to a
+    // local catalog snapshot, e.g. `data.json.gz`.
+    if args.dry_run {
+        AnsiTransactionManager::begin_transaction(conn)?;
+    }
+    let synced = sync_catalog(conn, &client_with_cache, args.catalog.as_deref()).await;
+    if args.dry_run {
+        AnsiTransactionManager::rollback_transaction(conn)?;
     }
+    synced?;
 
     let variables_unique_key_constraints = get_unique_constraints(conn, "variables")?;
     if variables_unique_key_constraints.len() != 1 {
@@ -44,21 +130,49 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     // Insert ACS survey variables and geographies into the database.
-    let variables_url_regex = "http://api.census.gov/data/\\d\\d\\d\\d/acs/acs\\d/variables.json";
-    let to_insert = dsl_api_paths
-        .filter(sql::<Bool>(
-            format!("c_variables_link ~ '{}'", variables_url_regex).as_str(),
-        ))
-        .load::<ApiPaths>(conn)?;
-    for metadata in to_insert {
-        insert_variables_and_geography_for_api_path(
-            conn,
-            &client_with_cache,
-            &metadata,
-            &variables_unique_key_constraints[0],
-        )
-        .await
-        .expect(format!("Error inserting variables: {}", metadata.c_variables_link).as_str());
+    let options = IngestOptions {
+        identical_links: IdenticalLinks::Skip,
+        max_failures: Some(10),
+        dry_run: args.dry_run,
+        only_missing: args.only_missing,
+        fetch_concurrency: FetchConcurrency {
+            variables: 2,
+            geography: 16,
+        },
+        ..Default::default()
+    };
+    let mut to_insert = filter_api_paths(conn, Some(&glob_to_like(&args.dataset)), args.vintage)?;
+    if !args.reingest {
+        let pending: HashSet<i32> = pending_api_paths(conn)?
+            .iter()
+            .map(|api_path| api_path.id)
+            .collect();
+        to_insert.retain(|api_path| pending.contains(&api_path.id));
+    }
+    let report = ingest_api_paths(
+        conn,
+        &client_with_cache,
+        &to_insert,
+        &variables_unique_key_constraints[0],
+        &options,
+    )
+    .await?;
+    for (link, summary) in &report.ingested {
+        println!("{}: {}", link, summary);
+        for warning in summary.warnings() {
+            eprintln!("Warning: {}: {}", link, warning);
+        }
+    }
+    for (link, e) in &report.failed {
+        eprintln!("Error inserting variables: {}: {}", link, e);
     }
+    let stats = client_with_cache.stats();
+    tracing::info!(
+        hits = stats.hits,
+        misses = stats.misses,
+        hit_ratio = stats.hit_ratio(),
+        bytes_fetched = stats.bytes_fetched,
+        "cache statistics"
+    );
     Ok(())
 }
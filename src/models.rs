@@ -1,18 +1,40 @@
-use crate::schema::api_paths;
-use crate::schema::api_paths_geography_association;
-use crate::schema::api_paths_variables_association;
+use crate::parse_utils::{JsonReader, StreamState};
+#[cfg(feature = "db")]
+use crate::parse_variables::CodeLabels;
+#[cfg(feature = "db")]
+use crate::schema::{
+    api_paths, api_paths_geography_association, api_paths_variables_association, variable_values,
+};
+#[cfg(feature = "db")]
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+#[cfg(feature = "db")]
+use diesel::pg::{Pg, PgValue};
+#[cfg(feature = "db")]
 use diesel::prelude::*;
+#[cfg(feature = "db")]
+use diesel::sql_types::{Array, Jsonb, Nullable, Text};
+#[cfg(feature = "gzip")]
+use flate2::read::GzDecoder;
 use serde::Deserialize;
 use std::borrow::Cow;
+#[cfg(feature = "db")]
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
 
 /// The metadata of each US Census API endpoint, as provided in each element of
 /// https://api.census.gov/data.json
-#[derive(Deserialize, Queryable, Identifiable, Selectable, Debug, PartialEq, Insertable)]
-#[diesel(table_name = api_paths)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Deserialize, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "db",
+    derive(Queryable, Identifiable, Selectable, Insertable),
+    diesel(table_name = api_paths),
+    diesel(check_for_backend(diesel::pg::Pg))
+)]
 pub struct ApiPaths<'a> {
     #[serde(skip, default)]
-    #[diesel(skip_insertion)]
+    #[cfg_attr(feature = "db", diesel(skip_insertion))]
     pub id: i32,
     pub c_vintage: Option<i32>,
     pub c_dataset: Vec<Option<Cow<'a, str>>>,
@@ -22,6 +44,27 @@ pub struct ApiPaths<'a> {
     pub c_variables_link: Cow<'a, str>,
     pub title: Cow<'a, str>,
     pub description: Cow<'a, str>,
+    /// Whether the dataset holds aggregate statistics rather than microdata.
+    #[serde(rename = "c_isAggregate")]
+    pub c_is_aggregate: Option<bool>,
+    /// Whether the dataset can be queried, rather than only being announced.
+    #[serde(rename = "c_isAvailable")]
+    pub c_is_available: Option<bool>,
+    /// The area covered by the dataset, e.g. "US".
+    pub spatial: Option<Cow<'a, str>>,
+    /// The period covered by the dataset, e.g. "2019/2019".
+    pub temporal: Option<Cow<'a, str>>,
+    /// When the dataset was last modified, as published, e.g. "2020-04-03 00:00:00.0".
+    pub modified: Option<Cow<'a, str>>,
+}
+
+impl ApiPaths<'_> {
+    /// Return whether the dataset is worth ingesting: only a dataset that the catalog marks
+    /// as unavailable isn't, because its variables.json likely doesn't exist. A catalog that
+    /// doesn't say is attempted.
+    pub fn is_available(&self) -> bool {
+        self.c_is_available != Some(false)
+    }
 }
 
 /// This is the top-level item at https://api.census.gov/data.json.
@@ -30,25 +73,526 @@ pub struct UsCensusApisResponse<'a> {
     pub dataset: Vec<ApiPaths<'a>>,
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum CatalogError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("JSON parsing error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// The first two bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Return a reader of the decompressed contents of the gzipped `reader`.
+#[cfg(feature = "gzip")]
+fn gzip_decoder<R: Read>(reader: R) -> io::Result<GzDecoder<R>> {
+    Ok(GzDecoder::new(reader))
+}
+
+/// Return an error, since decompressing requires the `gzip` feature.
+#[cfg(not(feature = "gzip"))]
+fn gzip_decoder<R: Read>(_reader: R) -> io::Result<R> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reading a gzipped catalog requires the `gzip` feature",
+    ))
+}
+
+impl UsCensusApisResponse<'static> {
+    /// Parse a catalog from its bytes, decompressing them first if they're gzipped.
+    ///
+    /// Gzip is detected from the content rather than a file name or `Content-Encoding`
+    /// header, so this accepts both a downloaded `data.json` and a `data.json.gz` snapshot.
+    /// Without the `gzip` feature, a gzipped catalog is a `CatalogError::Io`.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, CatalogError> {
+        if bytes.starts_with(&GZIP_MAGIC) {
+            Ok(serde_json::from_reader(gzip_decoder(bytes)?)?)
+        } else {
+            Ok(serde_json::from_slice(bytes)?)
+        }
+    }
+
+    /// Read and parse a catalog file, e.g. `data.json` or `data.json.gz`. See `from_slice`.
+    pub fn from_path(path: &Path) -> Result<Self, CatalogError> {
+        Self::from_slice(&std::fs::read(path)?)
+    }
+}
+
+/// An iterator over the entries of a catalog's `dataset` array, parsed one at a time from a
+/// reader. Unlike `UsCensusApisResponse`, it never holds more than one entry in memory.
+///
+/// Top-level keys before `dataset` are skipped; whatever follows the array is not read.
+/// Iteration stops after the first error.
+pub struct CatalogStream<R> {
+    reader: JsonReader<R>,
+    state: StreamState,
+}
+
+impl<R: BufRead> CatalogStream<R> {
+    pub fn new(reader: R) -> Self {
+        CatalogStream {
+            reader: JsonReader::new(reader),
+            state: StreamState::Start,
+        }
+    }
+
+    /// Parse the next entry, or return `None` at the end of the `dataset` array.
+    fn next_entry(&mut self) -> Result<Option<ApiPaths<'static>>, CatalogError> {
+        if self.state == StreamState::Start {
+            self.reader.seek_key("dataset", b'[')?;
+            self.state = StreamState::FirstEntry;
+        }
+        self.reader.skip_whitespace()?;
+        if self.reader.peek_byte()? == Some(b']') {
+            return Ok(None);
+        }
+        if self.state == StreamState::NextEntry {
+            self.reader.expect_byte(b',')?;
+        }
+        let entry = self.reader.parse_next()?;
+        self.state = StreamState::NextEntry;
+        Ok(Some(entry))
+    }
+}
+
+impl CatalogStream<Box<dyn BufRead>> {
+    /// Stream a catalog file, e.g. `data.json` or `data.json.gz`. Like
+    /// `UsCensusApisResponse::from_slice`, gzip is detected from the content.
+    pub fn from_path(path: &Path) -> Result<Self, CatalogError> {
+        let mut reader = BufReader::new(std::fs::File::open(path)?);
+        let reader: Box<dyn BufRead> = if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            Box::new(BufReader::new(gzip_decoder(reader)?))
+        } else {
+            Box::new(reader)
+        };
+        Ok(Self::new(reader))
+    }
+}
+
+impl<R: BufRead> Iterator for CatalogStream<R> {
+    type Item = Result<ApiPaths<'static>, CatalogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state == StreamState::Done {
+            return None;
+        }
+        let entry = self.next_entry().transpose();
+        if !matches!(entry, Some(Ok(_))) {
+            self.state = StreamState::Done;
+        }
+        entry
+    }
+}
+
+/// Return the `c_variables_link`s that appear in more than one entry of the catalog, in the
+/// order they first appear.
+///
+/// Ingesting several API paths with the same variables link associates the same variables
+/// with each of them, so the catalog should be reconciled first.
+pub fn catalog_duplicate_links<'c>(catalog: &'c [ApiPaths]) -> Vec<&'c str> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for api_path in catalog {
+        *counts
+            .entry(api_path.c_variables_link.as_ref())
+            .or_default() += 1;
+    }
+    let mut duplicates = Vec::new();
+    for api_path in catalog {
+        let link = api_path.c_variables_link.as_ref();
+        if let Some(count) = counts.remove(link) {
+            if count > 1 {
+                duplicates.push(link);
+            }
+        }
+    }
+    duplicates
+}
+
 /// Association table that enables a many-to-many relationship between
 /// the `api_paths` and `variables` tables.
-#[derive(Deserialize, Queryable, Identifiable, Selectable, Debug, PartialEq, Insertable)]
-#[diesel(table_name = api_paths_variables_association)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Deserialize, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "db",
+    derive(Queryable, Identifiable, Selectable, Insertable),
+    diesel(table_name = api_paths_variables_association),
+    diesel(check_for_backend(diesel::pg::Pg))
+)]
 pub struct ApiPathsVariablesAssociation {
-    #[diesel(skip_insertion)]
+    #[cfg_attr(feature = "db", diesel(skip_insertion))]
     pub id: i32,
     pub api_paths_id: i32,
     pub variables_id: i32,
 }
 /// Association table that enables a many-to-many relationship between
 /// the `api_paths` and `geography` tables.
-#[derive(Deserialize, Queryable, Identifiable, Selectable, Debug, PartialEq, Insertable)]
-#[diesel(table_name = api_paths_geography_association)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Deserialize, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "db",
+    derive(Queryable, Identifiable, Selectable, Insertable),
+    diesel(table_name = api_paths_geography_association),
+    diesel(check_for_backend(diesel::pg::Pg))
+)]
 pub struct ApiPathsGeographyAssociation {
-    #[diesel(skip_insertion)]
+    #[cfg_attr(feature = "db", diesel(skip_insertion))]
     pub id: i32,
     pub api_paths_id: i32,
     pub geography_id: i32,
 }
+
+/// A coded value of a variable and its label, from the `values` of variables.json. See
+/// `VariablesItem::values`.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(
+    feature = "db",
+    derive(Queryable, Identifiable, Selectable, Insertable),
+    diesel(table_name = variable_values),
+    diesel(check_for_backend(diesel::pg::Pg))
+)]
+pub struct VariableValue<'a> {
+    #[cfg_attr(feature = "db", diesel(skip_insertion))]
+    pub id: i32,
+    pub variables_id: i32,
+    pub code: Cow<'a, str>,
+    pub label: Cow<'a, str>,
+}
+
+/// A nullable `JSONB` object of strings read into an `Option<CodeLabels>`.
+///
+/// This reads `VariablesItem::values`, which is aggregated from the `variable_values` table.
+#[cfg(feature = "db")]
+#[derive(Debug, FromSqlRow)]
+pub struct NullableStringMap(Option<BTreeMap<String, String>>);
+
+#[cfg(feature = "db")]
+impl FromSql<Nullable<Jsonb>, Pg> for NullableStringMap {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        let value: serde_json::Value = FromSql::<Jsonb, Pg>::from_sql(bytes)?;
+        Ok(NullableStringMap(Some(serde_json::from_value(value)?)))
+    }
+
+    fn from_nullable_sql(bytes: Option<PgValue<'_>>) -> deserialize::Result<Self> {
+        match bytes {
+            Some(bytes) => Self::from_sql(bytes),
+            None => Ok(NullableStringMap(None)),
+        }
+    }
+}
+
+#[cfg(feature = "db")]
+impl<'a> From<NullableStringMap> for Option<CodeLabels<'a>> {
+    fn from(map: NullableStringMap) -> Self {
+        map.0.map(|map| {
+            map.into_iter()
+                .map(|(key, value)| (Cow::Owned(key), Cow::Owned(value)))
+                .collect()
+        })
+    }
+}
+
+/// A `TEXT[]` column read into a `Vec<Cow<str>>`.
+///
+/// Diesel maps `TEXT[]` to `Array<Nullable<Text>>`, whose elements can only be read into
+/// `Option`s. The arrays this crate writes never contain NULL elements, so this type lets
+/// `#[diesel(deserialize_as = TextArray)]` fields keep their `Vec<Cow<str>>` type. A NULL
+/// element is read as an empty string.
+#[cfg(feature = "db")]
+#[derive(Debug, FromSqlRow)]
+pub struct TextArray(Vec<String>);
+
+#[cfg(feature = "db")]
+impl FromSql<Array<Nullable<Text>>, Pg> for TextArray {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        let elements: Vec<Option<String>> = FromSql::<Array<Nullable<Text>>, Pg>::from_sql(bytes)?;
+        Ok(TextArray(
+            elements
+                .into_iter()
+                .map(Option::unwrap_or_default)
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(feature = "db")]
+impl<'a> From<TextArray> for Vec<Cow<'a, str>> {
+    fn from(array: TextArray) -> Self {
+        array.0.into_iter().map(Cow::Owned).collect()
+    }
+}
+
+/// A nullable `TEXT[]` column read into an `Option<Vec<Cow<str>>>`. See `TextArray`.
+#[cfg(feature = "db")]
+#[derive(Debug, FromSqlRow)]
+pub struct NullableTextArray(Option<TextArray>);
+
+#[cfg(feature = "db")]
+impl FromSql<Nullable<Array<Nullable<Text>>>, Pg> for NullableTextArray {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        FromSql::<Array<Nullable<Text>>, Pg>::from_sql(bytes)
+            .map(|array| NullableTextArray(Some(array)))
+    }
+
+    fn from_nullable_sql(bytes: Option<PgValue<'_>>) -> deserialize::Result<Self> {
+        match bytes {
+            Some(bytes) => Self::from_sql(bytes),
+            None => Ok(NullableTextArray(None)),
+        }
+    }
+}
+
+#[cfg(feature = "db")]
+impl<'a> From<NullableTextArray> for Option<Vec<Cow<'a, str>>> {
+    fn from(array: NullableTextArray) -> Self {
+        array.0.map(Vec::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn test_catalog_duplicate_links() {
+        let catalog_json = r#"
+    {
+      "dataset": [
+        {
+          "c_vintage": 2019,
+          "c_dataset": ["acs", "acs5"],
+          "c_geographyLink": "http://api.census.gov/data/2019/acs/acs5/geography.json",
+          "c_variablesLink": "http://api.census.gov/data/2019/acs/acs5/variables.json",
+          "title": "ACS 5-Year 2019",
+          "description": ""
+        },
+        {
+          "c_vintage": 2020,
+          "c_dataset": ["acs", "acs5"],
+          "c_geographyLink": "http://api.census.gov/data/2020/acs/acs5/geography.json",
+          "c_variablesLink": "http://api.census.gov/data/2020/acs/acs5/variables.json",
+          "title": "ACS 5-Year 2020",
+          "description": ""
+        },
+        {
+          "c_vintage": 2019,
+          "c_dataset": ["acs", "acs5"],
+          "c_geographyLink": "http://api.census.gov/data/2019/acs/acs5/geography.json",
+          "c_variablesLink": "http://api.census.gov/data/2019/acs/acs5/variables.json",
+          "title": "ACS 5-Year 2019 (duplicate)",
+          "description": ""
+        }
+      ]
+    }"#;
+        let catalog: UsCensusApisResponse =
+            serde_json::from_str(catalog_json).expect("Error parsing JSON");
+
+        let duplicates = catalog_duplicate_links(&catalog.dataset);
+
+        assert_eq!(
+            duplicates,
+            vec!["http://api.census.gov/data/2019/acs/acs5/variables.json"]
+        );
+    }
+
+    #[test]
+    fn test_catalog_without_duplicate_links() {
+        assert!(catalog_duplicate_links(&[]).is_empty());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzipped_catalog() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        // Arrange
+        let catalog_json = r#"
+    {
+      "dataset": [
+        {
+          "c_vintage": 2020,
+          "c_dataset": ["acs", "acs5"],
+          "c_geographyLink": "http://api.census.gov/data/2020/acs/acs5/geography.json",
+          "c_variablesLink": "http://api.census.gov/data/2020/acs/acs5/variables.json",
+          "title": "ACS 5-Year 2020",
+          "description": "American Community Survey"
+        }
+      ]
+    }"#;
+        let dir = tempfile::tempdir().unwrap();
+        let plain_path = dir.path().join("data.json");
+        std::fs::write(&plain_path, catalog_json).unwrap();
+        let gzipped_path = dir.path().join("data.json.gz");
+        let mut encoder = GzEncoder::new(
+            std::fs::File::create(&gzipped_path).unwrap(),
+            Compression::default(),
+        );
+        encoder.write_all(catalog_json.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        // Act
+        let plain = UsCensusApisResponse::from_path(&plain_path).unwrap();
+        let gzipped = UsCensusApisResponse::from_path(&gzipped_path).unwrap();
+
+        // Assert
+        assert_eq!(gzipped.dataset, plain.dataset);
+        assert_eq!(gzipped.dataset.len(), 1);
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn test_gzipped_catalog_unsupported() {
+        // Act
+        let result = UsCensusApisResponse::from_slice(&[0x1f, 0x8b, 0x08, 0x00]);
+
+        // Assert
+        assert!(matches!(
+            result,
+            Err(CatalogError::Io(e)) if e.kind() == io::ErrorKind::Unsupported
+        ));
+    }
+
+    #[test]
+    fn test_catalog_stream() {
+        // Arrange
+        let catalog_json = r#"
+    {
+      "@context": "https://project-open-data.cio.gov/v1.1/schema/catalog.jsonld",
+      "conformsTo": {"version": 1.1},
+      "count": 3,
+      "dataset": [
+        {
+          "c_vintage": 2019,
+          "c_dataset": ["acs", "acs5"],
+          "c_geographyLink": "http://api.census.gov/data/2019/acs/acs5/geography.json",
+          "c_variablesLink": "http://api.census.gov/data/2019/acs/acs5/variables.json",
+          "title": "ACS 5-Year 2019",
+          "description": ""
+        },
+        {
+          "c_vintage": 2020,
+          "c_dataset": ["acs", "acs5"],
+          "c_geographyLink": "http://api.census.gov/data/2020/acs/acs5/geography.json",
+          "c_variablesLink": "http://api.census.gov/data/2020/acs/acs5/variables.json",
+          "title": "ACS 5-Year 2020",
+          "description": ""
+        },
+        {
+          "c_dataset": ["timeseries", "eits"],
+          "c_geographyLink": "http://api.census.gov/data/timeseries/eits/geography.json",
+          "c_variablesLink": "http://api.census.gov/data/timeseries/eits/variables.json",
+          "title": "Economic Indicators",
+          "description": ""
+        }
+      ]
+    }"#;
+
+        // Act
+        let streamed: Vec<ApiPaths> = CatalogStream::new(catalog_json.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        // Assert
+        let titles: Vec<&str> = streamed.iter().map(|a| a.title.as_ref()).collect();
+        assert_eq!(
+            titles,
+            vec!["ACS 5-Year 2019", "ACS 5-Year 2020", "Economic Indicators"]
+        );
+        let parsed: UsCensusApisResponse = serde_json::from_str(catalog_json).unwrap();
+        assert_eq!(streamed, parsed.dataset);
+    }
+
+    #[test]
+    fn test_dataset_metadata() {
+        // Arrange
+        let catalog_json = r#"
+    {
+      "dataset": [
+        {
+          "c_vintage": 2019,
+          "c_dataset": ["acs", "acs5"],
+          "c_geographyLink": "http://api.census.gov/data/2019/acs/acs5/geography.json",
+          "c_variablesLink": "http://api.census.gov/data/2019/acs/acs5/variables.json",
+          "c_tagsLink": "http://api.census.gov/data/2019/acs/acs5/tags.json",
+          "c_examplesLink": "http://api.census.gov/data/2019/acs/acs5/examples.json",
+          "c_groupsLink": "http://api.census.gov/data/2019/acs/acs5/groups.json",
+          "c_sorts_url": "http://api.census.gov/data/2019/acs/acs5/sorts.json",
+          "c_documentationLink": "https://www.census.gov/developer/",
+          "c_isAggregate": true,
+          "c_isCube": false,
+          "c_isAvailable": true,
+          "@type": "dcat:Dataset",
+          "title": "ACS 5-Year Detailed Tables",
+          "accessLevel": "public",
+          "bureauCode": ["006:07"],
+          "description": "The American Community Survey (ACS) is an ongoing survey.",
+          "distribution": [
+            {
+              "@type": "dcat:Distribution",
+              "accessURL": "http://api.census.gov/data/2019/acs/acs5",
+              "description": "API endpoint",
+              "format": "API",
+              "mediaType": "application/json",
+              "title": "API endpoint"
+            }
+          ],
+          "contactPoint": {
+            "fn": "American Community Survey Office",
+            "hasEmail": "mailto:acso.users.support@census.gov"
+          },
+          "identifier": "https://api.census.gov/data/id/ACSDT5Y2019",
+          "keyword": [],
+          "license": "https://creativecommons.org/publicdomain/zero/1.0/",
+          "modified": "2020-04-03 00:00:00.0",
+          "programCode": ["006:004"],
+          "references": ["https://www.census.gov/developers/"],
+          "spatial": "US",
+          "temporal": "2019/2019",
+          "publisher": {
+            "@type": "org:Organization",
+            "name": "U.S. Census Bureau"
+          }
+        },
+        {
+          "c_dataset": ["timeseries", "eits"],
+          "c_geographyLink": "http://api.census.gov/data/timeseries/eits/geography.json",
+          "c_variablesLink": "http://api.census.gov/data/timeseries/eits/variables.json",
+          "title": "Economic Indicators",
+          "description": ""
+        }
+      ]
+    }"#;
+
+        // Act
+        let catalog: UsCensusApisResponse =
+            serde_json::from_str(catalog_json).expect("Error parsing JSON");
+
+        // Assert
+        let (acs5, eits) = (&catalog.dataset[0], &catalog.dataset[1]);
+        assert_eq!(acs5.c_is_aggregate, Some(true));
+        assert_eq!(acs5.c_is_available, Some(true));
+        assert_eq!(acs5.spatial.as_deref(), Some("US"));
+        assert_eq!(acs5.temporal.as_deref(), Some("2019/2019"));
+        assert_eq!(acs5.modified.as_deref(), Some("2020-04-03 00:00:00.0"));
+        assert_eq!(eits.c_is_aggregate, None);
+        assert_eq!(eits.c_is_available, None);
+        assert_eq!(eits.spatial, None);
+        assert_eq!(eits.temporal, None);
+        assert_eq!(eits.modified, None);
+    }
+
+    #[rstest]
+    #[case::empty(r#"{"dataset": []}"#, 0)]
+    #[case::missing_dataset(r#"{"@type": "dcat:Catalog"}"#, 1)]
+    #[case::truncated(r#"{"dataset": [{"c_vintage": 2020"#, 1)]
+    fn test_catalog_stream_stops(#[case] catalog_json: &str, #[case] expected_errors: usize) {
+        // Act
+        let entries: Vec<_> = CatalogStream::new(catalog_json.as_bytes()).collect();
+
+        // Assert
+        assert_eq!(entries.len(), expected_errors);
+        assert!(entries.iter().all(Result::is_err));
+    }
+}
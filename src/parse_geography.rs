@@ -1,69 +1,543 @@
+#[cfg(feature = "db")]
+use crate::models::NullableTextArray;
+use crate::parse_utils::{parse_limit, parse_optional_cow, CowStr};
+#[cfg(feature = "db")]
 use crate::schema::geography;
-use chrono::NaiveDate;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+#[cfg(feature = "db")]
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+#[cfg(feature = "db")]
+use diesel::expression::AsExpression;
+#[cfg(feature = "db")]
+use diesel::pg::{Pg, PgValue};
+#[cfg(feature = "db")]
 use diesel::prelude::*;
-use serde::{de, Deserialize, Deserializer};
+#[cfg(feature = "db")]
+use diesel::serialize::{self, Output, ToSql};
+#[cfg(feature = "db")]
+use diesel::sql_types::Text;
+use serde::ser::SerializeMap;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::fmt;
+#[cfg(feature = "db")]
+use std::io::Write;
 
-#[derive(Deserialize, Insertable, Queryable, Selectable, Identifiable, Debug, PartialEq)]
-#[diesel(table_name = geography)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Deserialize, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "db",
+    derive(Insertable, Queryable, Selectable, Identifiable),
+    diesel(table_name = geography),
+    diesel(check_for_backend(diesel::pg::Pg))
+)]
 pub struct GeographyItem<'a> {
     // Primary key in the database. This field is not in geography.json.
     #[serde(skip, default)]
-    #[diesel(skip_insertion)]
+    #[cfg_attr(feature = "db", diesel(skip_insertion))]
     pub id: i32,
     #[serde(borrow)]
     pub name: Cow<'a, str>,
-    #[serde(borrow, default, rename = "geoLevelDisplay")]
-    pub geo_level_display: Option<&'a str>,
+    #[serde(
+        borrow,
+        default,
+        rename = "geoLevelDisplay",
+        deserialize_with = "parse_optional_cow"
+    )]
+    pub geo_level_display: Option<Cow<'a, str>>,
     #[serde(default, rename = "referenceDate", deserialize_with = "parse_date")]
-    reference_date: Option<NaiveDate>,
-    #[serde(borrow, default)]
-    requires: Option<Vec<&'a str>>,
+    #[cfg_attr(feature = "db", diesel(embed))]
+    reference_date: ReferenceDate,
+    #[serde(borrow, default, deserialize_with = "parse_requires")]
+    #[cfg_attr(feature = "db", diesel(deserialize_as = NullableTextArray))]
+    pub(crate) requires: Option<Vec<Cow<'a, str>>>,
     #[serde(borrow, default, deserialize_with = "parse_wildcard")]
-    wildcard: Option<Vec<&'a str>>,
+    #[cfg_attr(feature = "db", diesel(deserialize_as = NullableTextArray))]
+    pub(crate) wildcard: Option<Vec<Cow<'a, str>>>,
     #[serde(default, deserialize_with = "parse_limit")]
-    limit: Option<i32>,
-    #[serde(borrow, default, rename = "geoLevelId")]
-    geo_level_id: Option<&'a str>,
-    #[serde(borrow, default, rename = "optionalWithWCFor")]
-    optional_with_wildcard_for: Option<&'a str>,
+    pub(crate) limit: Option<i32>,
+    #[serde(
+        borrow,
+        default,
+        rename = "geoLevelId",
+        deserialize_with = "parse_optional_cow"
+    )]
+    pub(crate) geo_level_id: Option<Cow<'a, str>>,
+    #[serde(
+        borrow,
+        default,
+        rename = "optionalWithWCFor",
+        deserialize_with = "parse_optional_cow"
+    )]
+    pub(crate) optional_with_wildcard_for: Option<Cow<'a, str>>,
+}
+
+/// Which parts of a `referenceDate` were given. The parts that weren't are inferred as the
+/// first month or day, e.g. "2010" is parsed into 2010-01-01 with `Year` precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "db",
+    derive(AsExpression, FromSqlRow),
+    diesel(sql_type = Text)
+)]
+pub enum DatePrecision {
+    Year,
+    Month,
+    Day,
+}
+
+#[cfg(feature = "db")]
+impl DatePrecision {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DatePrecision::Year => "year",
+            DatePrecision::Month => "month",
+            DatePrecision::Day => "day",
+        }
+    }
+}
+
+#[cfg(feature = "db")]
+impl ToSql<Text, Pg> for DatePrecision {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(serialize::IsNull::No)
+    }
+}
+
+#[cfg(feature = "db")]
+impl FromSql<Text, Pg> for DatePrecision {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"year" => Ok(DatePrecision::Year),
+            b"month" => Ok(DatePrecision::Month),
+            b"day" => Ok(DatePrecision::Day),
+            other => Err(format!(
+                "unknown reference date precision '{}'",
+                String::from_utf8_lossy(other)
+            )
+            .into()),
+        }
+    }
+}
+
+/// A parsed `referenceDate` and its precision, which are both `None` if there's no date.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "db",
+    derive(Insertable, Queryable, Selectable),
+    diesel(table_name = geography),
+    diesel(check_for_backend(diesel::pg::Pg))
+)]
+pub struct ReferenceDate {
+    #[cfg_attr(feature = "db", diesel(column_name = reference_date))]
+    pub date: Option<NaiveDate>,
+    #[cfg_attr(feature = "db", diesel(column_name = reference_date_precision))]
+    pub precision: Option<DatePrecision>,
+}
+
+/// How many geographies of a level may be requested at once, as given by its `limit` field.
+///
+/// * a missing or `null` `limit` maps to `Unlimited`
+/// * a `limit` of `0` (or a nonsensical negative value) maps to `None_`: none are allowed
+/// * any other `limit` maps to `Max`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeographyLimit {
+    Unlimited,
+    None_,
+    Max(i32),
 }
 
-#[derive(PartialEq, Deserialize, Debug)]
+impl GeographyItem<'_> {
+    /// Return a compact metadata record for a geography picker:
+    ///
+    /// ```json
+    /// {
+    ///   "name": "county",
+    ///   "level": "050",
+    ///   "requires": ["state"],
+    ///   "wildcard": ["state"],
+    ///   "wildcardAvailable": true
+    /// }
+    /// ```
+    ///
+    /// `level` is the `geoLevelDisplay`, or `null`. `requires` and `wildcard` are empty arrays
+    /// when missing. `wildcardAvailable` is whether any required geography accepts `*`.
+    pub fn to_metadata_json(&self) -> serde_json::Value {
+        let strings = |values: &Option<Vec<Cow<str>>>| -> Vec<String> {
+            values
+                .iter()
+                .flatten()
+                .map(|value| value.to_string())
+                .collect()
+        };
+        let wildcard = strings(&self.wildcard);
+        serde_json::json!({
+            "name": self.name,
+            "level": self.geo_level_display,
+            "requires": strings(&self.requires),
+            "wildcardAvailable": !wildcard.is_empty(),
+            "wildcard": wildcard,
+        })
+    }
+
+    /// Return the values compared by the unique constraint of the `geography` table.
+    #[cfg(feature = "net")]
+    pub(crate) fn natural_key(&self) -> (&str, Option<&str>, Option<NaiveDate>) {
+        (
+            self.name.as_ref(),
+            self.geo_level_id.as_deref(),
+            self.reference_date.date,
+        )
+    }
+
+    /// Return the `referenceDate`, or the collection's if the item has none.
+    pub fn reference_date(&self) -> ReferenceDate {
+        self.reference_date
+    }
+
+    /// Interpret the `limit` field. See `GeographyLimit` for the mapping.
+    pub fn effective_limit(&self) -> GeographyLimit {
+        match self.limit {
+            None => GeographyLimit::Unlimited,
+            Some(limit) if limit <= 0 => GeographyLimit::None_,
+            Some(limit) => GeographyLimit::Max(limit),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
 pub struct GeographyCollection<'a> {
-    #[serde(borrow, default)]
     pub fips: Vec<GeographyItem<'a>>,
+    /// The top-level `referenceDate`, if any, and its precision. When parsing, it's copied
+    /// into the items that don't have their own `referenceDate`.
+    pub reference_date: ReferenceDate,
+}
+
+/// The layout of geography.json, before the top-level `referenceDate` is inherited.
+#[derive(Deserialize)]
+struct RawGeographyCollection<'a> {
+    #[serde(borrow, default)]
+    fips: Vec<GeographyItem<'a>>,
+    #[serde(default, rename = "referenceDate", deserialize_with = "parse_date")]
+    reference_date: ReferenceDate,
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for GeographyCollection<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let RawGeographyCollection {
+            mut fips,
+            reference_date,
+        } = RawGeographyCollection::deserialize(deserializer)?;
+        if reference_date.date.is_some() {
+            for item in fips
+                .iter_mut()
+                .filter(|item| item.reference_date.date.is_none())
+            {
+                item.reference_date = reference_date;
+            }
+        }
+        Ok(GeographyCollection {
+            fips,
+            reference_date,
+        })
+    }
+}
+
+impl ReferenceDate {
+    /// Format the date as in geography.json, with only the parts given by its precision, e.g.
+    /// "2010" for `DatePrecision::Year`.
+    pub(crate) fn to_json_string(self) -> Option<String> {
+        let format = match self.precision {
+            Some(DatePrecision::Year) => "%Y",
+            Some(DatePrecision::Month) => "%Y-%m",
+            Some(DatePrecision::Day) | None => "%Y-%m-%d",
+        };
+        self.date.map(|date| date.format(format).to_string())
+    }
+}
+
+/// Serialize a geography as an element of `fips` in geography.json. Missing fields are
+/// omitted.
+impl Serialize for GeographyItem<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("name", &self.name)?;
+        if let Some(geo_level_display) = &self.geo_level_display {
+            map.serialize_entry("geoLevelDisplay", geo_level_display)?;
+        }
+        if let Some(reference_date) = self.reference_date.to_json_string() {
+            map.serialize_entry("referenceDate", &reference_date)?;
+        }
+        if let Some(requires) = &self.requires {
+            map.serialize_entry("requires", requires)?;
+        }
+        if let Some(wildcard) = &self.wildcard {
+            map.serialize_entry("wildcard", wildcard)?;
+        }
+        if let Some(limit) = self.limit {
+            map.serialize_entry("limit", &limit)?;
+        }
+        if let Some(geo_level_id) = &self.geo_level_id {
+            map.serialize_entry("geoLevelId", geo_level_id)?;
+        }
+        if let Some(optional_with_wildcard_for) = &self.optional_with_wildcard_for {
+            map.serialize_entry("optionalWithWCFor", optional_with_wildcard_for)?;
+        }
+        map.end()
+    }
+}
+
+/// Serialize the geographies as geography.json. Every item keeps its own `referenceDate`,
+/// whether or not it was inherited from the collection's.
+impl Serialize for GeographyCollection<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("fips", &self.fips)?;
+        if let Some(reference_date) = self.reference_date.to_json_string() {
+            map.serialize_entry("referenceDate", &reference_date)?;
+        }
+        map.end()
+    }
+}
+
+impl GeographyCollection<'_> {
+    /// Return the `GeographyItem::to_metadata_json` record of each item, as a JSON array.
+    pub fn to_metadata_json(&self) -> serde_json::Value {
+        serde_json::Value::Array(
+            self.fips
+                .iter()
+                .map(GeographyItem::to_metadata_json)
+                .collect(),
+        )
+    }
 }
 
-/// Deserialize a date string in the format "YYYY-MM-DD" or just "YYYY".
-fn parse_date<'de, D>(deserializer: D) -> Result<Option<NaiveDate>, D::Error>
+impl GeographyCollection<'static> {
+    /// Parse a geography.json that was already deserialized into a `serde_json::Value`.
+    ///
+    /// Strings are moved out of `value`, so the returned collection owns all of its data.
+    pub fn from_value(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        GeographyCollection::deserialize(value)
+    }
+}
+
+/// Parse a geography.json into its geographies. The geographies borrow their strings from
+/// `json` when possible, and those without a `referenceDate` inherit the top-level one.
+///
+/// ```
+/// use us_census::parse_geography::parse_geography;
+///
+/// let json = r#"{
+///   "fips": [
+///     {"name": "state", "geoLevelDisplay": "040", "referenceDate": "2020-01-01"},
+///     {"name": "county", "geoLevelDisplay": "050", "requires": ["state"]}
+///   ]
+/// }"#;
+/// let geographies = parse_geography(json).unwrap();
+/// assert_eq!(geographies.len(), 2);
+/// assert_eq!(geographies[1].name, "county");
+/// assert_eq!(geographies[1].geo_level_display.as_deref(), Some("050"));
+/// ```
+pub fn parse_geography(json: &str) -> Result<Vec<GeographyItem<'_>>, serde_json::Error> {
+    Ok(serde_json::from_str::<GeographyCollection>(json)?.fips)
+}
+
+/// Options for parsing a geography.json. The default is strict parsing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GeographyParseOptions {
+    /// Parse an invalid `referenceDate` into `None` and record a warning, instead of failing
+    /// to parse the whole collection.
+    pub lenient_reference_date: bool,
+    /// See `GeoLevelValidation`.
+    pub geo_level_codes: GeoLevelValidation,
+}
+
+/// What `GeographyCollection::from_str_with_options` does with a `geoLevelId` or
+/// `geoLevelDisplay` that isn't a code of 2 or 3 digits, like "040".
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GeoLevelValidation {
+    /// Accept any code.
+    #[default]
+    Ignore,
+    /// Keep the code and record a warning.
+    Warn,
+    /// Fail to parse the whole collection.
+    Fail,
+}
+
+/// Return whether `code` is a geo level code of 2 or 3 digits, like "040".
+fn is_geo_level_code(code: &str) -> bool {
+    (2..=3).contains(&code.len()) && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+impl<'a> GeographyCollection<'a> {
+    /// Parse a geography.json with the given options.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok((GeographyCollection, Vec<String>))` - The collection and the warnings recorded
+    ///   by lenient parsing and `GeoLevelValidation::Warn`
+    /// * `Err(serde_json::Error)` - An error if the JSON is invalid, or if a geo level code is
+    ///   invalid with `GeoLevelValidation::Fail`
+    pub fn from_str_with_options(
+        json: &'a str,
+        options: &GeographyParseOptions,
+    ) -> Result<(Self, Vec<String>), serde_json::Error> {
+        let _guard = options.lenient_reference_date.then(DateWarnings::record);
+        let collection: Self = serde_json::from_str(json)?;
+        let mut warnings = DateWarnings::take();
+        if options.geo_level_codes != GeoLevelValidation::Ignore {
+            for warning in collection.invalid_geo_level_codes() {
+                if options.geo_level_codes == GeoLevelValidation::Fail {
+                    return Err(de::Error::custom(warning));
+                }
+                warnings.push(warning);
+            }
+        }
+        Ok((collection, warnings))
+    }
+
+    /// Describe each `geoLevelId` and `geoLevelDisplay` that isn't a code of 2 or 3 digits.
+    fn invalid_geo_level_codes(&self) -> impl Iterator<Item = String> + '_ {
+        self.fips.iter().flat_map(|item| {
+            [
+                ("geoLevelId", &item.geo_level_id),
+                ("geoLevelDisplay", &item.geo_level_display),
+            ]
+            .into_iter()
+            .filter_map(|(field, code)| {
+                let code = code.as_deref().filter(|code| !is_geo_level_code(code))?;
+                Some(format!(
+                    "invalid {} '{}' of geography '{}': expected a code of 2 or 3 digits",
+                    field, code, item.name
+                ))
+            })
+        })
+    }
+}
+
+thread_local! {
+    /// Warnings for invalid dates, recorded instead of errors while it's `Some`.
+    static DATE_WARNINGS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Guard that makes `parse_date` on this thread lenient until dropped.
+struct DateWarnings;
+
+impl DateWarnings {
+    fn record() -> Self {
+        DATE_WARNINGS.with(|warnings| *warnings.borrow_mut() = Some(Vec::new()));
+        DateWarnings
+    }
+
+    /// Return the warnings recorded so far, or none if parsing is strict.
+    fn take() -> Vec<String> {
+        DATE_WARNINGS.with(|warnings| {
+            warnings
+                .borrow_mut()
+                .as_mut()
+                .map(std::mem::take)
+                .unwrap_or_default()
+        })
+    }
+
+    /// Record a warning and return `true` if parsing is lenient.
+    fn push(warning: String) -> bool {
+        DATE_WARNINGS.with(|warnings| match warnings.borrow_mut().as_mut() {
+            Some(warnings) => {
+                warnings.push(warning);
+                true
+            }
+            None => false,
+        })
+    }
+}
+
+impl Drop for DateWarnings {
+    fn drop(&mut self) {
+        DATE_WARNINGS.with(|warnings| *warnings.borrow_mut() = None);
+    }
+}
+
+/// Deserialize a date string in the format "YYYY-MM-DD", "YYYY-MM" or just "YYYY", recording
+/// which of the three it was. A missing month or day is the first one. A datetime, e.g.
+/// "2010-01-01T00:00:00Z", is parsed into its date with `Day` precision.
+///
+/// Invalid dates are an error unless parsing with `GeographyParseOptions::lenient_reference_date`.
+fn parse_date<'de, D>(deserializer: D) -> Result<ReferenceDate, D::Error>
 where
     D: Deserializer<'de>,
 {
     let opt_str = Option::<String>::deserialize(deserializer)?;
 
-    match opt_str {
-        None => Ok(None),
+    let result = match opt_str.as_deref() {
+        None => Ok((None, None)),
         Some(s) if s.len() == 4 && s.chars().all(|c| c.is_ascii_digit()) => {
             // Handle year-only format
-            let year = s.parse::<i32>().map_err(de::Error::custom)?;
-            Ok(NaiveDate::from_ymd_opt(year, 1, 1))
+            s.parse::<i32>()
+                .map(|year| {
+                    (
+                        NaiveDate::from_ymd_opt(year, 1, 1),
+                        Some(DatePrecision::Year),
+                    )
+                })
+                .map_err(|e| e.to_string())
+        }
+        Some(s) if s.len() == 7 => {
+            // Handle year-month format
+            NaiveDate::parse_from_str(&format!("{}-01", s), "%Y-%m-%d")
+                .map(|date| (Some(date), Some(DatePrecision::Month)))
+                .map_err(|e| e.to_string())
         }
         Some(s) => {
-            // Handle normal date format
-            NaiveDate::parse_from_str(&s, "%Y-%m-%d")
-                .map(Some)
-                .map_err(de::Error::custom)
+            // Handle normal date format, then datetimes, e.g. "2010-01-01T00:00:00Z"
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .or_else(|e| parse_datetime_date(s).ok_or(e))
+                .map(|date| (Some(date), Some(DatePrecision::Day)))
+                .map_err(|e| e.to_string())
         }
-    }
+    };
+    result
+        .map(|(date, precision)| ReferenceDate { date, precision })
+        .or_else(|e| {
+            let warning = format!(
+                "ignored invalid referenceDate '{}': {}",
+                opt_str.unwrap_or_default(),
+                e
+            );
+            if DateWarnings::push(warning) {
+                Ok(ReferenceDate::default())
+            } else {
+                Err(de::Error::custom(e))
+            }
+        })
+}
+
+/// Return the date portion of an RFC 3339 datetime, or of an ISO 8601 datetime without a
+/// time zone. The date is that of the datetime's own time zone, not UTC.
+fn parse_datetime_date(s: &str) -> Option<NaiveDate> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|datetime| datetime.date_naive())
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f").map(|datetime| datetime.date())
+        })
+        .ok()
 }
 
 struct WildcardVisitor;
 
 impl<'de> de::Visitor<'de> for WildcardVisitor {
-    type Value = Option<Vec<&'de str>>;
+    type Value = Option<Vec<Cow<'de, str>>>;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         formatter.write_str("an array of strings or a boolean")
@@ -87,56 +561,76 @@ impl<'de> de::Visitor<'de> for WildcardVisitor {
         A: de::SeqAccess<'de>,
     {
         let mut vec = Vec::new();
-        while let Some(value) = seq.next_element()? {
+        while let Some(CowStr(value)) = seq.next_element()? {
             vec.push(value);
         }
         Ok(Some(vec))
     }
 }
 
-fn parse_wildcard<'de, D>(deserializer: D) -> Result<Option<Vec<&'de str>>, D::Error>
+fn parse_wildcard<'de, D>(deserializer: D) -> Result<Option<Vec<Cow<'de, str>>>, D::Error>
 where
     D: Deserializer<'de>,
 {
     deserializer.deserialize_any(WildcardVisitor)
 }
 
-struct LimitVisitor;
+struct RequiresVisitor;
 
-impl<'de> de::Visitor<'de> for LimitVisitor {
-    type Value = Option<i32>;
+impl<'de> de::Visitor<'de> for RequiresVisitor {
+    type Value = Option<Vec<Cow<'de, str>>>;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("a string or integer")
+        formatter.write_str("an array of strings, a string or null")
     }
 
-    /// If the 'limit' field is already an integer, just return it.
-    fn visit_i32<E>(self, v: i32) -> Result<Self::Value, E>
+    fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
     where
         E: de::Error,
     {
-        Ok(Some(v))
+        Ok(Some(vec![Cow::Borrowed(v)]))
     }
 
-    /// Convert a string to an integer, stripping any quotation marks.
     fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
     where
         E: de::Error,
     {
-        let cleaned_str = v.trim_matches('"');
-        let limit = cleaned_str
-            .parse::<i32>()
-            .map_err(|_| E::custom(format!("invalid value for 'limit' field: {}", v)))?;
+        Ok(Some(vec![Cow::Owned(v.to_string())]))
+    }
 
-        Ok(Some(limit))
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(Some(vec![Cow::Owned(v)]))
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(None)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let mut vec = Vec::new();
+        while let Some(CowStr(value)) = seq.next_element()? {
+            vec.push(value);
+        }
+        Ok(Some(vec))
     }
 }
 
-fn parse_limit<'de, D>(deserializer: D) -> Result<Option<i32>, D::Error>
+/// Deserialize a `requires` field. Most entries list the required geographies in an array, but
+/// some give a single geography as a string, which is treated as an array of one.
+fn parse_requires<'de, D>(deserializer: D) -> Result<Option<Vec<Cow<'de, str>>>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    deserializer.deserialize_any(LimitVisitor)
+    deserializer.deserialize_any(RequiresVisitor)
 }
 
 #[cfg(test)]
@@ -159,19 +653,23 @@ mod tests {
         }
       ]
     }"#;
-        let result: GeographyCollection = from_str(&object_under_test).expect("Error parsing JSON");
+        let result: GeographyCollection = from_str(object_under_test).expect("Error parsing JSON");
         let expected = GeographyCollection {
             fips: vec![GeographyItem {
                 id: 0,
                 name: Cow::from("us"),
                 geo_level_display: None,
-                reference_date: NaiveDate::from_ymd_opt(2010, 1, 1),
+                reference_date: ReferenceDate {
+                    date: NaiveDate::from_ymd_opt(2010, 1, 1),
+                    precision: Some(DatePrecision::Day),
+                },
                 geo_level_id: None,
                 requires: None,
                 wildcard: None,
                 limit: None,
                 optional_with_wildcard_for: None,
             }],
+            reference_date: ReferenceDate::default(),
         };
         assert_eq!(result, expected);
     }
@@ -194,17 +692,21 @@ mod tests {
                 id: 0,
                 name: Cow::from("us"),
                 geo_level_display: None,
-                reference_date: NaiveDate::from_ymd_opt(2010, 1, 1),
+                reference_date: ReferenceDate {
+                    date: NaiveDate::from_ymd_opt(2010, 1, 1),
+                    precision: Some(DatePrecision::Year),
+                },
                 geo_level_id: None,
                 requires: None,
                 wildcard: None,
                 limit: None,
                 optional_with_wildcard_for: None,
             }],
+            reference_date: ReferenceDate::default(),
         };
 
         // Act
-        let result: GeographyCollection = from_str(&object_under_test).expect("Error parsing JSON");
+        let result: GeographyCollection = from_str(object_under_test).expect("Error parsing JSON");
 
         // Assert
         assert_eq!(result, expected);
@@ -281,6 +783,239 @@ mod tests {
         assert_eq!(result.fips[0].limit, Some(65536));
     }
 
+    /// `null`, `0` and positive 'limit' values map to the right `GeographyLimit`.
+    #[rstest]
+    #[case(Value::Null, GeographyLimit::Unlimited)]
+    #[case(json!(0), GeographyLimit::None_)]
+    #[case(json!(10), GeographyLimit::Max(10))]
+    #[case(json!("0"), GeographyLimit::None_)]
+    fn test_effective_limit(
+        mut base_value: Map<String, Value>,
+        #[case] limit: Value,
+        #[case] expected: GeographyLimit,
+    ) {
+        // Arrange
+        base_value.insert("limit".to_string(), limit);
+        let object_under_test = json!({
+            "fips": [Value::Object(base_value)]
+        });
+        let object_under_test_str = to_string(&object_under_test).unwrap();
+
+        // Act
+        let result: GeographyCollection =
+            from_str(&object_under_test_str).expect("Error parsing JSON");
+
+        // Assert
+        assert_eq!(result.fips[0].effective_limit(), expected);
+    }
+
+    /// A missing 'limit' is unlimited.
+    #[rstest]
+    fn test_effective_limit_missing(mut base_value: Map<String, Value>) {
+        // Arrange
+        base_value.remove("limit");
+        let object_under_test = to_string(&json!({ "fips": [Value::Object(base_value)] })).unwrap();
+
+        // Act
+        let result: GeographyCollection = from_str(&object_under_test).expect("Error parsing JSON");
+
+        // Assert
+        assert_eq!(result.fips[0].effective_limit(), GeographyLimit::Unlimited);
+    }
+
+    /// An invalid 'referenceDate' fails strict parsing.
+    #[rstest]
+    fn test_reference_date_strict(mut base_value: Map<String, Value>) {
+        // Arrange
+        base_value.insert("referenceDate".to_string(), json!("garbage"));
+        let object_under_test = to_string(&json!({ "fips": [Value::Object(base_value)] })).unwrap();
+
+        // Act
+        let result = GeographyCollection::from_str_with_options(
+            &object_under_test,
+            &GeographyParseOptions::default(),
+        );
+
+        // Assert
+        assert!(result.is_err());
+    }
+
+    /// An invalid 'referenceDate' is parsed into `None` with a warning in lenient mode.
+    #[rstest]
+    fn test_reference_date_lenient(mut base_value: Map<String, Value>) {
+        // Arrange
+        let mut invalid_value = base_value.clone();
+        invalid_value.insert("referenceDate".to_string(), json!("garbage"));
+        base_value.insert("name".to_string(), json!("state"));
+        let object_under_test = to_string(&json!({
+            "fips": [Value::Object(invalid_value), Value::Object(base_value)]
+        }))
+        .unwrap();
+        let options = GeographyParseOptions {
+            lenient_reference_date: true,
+            ..GeographyParseOptions::default()
+        };
+
+        // Act
+        let (result, warnings) =
+            GeographyCollection::from_str_with_options(&object_under_test, &options).unwrap();
+
+        // Assert
+        assert_eq!(result.fips[0].reference_date(), ReferenceDate::default());
+        assert_eq!(
+            result.fips[1].reference_date().date,
+            NaiveDate::from_ymd_opt(2010, 1, 1)
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("garbage"), "{}", warnings[0]);
+        // Parsing is strict again afterwards.
+        assert!(from_str::<GeographyCollection>(&object_under_test).is_err());
+    }
+
+    /// The precision of 'referenceDate' tells a year-only date from a full date.
+    #[rstest]
+    #[case::year(
+        Some("2010"),
+        NaiveDate::from_ymd_opt(2010, 1, 1),
+        Some(DatePrecision::Year)
+    )]
+    #[case::month(
+        Some("2019-06"),
+        NaiveDate::from_ymd_opt(2019, 6, 1),
+        Some(DatePrecision::Month)
+    )]
+    #[case::day(
+        Some("2010-06-15"),
+        NaiveDate::from_ymd_opt(2010, 6, 15),
+        Some(DatePrecision::Day)
+    )]
+    #[case::january_first(
+        Some("2010-01-01"),
+        NaiveDate::from_ymd_opt(2010, 1, 1),
+        Some(DatePrecision::Day)
+    )]
+    #[case::utc_datetime(
+        Some("2010-01-01T00:00:00Z"),
+        NaiveDate::from_ymd_opt(2010, 1, 1),
+        Some(DatePrecision::Day)
+    )]
+    #[case::offset_datetime(
+        Some("2010-06-15T23:30:00-05:00"),
+        NaiveDate::from_ymd_opt(2010, 6, 15),
+        Some(DatePrecision::Day)
+    )]
+    #[case::naive_datetime(
+        Some("2010-06-15T12:00:00.000"),
+        NaiveDate::from_ymd_opt(2010, 6, 15),
+        Some(DatePrecision::Day)
+    )]
+    #[case::missing(None, None, None)]
+    fn test_reference_date_precision(
+        mut base_value: Map<String, Value>,
+        #[case] reference_date: Option<&str>,
+        #[case] expected_date: Option<NaiveDate>,
+        #[case] expected_precision: Option<DatePrecision>,
+    ) {
+        // Arrange
+        match reference_date {
+            Some(reference_date) => {
+                base_value.insert("referenceDate".to_string(), json!(reference_date))
+            }
+            None => base_value.remove("referenceDate"),
+        };
+        let object_under_test = to_string(&json!({ "fips": [Value::Object(base_value)] })).unwrap();
+
+        // Act
+        let result: GeographyCollection = from_str(&object_under_test).expect("Error parsing JSON");
+
+        // Assert
+        assert_eq!(
+            result.fips[0].reference_date(),
+            ReferenceDate {
+                date: expected_date,
+                precision: expected_precision,
+            }
+        );
+    }
+
+    /// Items inherit a top-level 'referenceDate' unless they have their own.
+    #[rstest]
+    fn test_top_level_reference_date(mut base_value: Map<String, Value>) {
+        // Arrange
+        let mut inheriting = base_value.clone();
+        inheriting.remove("referenceDate");
+        base_value.insert("name".to_string(), json!("state"));
+        let object_under_test = to_string(&json!({
+            "referenceDate": "2020-01-01",
+            "fips": [Value::Object(inheriting), Value::Object(base_value)]
+        }))
+        .unwrap();
+
+        // Act
+        let result: GeographyCollection = from_str(&object_under_test).expect("Error parsing JSON");
+
+        // Assert
+        assert_eq!(
+            result.reference_date,
+            ReferenceDate {
+                date: NaiveDate::from_ymd_opt(2020, 1, 1),
+                precision: Some(DatePrecision::Day),
+            }
+        );
+        assert_eq!(
+            result.fips[0].reference_date().date,
+            NaiveDate::from_ymd_opt(2020, 1, 1)
+        );
+        assert_eq!(
+            result.fips[1].reference_date().date,
+            NaiveDate::from_ymd_opt(2010, 1, 1)
+        );
+    }
+
+    #[test]
+    fn test_to_metadata_json() {
+        // Arrange
+        let object_under_test = r#"
+        {
+          "fips": [
+            {"name": "us", "geoLevelDisplay": "010"},
+            {
+              "name": "county",
+              "geoLevelDisplay": "050",
+              "requires": ["state"],
+              "wildcard": ["state"],
+              "optionalWithWCFor": "state"
+            }
+          ]
+        }"#;
+        let collection: GeographyCollection =
+            from_str(object_under_test).expect("Error parsing JSON");
+
+        // Act
+        let result = collection.to_metadata_json();
+
+        // Assert
+        assert_eq!(
+            result,
+            json!([
+                {
+                    "name": "us",
+                    "level": "010",
+                    "requires": [],
+                    "wildcard": [],
+                    "wildcardAvailable": false
+                },
+                {
+                    "name": "county",
+                    "level": "050",
+                    "requires": ["state"],
+                    "wildcard": ["state"],
+                    "wildcardAvailable": true
+                }
+            ])
+        );
+    }
+
     /// Missing 'fips' field
     #[rstest]
     fn test_missing_fips() {
@@ -292,7 +1027,7 @@ mod tests {
             }
           ]
         }"#;
-        let result: GeographyCollection = from_str(&object_under_test).expect("Error parsing JSON");
+        let result: GeographyCollection = from_str(object_under_test).expect("Error parsing JSON");
         assert_eq!(result.fips.len(), 0);
     }
 
@@ -309,19 +1044,23 @@ mod tests {
         }
       ]
     }"#;
-        let result: GeographyCollection = from_str(&object_under_test).expect("Error parsing JSON");
+        let result: GeographyCollection = from_str(object_under_test).expect("Error parsing JSON");
         let expected = GeographyCollection {
             fips: vec![GeographyItem {
                 id: 0,
                 name: Cow::from("us"),
-                geo_level_display: Option::from("010"),
-                reference_date: NaiveDate::from_ymd_opt(2010, 1, 1),
+                geo_level_display: Some(Cow::from("010")),
+                reference_date: ReferenceDate {
+                    date: NaiveDate::from_ymd_opt(2010, 1, 1),
+                    precision: Some(DatePrecision::Day),
+                },
                 geo_level_id: None,
                 requires: None,
                 wildcard: Option::from(Vec::new()),
                 limit: None,
                 optional_with_wildcard_for: None,
             }],
+            reference_date: ReferenceDate::default(),
         };
         assert_eq!(result, expected);
     }
@@ -340,15 +1079,181 @@ mod tests {
         }
       ]
     }"#;
-        let result: Result<GeographyCollection, _> = from_str(&invalid_json);
+        let result: Result<GeographyCollection, _> = from_str(invalid_json);
         assert!(result.is_err());
         if let Err(err) = result {
             let expected_error_message_re = Regex::new(r".*true.*wildcard.*").unwrap();
             assert!(
                 expected_error_message_re.is_match(&err.to_string()),
                 "Unexpected error message: {}",
-                err.to_string()
+                err
             );
         }
     }
+
+    /// `geoLevelId` and `geoLevelDisplay` must be codes of 2 or 3 digits, if validated.
+    #[rstest]
+    #[case::valid_warn("040", GeoLevelValidation::Warn, Some(0))]
+    #[case::invalid_ignore("abc", GeoLevelValidation::Ignore, Some(0))]
+    #[case::invalid_warn("abc", GeoLevelValidation::Warn, Some(2))]
+    #[case::valid_fail("040", GeoLevelValidation::Fail, Some(0))]
+    #[case::invalid_fail("abc", GeoLevelValidation::Fail, None)]
+    fn test_geo_level_codes(
+        mut base_value: Map<String, Value>,
+        #[case] code: &str,
+        #[case] geo_level_codes: GeoLevelValidation,
+        #[case] expected_warnings: Option<usize>,
+    ) {
+        // Arrange
+        base_value.insert("geoLevelId".to_string(), json!(code));
+        base_value.insert("geoLevelDisplay".to_string(), json!(code));
+        let object_under_test = to_string(&json!({ "fips": [Value::Object(base_value)] })).unwrap();
+        let options = GeographyParseOptions {
+            geo_level_codes,
+            ..GeographyParseOptions::default()
+        };
+
+        // Act
+        let result = GeographyCollection::from_str_with_options(&object_under_test, &options);
+
+        // Assert
+        match (result, expected_warnings) {
+            (Ok((collection, warnings)), Some(expected_warnings)) => {
+                assert_eq!(collection.fips[0].geo_level_id.as_deref(), Some(code));
+                assert_eq!(warnings.len(), expected_warnings, "{:?}", warnings);
+            }
+            (Err(e), None) => assert!(e.to_string().contains("invalid geoLevelId 'abc'"), "{}", e),
+            (result, _) => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    /// `requires` is an array of strings, a single string, or missing.
+    #[rstest]
+    #[case::array(Some(json!(["state", "county"])), Some(vec!["state", "county"]))]
+    #[case::string(Some(json!("state")), Some(vec!["state"]))]
+    #[case::null(Some(Value::Null), None)]
+    #[case::missing(None, None)]
+    fn test_requires(
+        mut base_value: Map<String, Value>,
+        #[case] requires: Option<Value>,
+        #[case] expected: Option<Vec<&str>>,
+    ) {
+        // Arrange
+        if let Some(requires) = requires {
+            base_value.insert("requires".to_string(), requires);
+        }
+        let object_under_test = to_string(&json!({ "fips": [Value::Object(base_value)] })).unwrap();
+
+        // Act
+        let result: GeographyCollection = from_str(&object_under_test).expect("Error parsing JSON");
+
+        // Assert
+        assert_eq!(
+            result.fips[0].requires,
+            expected.map(|names| names.into_iter().map(Cow::from).collect())
+        );
+    }
+
+    #[rstest]
+    fn test_from_value() {
+        let value = json!({
+            "fips": [
+                {
+                    "name": "county",
+                    "geoLevelDisplay": "050",
+                    "referenceDate": "2020-01-01",
+                    "requires": ["state"],
+                    "wildcard": ["state"],
+                    "optionalWithWCFor": "state"
+                }
+            ]
+        });
+
+        let result = GeographyCollection::from_value(value).expect("Error parsing JSON value");
+
+        let expected = GeographyCollection {
+            fips: vec![GeographyItem {
+                id: 0,
+                name: Cow::from("county"),
+                geo_level_display: Some(Cow::from("050")),
+                reference_date: ReferenceDate {
+                    date: NaiveDate::from_ymd_opt(2020, 1, 1),
+                    precision: Some(DatePrecision::Day),
+                },
+                geo_level_id: None,
+                requires: Some(vec![Cow::from("state")]),
+                wildcard: Some(vec![Cow::from("state")]),
+                limit: None,
+                optional_with_wildcard_for: Some(Cow::from("state")),
+            }],
+            reference_date: ReferenceDate::default(),
+        };
+        assert_eq!(result, expected);
+    }
+
+    /// Strings without escape sequences are borrowed from the input.
+    #[rstest]
+    fn test_borrows_from_str() {
+        let object_under_test = r#"
+    {
+      "fips": [
+        {
+          "name": "county",
+          "geoLevelDisplay": "050",
+          "requires": ["state"],
+          "wildcard": ["state"]
+        }
+      ]
+    }"#;
+        let result: GeographyCollection = from_str(object_under_test).expect("Error parsing JSON");
+
+        let item = &result.fips[0];
+        assert!(matches!(item.geo_level_display, Some(Cow::Borrowed(_))));
+        assert!(matches!(
+            item.requires.as_ref().unwrap()[0],
+            Cow::Borrowed(_)
+        ));
+        assert!(matches!(
+            item.wildcard.as_ref().unwrap()[0],
+            Cow::Borrowed(_)
+        ));
+    }
+
+    /// Serializing a collection and parsing it again returns the same collection, including
+    /// the precision of each `referenceDate`.
+    #[rstest]
+    #[case::fixture(include_str!("../tests/fixtures/geography.json"))]
+    #[case::precision(
+        r#"{"fips": [{"name": "us", "referenceDate": "2010"}, {"name": "state", "referenceDate": "2010-07", "limit": "50", "wildcard": false}]}"#
+    )]
+    #[case::inherited_date(r#"{"fips": [{"name": "us"}], "referenceDate": "2020-01-01"}"#)]
+    #[case::inherited_year(r#"{"fips": [{"name": "us"}], "referenceDate": "2020"}"#)]
+    fn test_serialize_round_trip(#[case] geography_json: &str) {
+        // Arrange
+        let parsed: GeographyCollection = from_str(geography_json).unwrap();
+
+        // Act
+        let serialized = to_string(&parsed).unwrap();
+
+        // Assert
+        let reparsed: GeographyCollection = from_str(&serialized).unwrap();
+        assert_eq!(reparsed, parsed);
+    }
+
+    /// The top-level `referenceDate` is serialized with its own precision.
+    #[rstest]
+    #[case::year("2020")]
+    #[case::month("2020-07")]
+    #[case::day("2020-07-01")]
+    fn test_serialize_top_level_reference_date(#[case] reference_date: &str) {
+        // Arrange
+        let geography_json = json!({"fips": [], "referenceDate": reference_date}).to_string();
+        let parsed: GeographyCollection = from_str(&geography_json).unwrap();
+
+        // Act
+        let serialized: Value = serde_json::to_value(&parsed).unwrap();
+
+        // Assert
+        assert_eq!(serialized["referenceDate"], json!(reference_date));
+    }
 }
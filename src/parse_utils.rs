@@ -0,0 +1,245 @@
+//! Deserialization helpers shared by the `parse_*` modules.
+use serde::de::{DeserializeOwned, Visitor};
+use serde::{de, Deserialize, Deserializer};
+use std::borrow::Cow;
+use std::fmt;
+use std::io::BufRead;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+/// A string that borrows from the input when possible and is owned otherwise, e.g. when it
+/// contains escape sequences or is deserialized from a `serde_json::Value`.
+///
+/// `#[serde(borrow)]` only has this behavior for fields of type `Cow<'a, str>`, not for
+/// `Option<Cow<'a, str>>` or `Vec<Cow<'a, str>>`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct CowStr<'a>(pub Cow<'a, str>);
+
+struct CowStrVisitor;
+
+impl<'de> Visitor<'de> for CowStrVisitor {
+    type Value = CowStr<'de>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string")
+    }
+
+    fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(CowStr(Cow::Borrowed(v)))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(CowStr(Cow::Owned(v.to_string())))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(CowStr(Cow::Owned(v)))
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for CowStr<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(CowStrVisitor)
+    }
+}
+
+/// Deserialize an optional string, borrowing it from the input when possible.
+pub(crate) fn parse_optional_cow<'de, D>(deserializer: D) -> Result<Option<Cow<'de, str>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<CowStr>::deserialize(deserializer)?.map(|s| s.0))
+}
+
+/// Visitor for a `limit` field, which is an integer, a numeric string, or `null`.
+struct LimitVisitor<T>(PhantomData<T>);
+
+impl<T> LimitVisitor<T>
+where
+    T: TryFrom<i64> + TryFrom<u64> + FromStr,
+{
+    fn convert<E, V>(v: V) -> Result<Option<T>, E>
+    where
+        E: de::Error,
+        V: fmt::Display + Copy,
+        T: TryFrom<V>,
+    {
+        T::try_from(v)
+            .map(Some)
+            .map_err(|_| E::custom(format!("invalid value for 'limit' field: {}", v)))
+    }
+}
+
+impl<'de, T> Visitor<'de> for LimitVisitor<T>
+where
+    T: TryFrom<i64> + TryFrom<u64> + FromStr,
+{
+    type Value = Option<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string, integer or null")
+    }
+
+    /// JSON integers are visited as `i64` (negative) or `u64` (non-negative).
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Self::convert(v)
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Self::convert(v)
+    }
+
+    /// Convert a string to an integer, stripping any quotation marks.
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        let cleaned_str = v.trim_matches('"');
+        let limit = cleaned_str
+            .parse::<T>()
+            .map_err(|_| E::custom(format!("invalid value for 'limit' field: {}", v)))?;
+
+        Ok(Some(limit))
+    }
+
+    /// A `null` 'limit' means the limit is unknown or there's none.
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(None)
+    }
+}
+
+/// Deserialize a `limit` field given as an integer, a numeric string such as `"51"` (possibly
+/// with stray quotation marks), or `null`. Use with `#[serde(default)]` so that a missing
+/// `limit` is `None` too.
+pub(crate) fn parse_limit<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<i64> + TryFrom<u64> + FromStr,
+{
+    deserializer.deserialize_any(LimitVisitor(PhantomData))
+}
+
+/// Reads a JSON document from a `BufRead` piece by piece, so that the entries of a large array
+/// or object can be parsed one at a time. I/O errors are reported as `serde_json::Error`s, as
+/// `serde_json::from_reader` does.
+pub(crate) struct JsonReader<R> {
+    reader: R,
+}
+
+/// Where a stream over the entries of a JSON array or object is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StreamState {
+    /// The reader is at the start of the document.
+    Start,
+    /// The reader is inside the array or object, before its first entry.
+    FirstEntry,
+    /// The reader is inside the array or object, after an entry.
+    NextEntry,
+    Done,
+}
+
+impl<R: BufRead> JsonReader<R> {
+    pub fn new(reader: R) -> Self {
+        JsonReader { reader }
+    }
+
+    /// Move the reader past `open`, the first byte of the value of the top-level `key`.
+    pub fn seek_key(&mut self, key: &str, open: u8) -> serde_json::Result<()> {
+        self.expect_byte(b'{')?;
+        loop {
+            self.skip_whitespace()?;
+            let found: String = self.parse_next()?;
+            self.expect_byte(b':')?;
+            if found == key {
+                return self.expect_byte(open);
+            }
+            self.skip_value()?;
+            self.skip_whitespace()?;
+            if self.next_byte()? != Some(b',') {
+                return Err(de::Error::custom(format!("missing field `{}`", key)));
+            }
+        }
+    }
+
+    /// Parse the next JSON value. Strings, objects and arrays end with a delimiter, so
+    /// serde_json doesn't read past them.
+    pub fn parse_next<T: DeserializeOwned>(&mut self) -> serde_json::Result<T> {
+        let mut deserializer = serde_json::Deserializer::from_reader(&mut self.reader);
+        T::deserialize(&mut deserializer)
+    }
+
+    /// Skip a JSON value without parsing it.
+    pub fn skip_value(&mut self) -> serde_json::Result<()> {
+        self.skip_whitespace()?;
+        match self.peek_byte()? {
+            Some(b'{' | b'[' | b'"') => {
+                self.parse_next::<de::IgnoredAny>()?;
+            }
+            // Numbers, booleans and null.
+            _ => {
+                while let Some(byte) = self.peek_byte()? {
+                    if matches!(byte, b',' | b'}') || byte.is_ascii_whitespace() {
+                        break;
+                    }
+                    self.reader.consume(1);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn skip_whitespace(&mut self) -> serde_json::Result<()> {
+        while let Some(byte) = self.peek_byte()? {
+            if !byte.is_ascii_whitespace() {
+                break;
+            }
+            self.reader.consume(1);
+        }
+        Ok(())
+    }
+
+    pub fn expect_byte(&mut self, expected: u8) -> serde_json::Result<()> {
+        self.skip_whitespace()?;
+        match self.next_byte()? {
+            Some(byte) if byte == expected => Ok(()),
+            _ => Err(de::Error::custom(format!(
+                "expected `{}`",
+                expected as char
+            ))),
+        }
+    }
+
+    pub fn peek_byte(&mut self) -> serde_json::Result<Option<u8>> {
+        let buffer = self.reader.fill_buf().map_err(serde_json::Error::io)?;
+        Ok(buffer.first().copied())
+    }
+
+    pub fn next_byte(&mut self) -> serde_json::Result<Option<u8>> {
+        let byte = self.peek_byte()?;
+        if byte.is_some() {
+            self.reader.consume(1);
+        }
+        Ok(byte)
+    }
+}
@@ -1,24 +1,42 @@
+#[cfg(feature = "db")]
+use crate::models::{NullableStringMap, NullableTextArray, TextArray};
+use crate::parse_utils::{parse_limit, parse_optional_cow, CowStr, JsonReader, StreamState};
+#[cfg(feature = "db")]
 use crate::schema::variables;
 
+#[cfg(feature = "db")]
 use diesel::prelude::*;
+use md5::{Digest, Md5};
 use regex::Regex;
 use serde::de::Visitor;
-use serde::{de, Deserialize, Deserializer};
+use serde::ser::SerializeMap;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::value::RawValue;
 use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::io::BufRead;
 use std::sync::OnceLock;
 
+/// The labels of a variable's coded values, keyed by code. See `VariablesItem::values`.
+pub type CodeLabels<'a> = BTreeMap<Cow<'a, str>, Cow<'a, str>>;
+
 /// `VariablesItem` is a single variable in the variables.json of an API endpoint.
 /// Functions that parse the variables.json file will return a `Vec<VariablesItem>` and
 /// `VariablesItem` is also used directly reading and writing to the postgres database.
-#[derive(Deserialize, Insertable, Queryable, Selectable, Identifiable, Debug, PartialEq)]
-#[diesel(table_name = variables)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Deserialize, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "db",
+    derive(Insertable, Queryable, Selectable, Identifiable),
+    diesel(table_name = variables),
+    diesel(check_for_backend(diesel::pg::Pg))
+)]
 pub struct VariablesItem<'a> {
     /// Primary key in the database. This field is not in variables.json although for the class
     /// to be insertable `id` must be an `i32` and not an `Option<i32>`.
     #[serde(skip, default)]
-    #[diesel(skip_insertion)]
+    #[cfg_attr(feature = "db", diesel(skip_insertion))]
     pub id: i32,
     /// The name of the variable. In variables.json, this is the key of each item
     /// in the top-level "variables" map. The remaining fields are the values. `default` is
@@ -26,36 +44,511 @@ pub struct VariablesItem<'a> {
     #[serde(borrow, default)]
     pub name: Cow<'a, str>,
     /// `label` field must be a `Vec<Cow<'a, str>>` to parse backslashes. Due to how
-    /// serde_json parses, backslashes must be owned.
-    #[serde(borrow, deserialize_with = "parse_label")]
+    /// serde_json parses, backslashes must be owned. An empty, `null` or missing label, e.g.
+    /// of the `for` and `in` predicates, is parsed into an empty list.
+    #[serde(borrow, default, deserialize_with = "parse_label")]
+    #[cfg_attr(feature = "db", diesel(deserialize_as = TextArray))]
     pub label: Vec<Cow<'a, str>>,
     // `concept` must be owned to parse escaped quote characters.
     #[serde(borrow)]
     pub concept: Option<Cow<'a, str>>,
-    #[serde(borrow)]
-    pub required: Option<&'a str>,
-    #[serde(borrow, rename = "predicateType")]
-    pub predicate_type: Option<&'a str>,
+    #[serde(borrow, default, deserialize_with = "parse_optional_cow")]
+    pub required: Option<Cow<'a, str>>,
+    #[serde(
+        borrow,
+        default,
+        rename = "predicateType",
+        deserialize_with = "parse_optional_cow"
+    )]
+    pub predicate_type: Option<Cow<'a, str>>,
     #[serde(borrow, deserialize_with = "parse_comma_separated_string")]
+    #[cfg_attr(feature = "db", diesel(deserialize_as = NullableTextArray))]
     pub group: Option<Vec<Cow<'a, str>>>,
-    pub limit: Option<i16>,
+    /// Stored as an `INTEGER`, since Census JSON has limits beyond the range of `i16`.
+    #[serde(default, deserialize_with = "parse_limit")]
+    pub limit: Option<i32>,
     #[serde(rename = "predicateOnly")]
     pub predicate_only: Option<bool>,
     #[serde(borrow, default, deserialize_with = "parse_comma_separated_string")]
+    #[cfg_attr(feature = "db", diesel(deserialize_as = NullableTextArray))]
     pub attributes: Option<Vec<Cow<'a, str>>>,
+    /// The labels of the variable's coded values, keyed by code, e.g. `"1"` to
+    /// `"White alone"`. In variables.json, this is the `item` object of the `values` field.
+    /// They're stored in the `variable_values` table rather than in a column.
+    #[serde(borrow, default, deserialize_with = "parse_values")]
+    #[cfg_attr(
+        feature = "db",
+        diesel(
+            skip_insertion,
+            select_expression = diesel::dsl::sql::<diesel::sql_types::Nullable<diesel::sql_types::Jsonb>>(
+                "(SELECT jsonb_object_agg(variable_values.code, variable_values.label) \
+                 FROM variable_values WHERE variable_values.variables_id = variables.id)"
+            ),
+            select_expression_type = diesel::expression::SqlLiteral<diesel::sql_types::Nullable<diesel::sql_types::Jsonb>>,
+            deserialize_as = NullableStringMap
+        )
+    )]
+    pub values: Option<CodeLabels<'a>>,
+}
+
+/// Join the `label` segments with `!!`, as in variables.json. Parsing trims the colon that ends
+/// the segments with children, e.g. "Total:" in "Estimate!!Total:!!Male", which can't be told
+/// from a single label. The segments between the first and the last get a colon, since they
+/// usually have children.
+pub(crate) fn join_label(label: &[Cow<str>]) -> String {
+    label
+        .iter()
+        .enumerate()
+        .map(|(index, segment)| {
+            if index > 0 && index + 1 < label.len() {
+                format!("{}:", segment)
+            } else {
+                segment.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("!!")
+}
+
+/// Serialize a variable as the value of its entry in variables.json. The `name` is the key of
+/// the entry, so it's serialized by `VariablesCollection` rather than here. Missing fields
+/// are omitted.
+impl Serialize for VariablesItem<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        if !self.label.is_empty() {
+            map.serialize_entry("label", &join_label(&self.label))?;
+        }
+        if let Some(concept) = &self.concept {
+            map.serialize_entry("concept", concept)?;
+        }
+        if let Some(required) = &self.required {
+            map.serialize_entry("required", required)?;
+        }
+        if let Some(predicate_type) = &self.predicate_type {
+            map.serialize_entry("predicateType", predicate_type)?;
+        }
+        if let Some(group) = &self.group {
+            map.serialize_entry("group", &group.join(","))?;
+        }
+        if let Some(limit) = self.limit {
+            map.serialize_entry("limit", &limit)?;
+        }
+        if let Some(predicate_only) = self.predicate_only {
+            map.serialize_entry("predicateOnly", &predicate_only)?;
+        }
+        if let Some(attributes) = &self.attributes {
+            map.serialize_entry("attributes", &attributes.join(","))?;
+        }
+        if let Some(values) = &self.values {
+            map.serialize_entry("values", &BTreeMap::from([("item", values)]))?;
+        }
+        map.end()
+    }
+}
+
+/// Serialize the variables as variables.json: a `variables` map keyed by name, in the order
+/// of `variables`.
+impl Serialize for VariablesCollection<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        struct ByName<'c, 'a>(&'c [VariablesItem<'a>]);
+
+        impl Serialize for ByName<'_, '_> {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                serializer.collect_map(self.0.iter().map(|item| (&item.name, item)))
+            }
+        }
+
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry("variables", &ByName(&self.variables))?;
+        map.end()
+    }
+}
+
+impl<'a> VariablesItem<'a> {
+    /// Return the `label` as a path through the label hierarchy.
+    pub fn label_path(&self) -> LabelPath<'_, 'a> {
+        LabelPath::new(&self.label)
+    }
+
+    /// Return a canonicalized copy for deterministic comparison and hashing: `attributes` and
+    /// `group` are sorted and each `label` element is trimmed. The `id` is kept.
+    pub fn normalized(&self) -> VariablesItem<'a> {
+        let sorted = |values: &Option<Vec<Cow<'a, str>>>| {
+            values.clone().map(|mut values| {
+                values.sort();
+                values
+            })
+        };
+        VariablesItem {
+            id: self.id,
+            name: self.name.clone(),
+            label: self
+                .label
+                .iter()
+                .map(|segment| match segment {
+                    Cow::Borrowed(segment) => Cow::Borrowed(segment.trim()),
+                    Cow::Owned(segment) => Cow::Owned(segment.trim().to_string()),
+                })
+                .collect(),
+            concept: self.concept.clone(),
+            required: self.required.clone(),
+            predicate_type: self.predicate_type.clone(),
+            group: sorted(&self.group),
+            limit: self.limit,
+            predicate_only: self.predicate_only,
+            attributes: sorted(&self.attributes),
+            values: self.values.clone(),
+        }
+    }
+
+    /// Compare variables by `name` only, ignoring the database-assigned `id` and the other
+    /// fields. This isn't an `Ord` implementation because `PartialEq` compares every field.
+    pub fn cmp_by_name(&self, other: &Self) -> Ordering {
+        self.name.cmp(&other.name)
+    }
+
+    /// Return a copy that owns all of its strings.
+    pub fn into_owned(self) -> VariablesItem<'static> {
+        let own = |values: Option<Vec<Cow<'a, str>>>| values.map(into_owned_vec);
+        VariablesItem {
+            id: self.id,
+            name: Cow::Owned(self.name.into_owned()),
+            label: into_owned_vec(self.label),
+            concept: self.concept.map(|concept| Cow::Owned(concept.into_owned())),
+            required: self
+                .required
+                .map(|required| Cow::Owned(required.into_owned())),
+            predicate_type: self
+                .predicate_type
+                .map(|predicate_type| Cow::Owned(predicate_type.into_owned())),
+            group: own(self.group),
+            limit: self.limit,
+            predicate_only: self.predicate_only,
+            attributes: own(self.attributes),
+            values: self.values.map(|values| {
+                values
+                    .into_iter()
+                    .map(|(code, label)| {
+                        (
+                            Cow::Owned(code.into_owned()),
+                            Cow::Owned(label.into_owned()),
+                        )
+                    })
+                    .collect()
+            }),
+        }
+    }
+
+    /// Return the first element of `group`, or `None` if there is none. This matches the
+    /// `_first_group` column of the `variables` table.
+    pub fn first_group(&self) -> Option<&str> {
+        self.group.as_ref()?.first().map(|group| group.as_ref())
+    }
+
+    /// Return the hex-encoded MD5 hash of the `concept`, or of the empty string if there is
+    /// none. This matches the `_concept_hash` column of the `variables` table.
+    pub fn concept_hash(&self) -> String {
+        md5_hex(self.concept.as_deref().unwrap_or(""))
+    }
+
+    /// Return the hex-encoded MD5 hash of the sorted, deduplicated `attributes` joined by
+    /// commas, so that the hash doesn't depend on the order of the attributes in
+    /// variables.json, or `None` if there are no attributes. This matches the
+    /// `_attributes_hash` column of the `variables` table.
+    pub fn attributes_hash(&self) -> Option<String> {
+        let mut attributes: Vec<&str> = self
+            .attributes
+            .as_ref()?
+            .iter()
+            .map(|attribute| attribute.as_ref())
+            .collect();
+        attributes.sort_unstable();
+        attributes.dedup();
+        Some(md5_hex(&attributes.join(",")))
+    }
+}
+
+fn into_owned_vec(values: Vec<Cow<'_, str>>) -> Vec<Cow<'static, str>> {
+    values
+        .into_iter()
+        .map(|value| Cow::Owned(value.into_owned()))
+        .collect()
+}
+
+/// Return the hex-encoded MD5 hash of `input`, like PostgreSQL's `md5` function.
+fn md5_hex(input: &str) -> String {
+    format!("{:x}", Md5::digest(input.as_bytes()))
+}
+
+/// A variable's `label` viewed as a hierarchy. The `!!`-separated label
+/// `Estimate!!Total!!Male` is the leaf `Male` whose ancestors are `Estimate` and `Total`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LabelPath<'l, 'a> {
+    segments: &'l [Cow<'a, str>],
+}
+
+impl<'l, 'a> LabelPath<'l, 'a> {
+    pub fn new(segments: &'l [Cow<'a, str>]) -> Self {
+        LabelPath { segments }
+    }
+
+    /// Return the last, most specific element of the label, or `None` if the label is empty.
+    pub fn leaf(&self) -> Option<&'l str> {
+        self.segments.last().map(|segment| segment.as_ref())
+    }
+
+    /// Return the elements above the leaf, starting from the root.
+    pub fn ancestors(&self) -> &'l [Cow<'a, str>] {
+        match self.segments.split_last() {
+            Some((_, ancestors)) => ancestors,
+            None => &[],
+        }
+    }
 }
 
 /// `VariablesCollection` is a parsed variables.json file of an API endpoint.
 ///
 /// The variables.json contains a top-level "variables" key, whose items describe
 /// query parameters of the endpoint. Each item is parsed into a `VariablesItem`
-/// using the `deserialize_variables` function.
+/// using the `deserialize_variables` function. A variables.json without the "variables"
+/// key, e.g. `{}`, has no variables, like a geography.json without "fips".
 #[derive(PartialEq, Deserialize, Debug)]
 pub struct VariablesCollection<'a> {
-    #[serde(borrow, deserialize_with = "deserialize_variables")]
+    #[serde(borrow, default, deserialize_with = "deserialize_variables")]
     pub variables: Vec<VariablesItem<'a>>,
 }
 
+/// The names of the variables that differ between two `VariablesCollection`s, sorted. See
+/// `VariablesCollection::diff`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VariablesDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Variables in both collections whose other fields differ.
+    pub changed: Vec<String>,
+}
+
+/// Whether a parsed `VariablesCollection` borrows from its input where possible, or owns all
+/// of its strings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Borrow strings from the input unless they contain escape sequences.
+    #[default]
+    Borrow,
+    /// Copy all strings, e.g. to keep the variables after the input is dropped.
+    Own,
+}
+
+impl<'a> VariablesCollection<'a> {
+    /// Parse a variables.json, borrowing from `json` or not depending on `mode`.
+    pub fn parse(json: &'a str, mode: ParseMode) -> Result<Self, serde_json::Error> {
+        let collection: VariablesCollection<'a> = serde_json::from_str(json)?;
+        Ok(match mode {
+            ParseMode::Borrow => collection,
+            ParseMode::Own => collection.into_owned(),
+        })
+    }
+
+    /// Sort the variables by name, e.g. to compare two parses element-wise. Variables with the
+    /// same name keep their order.
+    pub fn sort_by_name(&mut self) {
+        self.variables.sort_by(VariablesItem::cmp_by_name);
+    }
+
+    /// Return the names of the variables added, removed and changed from `self` to `other`,
+    /// e.g. from one vintage to the next.
+    ///
+    /// Variables are compared by their `normalized` forms, ignoring the database `id`.
+    pub fn diff(&self, other: &VariablesCollection) -> VariablesDiff {
+        let by_name = |collection: &'_ VariablesCollection<'_>| {
+            collection
+                .variables
+                .iter()
+                .map(|item| {
+                    let mut normalized = item.normalized().into_owned();
+                    normalized.id = 0;
+                    (item.name.to_string(), normalized)
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+        let (before, after) = (by_name(self), by_name(other));
+
+        let mut diff = VariablesDiff::default();
+        for (name, item) in &before {
+            match after.get(name) {
+                None => diff.removed.push(name.clone()),
+                Some(other_item) if other_item != item => diff.changed.push(name.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.added = after
+            .into_keys()
+            .filter(|name| !before.contains_key(name))
+            .collect();
+        diff
+    }
+
+    /// Return a copy that owns all of its strings.
+    pub fn into_owned(self) -> VariablesCollection<'static> {
+        VariablesCollection {
+            variables: self
+                .variables
+                .into_iter()
+                .map(VariablesItem::into_owned)
+                .collect(),
+        }
+    }
+}
+
+impl VariablesCollection<'static> {
+    /// Parse a variables.json that was already deserialized into a `serde_json::Value`.
+    ///
+    /// Strings are moved out of `value`, so the returned collection owns all of its data.
+    pub fn from_value(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        VariablesCollection::deserialize(value)
+    }
+}
+
+/// A variable that `parse_variables_lenient` skipped because it failed to parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWarning {
+    /// The variable's key in the `variables` map.
+    pub name: String,
+    /// Why the variable failed to parse.
+    pub message: String,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "skipped variable '{}': {}", self.name, self.message)
+    }
+}
+
+/// Parse a variables.json like `VariablesCollection`, but skip the variables that fail to
+/// parse rather than failing, so that one malformed variable doesn't lose thousands of good
+/// ones.
+///
+/// # Arguments
+///
+/// * `json` - the contents of a variables.json
+///
+/// # Returns
+///
+/// The variables that parsed, in order, and a warning for each one skipped. An error if `json`
+/// isn't a JSON object with a `variables` map.
+pub fn parse_variables_lenient(
+    json: &str,
+) -> Result<(Vec<VariablesItem<'_>>, Vec<ParseWarning>), serde_json::Error> {
+    let raw: RawVariables = serde_json::from_str(json)?;
+    let mut variables = Vec::new();
+    let mut warnings = Vec::new();
+    for (name, value) in raw.variables {
+        match serde_json::from_str::<VariablesItem>(value.get()) {
+            Ok(item) => variables.push(VariablesItem {
+                name: name.0,
+                ..item
+            }),
+            Err(e) => warnings.push(ParseWarning {
+                name: name.0.into_owned(),
+                message: e.to_string(),
+            }),
+        }
+    }
+    Ok((variables, warnings))
+}
+
+/// Parse a variables.json into its variables. The variables borrow their strings from `json`
+/// when possible; see `VariablesItem::into_owned` to keep them longer.
+///
+/// ```
+/// use us_census::parse_variables::parse_variables;
+///
+/// let json = r#"{
+///   "variables": {
+///     "B01001_001E": {"label": "Estimate!!Total:", "concept": "SEX BY AGE", "group": "B01001"}
+///   }
+/// }"#;
+/// let variables = parse_variables(json).unwrap();
+/// assert_eq!(variables.len(), 1);
+/// assert_eq!(variables[0].name, "B01001_001E");
+/// assert_eq!(variables[0].label, vec!["Estimate", "Total"]);
+/// ```
+pub fn parse_variables(json: &str) -> Result<Vec<VariablesItem<'_>>, serde_json::Error> {
+    Ok(serde_json::from_str::<VariablesCollection>(json)?.variables)
+}
+
+/// An iterator over the variables of a variables.json, parsed one at a time from a reader,
+/// e.g. a file. Unlike `VariablesCollection`, it never holds more than one variable in memory.
+///
+/// Top-level keys before `variables` are skipped; whatever follows the map is not read.
+/// Unlike `VariablesCollection`, a missing `variables` key is an error, so that e.g. a
+/// geography.json served at a variables link isn't ingested as an endpoint without variables.
+/// Iteration stops after the first error.
+pub struct VariablesStream<R> {
+    reader: JsonReader<R>,
+    state: StreamState,
+}
+
+impl<R: BufRead> VariablesStream<R> {
+    pub fn new(reader: R) -> Self {
+        VariablesStream {
+            reader: JsonReader::new(reader),
+            state: StreamState::Start,
+        }
+    }
+
+    /// Parse the next variable, or return `None` at the end of the `variables` map.
+    fn next_item(&mut self) -> Result<Option<VariablesItem<'static>>, serde_json::Error> {
+        if self.state == StreamState::Start {
+            self.reader.seek_key("variables", b'{')?;
+            self.state = StreamState::FirstEntry;
+        }
+        self.reader.skip_whitespace()?;
+        if self.reader.peek_byte()? == Some(b'}') {
+            return Ok(None);
+        }
+        if self.state == StreamState::NextEntry {
+            self.reader.expect_byte(b',')?;
+        }
+        let name: String = self.reader.parse_next()?;
+        self.reader.expect_byte(b':')?;
+        // Parse the value first, since serde_json can't lend strings from a reader.
+        let value: serde_json::Value = self.reader.parse_next()?;
+        let item = VariablesItem::deserialize(value).map_err(|e| variable_error(&name, e))?;
+        let item = VariablesItem {
+            name: Cow::Owned(name),
+            ..item
+        };
+        self.state = StreamState::NextEntry;
+        Ok(Some(item))
+    }
+}
+
+impl<R: BufRead> Iterator for VariablesStream<R> {
+    type Item = Result<VariablesItem<'static>, serde_json::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state == StreamState::Done {
+            return None;
+        }
+        let item = self.next_item().transpose();
+        if !matches!(item, Some(Ok(_))) {
+            self.state = StreamState::Done;
+        }
+        item
+    }
+}
+
 /// Generic Visitor trait for deserializing a string field in `variables.json` into
 /// a list of strings.
 trait StringToVecVisitorConfig {
@@ -94,7 +587,7 @@ static COMMA_REGEX: OnceLock<Regex> = OnceLock::new();
 struct CommaSplitVisitorConfig;
 impl StringToVecVisitorConfig for CommaSplitVisitorConfig {
     const TRIM_CHAR: char = ' ';
-    const DESCRIPTION: &'static str = "comma-separated words";
+    const DESCRIPTION: &'static str = "comma-separated words or an array of strings";
 
     fn get_split_regex() -> &'static Regex {
         COMMA_REGEX
@@ -117,19 +610,14 @@ impl<'de, T: StringToVecVisitorConfig> Visitor<'de> for StringToVecVisitor<T> {
     type Value = Vec<Cow<'de, str>>;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("words separated by '!!:`, '!!', or `:`")
+        formatter.write_str(T::DESCRIPTION)
     }
 
     fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
     where
         E: de::Error,
     {
-        let re = T::get_split_regex();
-        let removed_terminal_colon = v.trim_matches(T::TRIM_CHAR);
-        Ok(re
-            .split(removed_terminal_colon)
-            .map(|s| Cow::Owned(s.to_string()))
-            .collect())
+        Ok(split::<T>(v).map(|s| Cow::Owned(s.to_string())).collect())
     }
 
     /// Same as `visit_str`, but for borrowed strings.
@@ -137,24 +625,82 @@ impl<'de, T: StringToVecVisitorConfig> Visitor<'de> for StringToVecVisitor<T> {
     where
         E: de::Error,
     {
-        let re = T::get_split_regex();
-        let removed_terminal_colon = v.trim_matches(T::TRIM_CHAR);
-        Ok(re
-            .split(removed_terminal_colon)
-            .map(|s| Cow::Borrowed(s))
-            .collect())
+        Ok(split::<T>(v).map(Cow::Borrowed).collect())
+    }
+
+    /// An array's elements are already split, so they're kept as they are. Only reached by
+    /// `deserialize_any`, as in `parse_comma_separated_string`.
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let mut vec = Vec::new();
+        while let Some(CowStr(value)) = seq.next_element()? {
+            vec.push(value);
+        }
+        Ok(vec)
     }
 }
 
-/// Deseralize the `label` field in `variables.json` into a list of strings.
+/// Trim and split `v` as configured by `T`. A string that's empty after trimming has no
+/// elements, rather than a single empty one.
+fn split<T: StringToVecVisitorConfig>(v: &str) -> impl Iterator<Item = &str> {
+    let trimmed = v.trim_matches(T::TRIM_CHAR);
+    (!trimmed.is_empty())
+        .then(|| T::get_split_regex().split(trimmed))
+        .into_iter()
+        .flatten()
+}
+
+/// Deseralize the `label` field in `variables.json` into a list of strings. `null` is
+/// deserialized into an empty list.
 fn parse_label<'de, D>(deserializer: D) -> Result<Vec<Cow<'de, str>>, D::Error>
 where
     D: Deserializer<'de>,
 {
     let visitor = StringToVecVisitor::<LabelVisitorConfig>::new();
-    deserializer.deserialize_str(visitor)
+    deserializer.deserialize_option(NullAsEmptyVisitor(visitor))
+}
+
+/// Visitor that deserializes `null` into an empty list, and anything else as a string with the
+/// wrapped visitor.
+struct NullAsEmptyVisitor<V>(V);
+
+impl<'de, V> Visitor<'de> for NullAsEmptyVisitor<V>
+where
+    V: Visitor<'de, Value = Vec<Cow<'de, str>>>,
+{
+    type Value = Vec<Cow<'de, str>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.0.expecting(formatter)?;
+        formatter.write_str(" or null")
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(Vec::new())
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(Vec::new())
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(self.0)
+    }
 }
 
+/// Deserialize the `group` or `attributes` field in `variables.json`, a comma-separated string
+/// in most datasets and an array of strings in some newer ones, into a list of strings.
 fn parse_comma_separated_string<'de, D>(
     deserializer: D,
 ) -> Result<Option<Vec<Cow<'de, str>>>, D::Error>
@@ -162,10 +708,66 @@ where
     D: Deserializer<'de>,
 {
     let visitor = StringToVecVisitor::<CommaSplitVisitorConfig>::new();
-    let deserialization_result = deserializer.deserialize_str(visitor)?;
+    let deserialization_result = deserializer.deserialize_any(visitor)?;
     Ok(Some(deserialization_result))
 }
 
+/// Visitor for the `item` object of a variable's `values`, which maps codes to labels.
+struct ValueLabelsVisitor;
+
+impl<'de> Visitor<'de> for ValueLabelsVisitor {
+    type Value = CodeLabels<'de>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map of codes to labels")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let mut labels = BTreeMap::new();
+        while let Some((code, label)) = map.next_entry::<CowStr<'de>, CowStr<'de>>()? {
+            labels.insert(code.0, label.0);
+        }
+        Ok(labels)
+    }
+}
+
+/// The code-to-label map of a variable's `values`. See `ValueLabelsVisitor`.
+struct ValueLabels<'a>(CodeLabels<'a>);
+
+impl<'de: 'a, 'a> Deserialize<'de> for ValueLabels<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer
+            .deserialize_map(ValueLabelsVisitor)
+            .map(ValueLabels)
+    }
+}
+
+/// The `values` field of a variable, e.g.
+/// `{"item": {"1": "White alone", "2": "Black or African American alone"}}`. Only the
+/// `item` object is kept; other kinds of values, such as `range`, are ignored.
+#[derive(Deserialize)]
+struct Values<'a> {
+    #[serde(borrow)]
+    item: Option<ValueLabels<'a>>,
+}
+
+/// Deserialize the `values` field in `variables.json` into a map of codes to labels, or
+/// `None` if it's `null` or has no `item` object.
+fn parse_values<'de, D>(deserializer: D) -> Result<Option<CodeLabels<'de>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<Values>::deserialize(deserializer)?
+        .and_then(|values| values.item)
+        .map(|labels| labels.0))
+}
+
 struct VariablesItemVisitor;
 
 impl<'de> Visitor<'de> for VariablesItemVisitor {
@@ -182,10 +784,12 @@ impl<'de> Visitor<'de> for VariablesItemVisitor {
         A: de::MapAccess<'de>,
     {
         let mut variables = Vec::new();
-        while let Some((key, value)) = map.next_entry::<&'de str, VariablesItem<'de>>()? {
+        while let Some(key) = map.next_key::<CowStr<'de>>()? {
+            let value: VariablesItem<'de> =
+                map.next_value().map_err(|e| variable_error(&key.0, e))?;
             variables.push(VariablesItem {
                 id: value.id,
-                name: Cow::from(key),
+                name: key.0,
                 label: value.label,
                 concept: value.concept,
                 required: value.required,
@@ -194,12 +798,57 @@ impl<'de> Visitor<'de> for VariablesItemVisitor {
                 limit: value.limit,
                 predicate_only: value.predicate_only,
                 attributes: value.attributes,
+                values: value.values,
             });
         }
         Ok(variables)
     }
 }
 
+/// The `variables` map of a variables.json, with each variable left unparsed. See
+/// `parse_variables_lenient`.
+#[derive(Deserialize)]
+struct RawVariables<'a> {
+    #[serde(borrow, default, deserialize_with = "deserialize_raw_variables")]
+    variables: Vec<(CowStr<'a>, &'a RawValue)>,
+}
+
+struct RawVariablesVisitor;
+
+impl<'de> Visitor<'de> for RawVariablesVisitor {
+    type Value = Vec<(CowStr<'de>, &'de RawValue)>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map of variables")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let mut variables = Vec::new();
+        while let Some(entry) = map.next_entry()? {
+            variables.push(entry);
+        }
+        Ok(variables)
+    }
+}
+
+fn deserialize_raw_variables<'de, D>(
+    deserializer: D,
+) -> Result<Vec<(CowStr<'de>, &'de RawValue)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_map(RawVariablesVisitor)
+}
+
+/// Return an error naming the variable that failed to parse, so that one malformed variable
+/// among thousands can be found.
+fn variable_error<E: de::Error>(name: &str, error: impl fmt::Display) -> E {
+    E::custom(format_args!("error parsing variable '{}': {}", name, error))
+}
+
 /// Deserialize the `variables` field in variables.json into a list of `VariablesItem`.
 /// See `VariablesItemVisitor.visit_map` for details.
 fn deserialize_variables<'de, D>(deserializer: D) -> Result<Vec<VariablesItem<'de>>, D::Error>
@@ -212,6 +861,7 @@ where
 #[cfg(test)]
 mod test {
     use super::*;
+    use rstest::rstest;
     #[test]
     fn test_parse_backslashes() {
         let object_under_test = r#"
@@ -234,7 +884,7 @@ mod test {
       }
     }"#;
         let result: VariablesCollection =
-            serde_json::from_str(&object_under_test).expect("Error parsing JSON");
+            serde_json::from_str(object_under_test).expect("Error parsing JSON");
         let expected = VariablesCollection {
             variables: vec![
                 VariablesItem {
@@ -243,7 +893,7 @@ mod test {
                     label: vec![Cow::from("foo"), Cow::from("bar"), Cow::from(" \"baz\"")],
                     concept: None,
                     required: None,
-                    predicate_type: Option::from("int"),
+                    predicate_type: Some(Cow::from("int")),
                     group: Option::from(vec![
                         Cow::from("g1"),
                         Cow::from("g2"),
@@ -253,6 +903,7 @@ mod test {
                     limit: Option::from(0),
                     predicate_only: None,
                     attributes: Option::from(vec![Cow::from("A"), Cow::from("B"), Cow::from("C")]),
+                    values: None,
                 },
                 VariablesItem {
                     id: 0,
@@ -260,11 +911,12 @@ mod test {
                     label: vec![Cow::from("qux"), Cow::from("quux"), Cow::from("corge")],
                     concept: None,
                     required: None,
-                    predicate_type: Option::from("int"),
+                    predicate_type: Some(Cow::from("int")),
                     group: Option::from(vec![Cow::from("g2")]),
                     limit: Option::from(0),
                     predicate_only: None,
                     attributes: Option::from(vec![Cow::from("D"), Cow::from("E"), Cow::from("F")]),
+                    values: None,
                 },
             ],
         };
@@ -278,5 +930,624 @@ mod test {
         matches!(b_item.label[0], Cow::Borrowed(_));
         matches!(b_item.label[1], Cow::Borrowed(_));
         matches!(b_item.label[2], Cow::Borrowed(_));
+        assert!(matches!(a_item.name, Cow::Borrowed(_)));
+        assert!(matches!(a_item.predicate_type, Some(Cow::Borrowed(_))));
+    }
+
+    #[test]
+    fn test_from_value() {
+        let value = serde_json::json!({
+            "variables": {
+                "B01001_002E": {
+                    "label": "Estimate!!Total:!!Male:",
+                    "concept": "SEX BY AGE",
+                    "predicateType": "int",
+                    "group": "B01001",
+                    "limit": 0,
+                    "attributes": "B01001_002EA,B01001_002M"
+                }
+            }
+        });
+
+        let result = VariablesCollection::from_value(value).expect("Error parsing JSON value");
+
+        let expected = VariablesCollection {
+            variables: vec![VariablesItem {
+                id: 0,
+                name: Cow::from("B01001_002E"),
+                label: vec![Cow::from("Estimate"), Cow::from("Total"), Cow::from("Male")],
+                concept: Some(Cow::from("SEX BY AGE")),
+                required: None,
+                predicate_type: Some(Cow::from("int")),
+                group: Some(vec![Cow::from("B01001")]),
+                limit: Some(0),
+                predicate_only: None,
+                attributes: Some(vec![Cow::from("B01001_002EA"), Cow::from("B01001_002M")]),
+                values: None,
+            }],
+        };
+        assert_eq!(result, expected);
+        assert!(matches!(result.variables[0].name, Cow::Owned(_)));
+    }
+
+    #[test]
+    fn test_label_path() {
+        let label = vec![Cow::from("Estimate"), Cow::from("Total"), Cow::from("Male")];
+
+        let label_path = LabelPath::new(&label);
+
+        assert_eq!(label_path.leaf(), Some("Male"));
+        assert_eq!(
+            label_path.ancestors(),
+            &[Cow::from("Estimate"), Cow::from("Total")]
+        );
+    }
+
+    #[test]
+    fn test_label_path_single_segment() {
+        let label = vec![Cow::from("Geography")];
+
+        let label_path = LabelPath::new(&label);
+
+        assert_eq!(label_path.leaf(), Some("Geography"));
+        assert!(label_path.ancestors().is_empty());
+    }
+
+    #[test]
+    fn test_label_path_empty() {
+        let label_path = LabelPath::new(&[]);
+
+        assert_eq!(label_path.leaf(), None);
+        assert!(label_path.ancestors().is_empty());
+    }
+
+    #[test]
+    fn test_variables_item_label_path() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "B01001_002E": {
+          "label": "Estimate!!Total:!!Male:",
+          "predicateType": "int",
+          "group": "B01001",
+          "limit": 0
+        }
+      }
+    }"#;
+        let result: VariablesCollection =
+            serde_json::from_str(object_under_test).expect("Error parsing JSON");
+
+        let label_path = result.variables[0].label_path();
+
+        assert_eq!(label_path.leaf(), Some("Male"));
+        assert_eq!(
+            label_path.ancestors(),
+            &[Cow::from("Estimate"), Cow::from("Total")]
+        );
+    }
+
+    #[test]
+    fn test_normalized_ignores_attribute_order() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "a": {
+          "label": "Estimate !!Total",
+          "concept": "SEX BY AGE",
+          "group": "g2,g1",
+          "attributes": "B,A,C"
+        },
+        "b": {
+          "label": "Estimate!! Total",
+          "concept": "SEX BY AGE",
+          "group": "g1,g2",
+          "attributes": "C,B,A"
+        }
+      }
+    }"#;
+        let result: VariablesCollection =
+            serde_json::from_str(object_under_test).expect("Error parsing JSON");
+        let (mut first, second) = (
+            result.variables[0].normalized(),
+            result.variables[1].normalized(),
+        );
+        // Only the names differ.
+        first.name = second.name.clone();
+
+        assert_eq!(first, second);
+        assert_eq!(first.label, vec!["Estimate", "Total"]);
+        assert_eq!(
+            result.variables[0].attributes_hash(),
+            result.variables[1].attributes_hash()
+        );
+        // md5("A,B,C")
+        assert_eq!(
+            result.variables[0].attributes_hash().unwrap(),
+            "ce04be1226e56f48da55b6c130d45b94"
+        );
+        assert_eq!(
+            result.variables[0].concept_hash(),
+            result.variables[1].concept_hash()
+        );
+    }
+
+    #[test]
+    fn test_parse_values() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "RAC1P": {
+          "label": "Recoded detailed race code",
+          "predicateType": "string",
+          "group": "N/A",
+          "limit": 0,
+          "values": {
+            "item": {
+              "1": "White alone",
+              "2": "Black or African American alone",
+              "3": "American Indian alone",
+              "9": "Two or More Races"
+            }
+          }
+        },
+        "AGEP": {
+          "label": "Age",
+          "predicateType": "int",
+          "group": "N/A",
+          "limit": 0,
+          "values": {"range": [{"min": "0", "max": "99", "description": "Age"}]}
+        },
+        "B01001_001E": {"label": "Estimate!!Total:", "group": "B01001", "values": null},
+        "NAME": {"label": "Geographic Area Name", "group": "N/A"}
+      }
+    }"#;
+
+        let result: VariablesCollection =
+            serde_json::from_str(object_under_test).expect("Error parsing JSON");
+
+        let race = result.variables[0].values.as_ref().unwrap();
+        assert_eq!(
+            race.iter()
+                .map(|(code, label)| (code.as_ref(), label.as_ref()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("1", "White alone"),
+                ("2", "Black or African American alone"),
+                ("3", "American Indian alone"),
+                ("9", "Two or More Races"),
+            ]
+        );
+        assert!(matches!(race["1"], Cow::Borrowed(_)));
+        for item in &result.variables[1..] {
+            assert_eq!(item.values, None, "{}", item.name);
+        }
+    }
+
+    #[test]
+    fn test_null_or_missing_label() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "for": {"label": null, "concept": "Census API Geography Specification", "group": "N/A"},
+        "in": {"concept": "Census API Geography Specification", "group": "N/A"}
+      }
+    }"#;
+
+        let result: VariablesCollection =
+            serde_json::from_str(object_under_test).expect("Error parsing JSON");
+
+        assert_eq!(result.variables.len(), 2);
+        for item in &result.variables {
+            assert!(item.label.is_empty(), "{}", item.name);
+        }
+    }
+
+    #[test]
+    fn test_first_group() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "several": {"label": "Estimate", "group": "g1,g2,g3"},
+        "empty": {"label": "Estimate", "group": ""}
+      }
+    }"#;
+
+        let result: VariablesCollection =
+            serde_json::from_str(object_under_test).expect("Error parsing JSON");
+
+        let first_groups: Vec<Option<&str>> = result
+            .variables
+            .iter()
+            .map(VariablesItem::first_group)
+            .collect();
+        assert_eq!(first_groups, vec![Some("g1"), None]);
+    }
+
+    #[test]
+    fn test_attributes_hash() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "sorted": {"label": "Estimate", "group": "g1", "attributes": "A,B,C"},
+        "permuted": {"label": "Estimate", "group": "g1", "attributes": "C,A,B"},
+        "duplicated": {"label": "Estimate", "group": "g1", "attributes": "B,C,A,C"},
+        "missing": {"label": "Estimate", "group": "g1"}
+      }
+    }"#;
+
+        let result: VariablesCollection =
+            serde_json::from_str(object_under_test).expect("Error parsing JSON");
+
+        let hashes: Vec<Option<String>> = result
+            .variables
+            .iter()
+            .map(VariablesItem::attributes_hash)
+            .collect();
+        let abc = Some("ce04be1226e56f48da55b6c130d45b94".to_string());
+        assert_eq!(hashes, vec![abc.clone(), abc.clone(), abc, None]);
+    }
+
+    #[test]
+    fn test_group_error_describes_comma_separated_format() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "a": {
+          "label": "Estimate!!Total",
+          "group": {"name": "B01001"}
+        }
+      }
+    }"#;
+
+        let error = serde_json::from_str::<VariablesCollection>(object_under_test).unwrap_err();
+
+        assert!(
+            error.to_string().contains("comma-separated words"),
+            "{}",
+            error
+        );
+        assert!(!error.to_string().contains("!!"), "{}", error);
+    }
+
+    /// `group` and `attributes` parse the same from a comma-separated string and an array.
+    #[rstest]
+    #[case::group("group")]
+    #[case::attributes("attributes")]
+    fn test_comma_separated_or_array(#[case] field: &str) {
+        // Arrange
+        let variable = |value: serde_json::Value| {
+            let mut variable = serde_json::json!({"label": "Estimate!!Total", "group": "B01001"});
+            variable[field] = value;
+            serde_json::json!({ "variables": { "B01001_001E": variable } }).to_string()
+        };
+        let string_form = variable(serde_json::json!("A,B,C"));
+        let array_form = variable(serde_json::json!(["A", "B", "C"]));
+
+        // Act
+        let from_string: VariablesCollection = serde_json::from_str(&string_form).unwrap();
+        let from_array: VariablesCollection = serde_json::from_str(&array_form).unwrap();
+
+        // Assert
+        assert_eq!(from_string, from_array);
+        let expected = Some(vec![Cow::from("A"), Cow::from("B"), Cow::from("C")]);
+        let item = &from_array.variables[0];
+        let parsed = if field == "group" {
+            &item.group
+        } else {
+            &item.attributes
+        };
+        assert_eq!(parsed, &expected);
+    }
+
+    #[test]
+    fn test_error_names_variable() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "B01001_001E": {"label": "Estimate!!Total:", "group": "B01001"},
+        "B01001_002E": {"label": "Estimate!!Total:!!Male:", "group": 1},
+        "B01001_026E": {"label": "Estimate!!Total:!!Female:", "group": "B01001"}
+      }
+    }"#;
+
+        let error = serde_json::from_str::<VariablesCollection>(object_under_test).unwrap_err();
+        let stream_error = VariablesStream::new(object_under_test.as_bytes())
+            .find_map(Result::err)
+            .unwrap();
+
+        for error in [error, stream_error] {
+            assert!(
+                error
+                    .to_string()
+                    .starts_with("error parsing variable 'B01001_002E': "),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_variables_lenient() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "B01001_001E": {"label": "Estimate!!Total:", "group": "B01001"},
+        "B01001_002E": {"label": "Estimate!!Total:!!Male:", "group": 1},
+        "B01001_026E": {"label": "Estimate!!Total:!!Female:", "group": "B01001"}
+      }
+    }"#;
+
+        let (variables, warnings) = parse_variables_lenient(object_under_test).unwrap();
+
+        let names: Vec<&str> = variables.iter().map(|v| v.name.as_ref()).collect();
+        assert_eq!(names, vec!["B01001_001E", "B01001_026E"]);
+        assert_eq!(variables[1].label, vec!["Estimate", "Total", "Female"]);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].name, "B01001_002E");
+        assert!(
+            warnings[0].message.contains("comma-separated words"),
+            "{}",
+            warnings[0]
+        );
+    }
+
+    #[test]
+    fn test_parse_modes() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "B01001_002E": {
+          "label": "Estimate!!Total:!!Male:",
+          "concept": "SEX BY AGE",
+          "predicateType": "int",
+          "group": "B01001",
+          "limit": 0,
+          "attributes": "B01001_002EA,B01001_002M"
+        }
+      }
+    }"#;
+
+        let borrowed = VariablesCollection::parse(object_under_test, ParseMode::Borrow).unwrap();
+        let owned = VariablesCollection::parse(object_under_test, ParseMode::Own).unwrap();
+
+        assert_eq!(borrowed, owned);
+        assert!(matches!(borrowed.variables[0].name, Cow::Borrowed(_)));
+        assert!(matches!(borrowed.variables[0].label[0], Cow::Borrowed(_)));
+        let owned = &owned.variables[0];
+        assert!(matches!(owned.name, Cow::Owned(_)));
+        assert!(owned.label.iter().all(|s| matches!(s, Cow::Owned(_))));
+        assert!(matches!(owned.concept, Some(Cow::Owned(_))));
+        assert!(owned
+            .attributes
+            .iter()
+            .flatten()
+            .all(|s| matches!(s, Cow::Owned(_))));
+    }
+
+    #[test]
+    fn test_sort_by_name() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "B01001_002E": {"label": "Estimate!!Total:!!Male:", "group": "B01001"},
+        "AIANHH": {"label": "Geography", "group": "N/A"},
+        "B01001_001E": {"label": "Estimate!!Total:", "group": "B01001"}
+      }
+    }"#;
+        let mut result: VariablesCollection =
+            serde_json::from_str(object_under_test).expect("Error parsing JSON");
+
+        result.sort_by_name();
+
+        let names: Vec<&str> = result.variables.iter().map(|v| v.name.as_ref()).collect();
+        assert_eq!(names, vec!["AIANHH", "B01001_001E", "B01001_002E"]);
+    }
+
+    #[test]
+    fn test_cmp_by_name_ignores_other_fields() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "a": {"label": "Estimate!!Total", "group": "B01001"}
+      }
+    }"#;
+        let first: VariablesCollection =
+            serde_json::from_str(object_under_test).expect("Error parsing JSON");
+        let mut second: VariablesCollection =
+            serde_json::from_str(object_under_test).expect("Error parsing JSON");
+        second.variables[0].id = 42;
+        second.variables[0].label = vec![Cow::from("Other")];
+
+        assert_eq!(
+            first.variables[0].cmp_by_name(&second.variables[0]),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn test_empty_strings_have_no_elements() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "empty": {"label": "", "group": "", "attributes": ""},
+        "trimmed": {"label": ":", "group": " ", "attributes": "  "}
+      }
+    }"#;
+
+        let result: VariablesCollection =
+            serde_json::from_str(object_under_test).expect("Error parsing JSON");
+
+        for item in &result.variables {
+            assert!(item.label.is_empty(), "{}", item.name);
+            assert_eq!(item.group, Some(vec![]), "{}", item.name);
+            assert_eq!(item.attributes, Some(vec![]), "{}", item.name);
+            assert!(item.label_path().leaf().is_none(), "{}", item.name);
+        }
+    }
+
+    #[test]
+    fn test_limit() {
+        let object_under_test = r#"
+    {
+      "variables": {
+        "null": {"label": "Estimate!!Total", "group": "N/A", "limit": null},
+        "zero": {"label": "Estimate!!Total", "group": "N/A", "limit": 0},
+        "string": {"label": "Estimate!!Total", "group": "N/A", "limit": "0"},
+        "positive": {"label": "Estimate!!Total", "group": "N/A", "limit": 7},
+        "beyond_i16": {"label": "Estimate!!Total", "group": "N/A", "limit": 40000},
+        "missing": {"label": "Estimate!!Total", "group": "N/A"}
+      }
+    }"#;
+
+        let result: VariablesCollection =
+            serde_json::from_str(object_under_test).expect("Error parsing JSON");
+
+        let limits: Vec<(&str, Option<i32>)> = result
+            .variables
+            .iter()
+            .map(|v| (v.name.as_ref(), v.limit))
+            .collect();
+        assert_eq!(
+            limits,
+            vec![
+                ("null", None),
+                ("zero", Some(0)),
+                ("string", Some(0)),
+                ("positive", Some(7)),
+                ("beyond_i16", Some(40000)),
+                ("missing", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = r#"
+    {
+      "variables": {
+        "kept": {"label": "Estimate!!Total", "group": "B01001", "attributes": "A,B"},
+        "relabeled": {"label": "Estimate!!Total", "group": "B01001"},
+        "removed": {"label": "Estimate!!Total", "group": "B01001"}
+      }
+    }"#;
+        let after = r#"
+    {
+      "variables": {
+        "added": {"label": "Estimate!!Total", "group": "B01001"},
+        "kept": {"label": "Estimate!!Total", "group": "B01001", "attributes": "B,A"},
+        "relabeled": {"label": "Estimate!!Total!!Male", "group": "B01001"}
+      }
+    }"#;
+        let before: VariablesCollection = serde_json::from_str(before).expect("Error parsing JSON");
+        let after: VariablesCollection = serde_json::from_str(after).expect("Error parsing JSON");
+
+        let diff = before.diff(&after);
+
+        assert_eq!(
+            diff,
+            VariablesDiff {
+                added: vec!["added".to_string()],
+                removed: vec!["removed".to_string()],
+                changed: vec!["relabeled".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_variables_stream() {
+        // Arrange
+        let variables_json = r#"
+    {
+      "variables": {
+        "for": {"label": "Census API FIPS 'for' clause", "group": "N/A", "predicateOnly": true},
+        "B01001_002E": {
+          "label": "Estimate!!Total:!!Male:",
+          "concept": "SEX BY AGE",
+          "predicateType": "int",
+          "group": "B01001",
+          "limit": 0,
+          "attributes": "B01001_002EA,B01001_002M"
+        },
+        "SEX": {"label": "Sex", "group": "N/A", "values": {"item": {"1": "Male", "2": "Female"}}}
+      }
+    }"#;
+
+        // Act
+        let streamed: Vec<VariablesItem> = VariablesStream::new(variables_json.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        // Assert
+        let parsed: VariablesCollection = serde_json::from_str(variables_json).unwrap();
+        assert_eq!(streamed, parsed.variables);
+    }
+
+    #[rstest]
+    #[case::missing_variables("{}", &[])]
+    #[case::empty_variables(r#"{"variables": {}}"#, &[])]
+    #[case::populated(
+        r#"{"variables": {"NAME": {"label": "Geographic Area Name", "group": "N/A"}, "GEO_ID": {"label": "Geography", "group": "N/A"}}}"#,
+        &["NAME", "GEO_ID"]
+    )]
+    fn test_empty_collection(#[case] variables_json: &str, #[case] expected_names: &[&str]) {
+        // Act
+        let collection: VariablesCollection = serde_json::from_str(variables_json).unwrap();
+        let (lenient, warnings) = parse_variables_lenient(variables_json).unwrap();
+
+        // Assert
+        let names: Vec<&str> = collection
+            .variables
+            .iter()
+            .map(|v| v.name.as_ref())
+            .collect();
+        assert_eq!(names, expected_names);
+        assert_eq!(lenient, collection.variables);
+        assert!(warnings.is_empty());
+    }
+
+    #[rstest]
+    #[case::empty(r#"{"variables": {}}"#, 0)]
+    #[case::missing_variables(r#"{"fips": []}"#, 1)]
+    #[case::truncated(r#"{"variables": {"NAME": {"label": "Geographic Area Name""#, 1)]
+    fn test_variables_stream_stops(#[case] variables_json: &str, #[case] expected_errors: usize) {
+        // Act
+        let items: Vec<_> = VariablesStream::new(variables_json.as_bytes()).collect();
+
+        // Assert
+        assert_eq!(items.len(), expected_errors);
+        assert!(items.iter().all(Result::is_err));
+    }
+
+    /// Serializing a collection and parsing it again returns the same collection.
+    #[rstest]
+    #[case::fixture(include_str!("../tests/fixtures/variables.json"))]
+    #[case::values(
+        r#"{"variables": {"SEX": {"label": "Sex", "group": "N/A", "values": {"item": {"1": "Male", "2": "Female"}}}}}"#
+    )]
+    fn test_serialize_round_trip(#[case] variables_json: &str) {
+        // Arrange
+        let parsed: VariablesCollection = serde_json::from_str(variables_json).unwrap();
+
+        // Act
+        let serialized = serde_json::to_string(&parsed).unwrap();
+
+        // Assert
+        let reparsed: VariablesCollection = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(reparsed, parsed);
+    }
+
+    #[rstest]
+    #[case::single(&["Geographic Area Name"], "Geographic Area Name")]
+    #[case::two(&["Estimate", "Total"], "Estimate!!Total")]
+    #[case::nested(&["Estimate", "Total", "Male", "Under 5 years"], "Estimate!!Total:!!Male:!!Under 5 years")]
+    fn test_join_label(#[case] segments: &[&str], #[case] expected: &str) {
+        // Arrange
+        let label: Vec<Cow<str>> = segments.iter().map(|&segment| Cow::from(segment)).collect();
+
+        // Act
+        let joined = join_label(&label);
+
+        // Assert
+        assert_eq!(joined, expected);
     }
 }
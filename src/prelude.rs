@@ -0,0 +1,38 @@
+//! The commonly used types and functions, so that `use us_census::prelude::*;` is enough for
+//! typical use.
+pub use crate::models::{catalog_duplicate_links, ApiPaths, CatalogStream, UsCensusApisResponse};
+pub use crate::parse_geography::{
+    parse_geography, DatePrecision, GeographyCollection, GeographyItem, GeographyLimit,
+    ReferenceDate,
+};
+pub use crate::parse_variables::{
+    parse_variables, parse_variables_lenient, CodeLabels, LabelPath, ParseMode, ParseWarning,
+    VariablesCollection, VariablesDiff, VariablesItem, VariablesStream,
+};
+
+#[cfg(feature = "db")]
+pub use crate::constraints::{get_unique_constraints, get_unique_constraints_with_columns};
+#[cfg(feature = "db")]
+pub use crate::export::{export_geography_csv, export_variables_csv, ExportError};
+#[cfg(feature = "db")]
+pub use crate::query::{
+    api_paths_without_variables, associations_outside, available_api_paths, filter_api_paths,
+    geographies_for, geographies_requiring, pending_api_paths, search_variables, variables_for,
+};
+#[cfg(feature = "db")]
+pub use crate::{
+    establish_database_connection, establish_pool, establish_read_database_connection,
+    establish_with_retry, ConnectError,
+};
+
+#[cfg(feature = "net")]
+pub use crate::fetch_api_metadata::{CachedClient, FetchError};
+#[cfg(feature = "net")]
+pub use crate::ingest::{
+    ingest_api_paths, ingest_datasets, ingest_survey, insert_variables_and_geography_for_api_path,
+    sync_api_paths, IngestOptions, IngestReport, InsertError, ProgressEvent,
+};
+#[cfg(feature = "async-db")]
+pub use crate::{
+    establish_async_database_connection, insert_variables_and_geography_for_api_path_async,
+};
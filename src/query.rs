@@ -0,0 +1,624 @@
+//! Read what was ingested from the database.
+//!
+//! These functions only read, so they can run on a read replica. See
+//! `establish_read_database_connection`.
+use crate::models::ApiPaths;
+use crate::parse_geography::GeographyItem;
+use crate::parse_variables::VariablesItem;
+use diesel::dsl::{exists, not, sql};
+use diesel::expression::functions::define_sql_function;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use diesel::sql_types::{Array, Bool, Float, Nullable, Text};
+use serde_json::json;
+
+/// Return the geographies that require `parent`, e.g. "state" for counties.
+///
+/// # Arguments
+///
+/// * `conn` - the connection to the database
+/// * `parent` - the name of the required geography
+pub fn geographies_requiring(
+    conn: &mut PgConnection,
+    parent: &str,
+) -> Result<Vec<GeographyItem<'static>>, DieselError> {
+    use crate::schema::geography::dsl::*;
+
+    geography
+        .filter(requires_jsonb.contains(json!([parent])))
+        .select(GeographyItem::as_select())
+        .order(id)
+        .load::<GeographyItem<'static>>(conn)
+}
+
+/// Return the API paths that have no associated variables, e.g. because their ingestion
+/// failed, ordered by primary key.
+///
+/// # Arguments
+///
+/// * `conn` - the connection to the database
+pub fn api_paths_without_variables(
+    conn: &mut PgConnection,
+) -> Result<Vec<ApiPaths<'static>>, DieselError> {
+    use crate::schema::api_paths;
+    use crate::schema::api_paths_variables_association as association;
+
+    api_paths::table
+        .filter(not(exists(
+            association::table.filter(association::api_paths_id.eq(api_paths::id)),
+        )))
+        .select(ApiPaths::as_select())
+        .order(api_paths::id)
+        .load::<ApiPaths<'static>>(conn)
+}
+
+/// Return the API paths that are worth ingesting, ordered by primary key: those whose dataset
+/// isn't marked unavailable. See `ApiPaths::is_available`.
+///
+/// # Arguments
+///
+/// * `conn` - the connection to the database
+pub fn available_api_paths(conn: &mut PgConnection) -> Result<Vec<ApiPaths<'static>>, DieselError> {
+    use crate::schema::api_paths;
+
+    api_paths::table
+        .filter(api_paths::c_is_available.is_distinct_from(false))
+        .select(ApiPaths::as_select())
+        .order(api_paths::id)
+        .load::<ApiPaths<'static>>(conn)
+}
+
+/// Return the API paths whose ingestion hasn't completed, i.e. that have no row in the
+/// `ingest_state` table, ordered by primary key. An interrupted ingestion resumes with these.
+///
+/// # Arguments
+///
+/// * `conn` - the connection to the database
+pub fn pending_api_paths(conn: &mut PgConnection) -> Result<Vec<ApiPaths<'static>>, DieselError> {
+    use crate::schema::{api_paths, ingest_state};
+
+    api_paths::table
+        .filter(not(exists(
+            ingest_state::table.filter(ingest_state::api_paths_id.eq(api_paths::id)),
+        )))
+        .select(ApiPaths::as_select())
+        .order(api_paths::id)
+        .load::<ApiPaths<'static>>(conn)
+}
+
+define_sql_function! {
+    /// Postgres' `array_to_string`, which joins the non-null elements of an array.
+    fn array_to_string(array: Array<Nullable<Text>>, delimiter: Text) -> Text;
+}
+
+/// Return the API paths whose dataset and vintage match, ordered by primary key.
+///
+/// # Arguments
+///
+/// * `conn` - the connection to the database
+/// * `dataset` - a case-insensitive `LIKE` pattern matched against the elements of
+///   `c_dataset` joined by `/`, e.g. "acs/acs_" or "timeseries/%". `None` matches any dataset.
+/// * `vintage` - the `c_vintage` to match. `None` matches any vintage.
+pub fn filter_api_paths(
+    conn: &mut PgConnection,
+    dataset: Option<&str>,
+    vintage: Option<i32>,
+) -> Result<Vec<ApiPaths<'static>>, DieselError> {
+    use crate::schema::api_paths;
+
+    let mut query = api_paths::table
+        .select(ApiPaths::as_select())
+        .order(api_paths::id)
+        .into_boxed();
+    if let Some(dataset) = dataset {
+        query = query.filter(array_to_string(api_paths::c_dataset, "/").ilike(dataset));
+    }
+    if let Some(vintage) = vintage {
+        query = query.filter(api_paths::c_vintage.eq(vintage));
+    }
+    query.load::<ApiPaths<'static>>(conn)
+}
+
+/// Return the variables of the API paths of a dataset and vintage, e.g. "acs5" and 2020,
+/// ordered by name.
+///
+/// # Arguments
+///
+/// * `conn` - the connection to the database
+/// * `dataset` - an element of the API paths' `c_dataset`
+/// * `vintage` - the API paths' `c_vintage`
+pub fn variables_for(
+    conn: &mut PgConnection,
+    dataset: &str,
+    vintage: i32,
+) -> Result<Vec<VariablesItem<'static>>, DieselError> {
+    use crate::schema::api_paths_variables_association as association;
+    use crate::schema::{api_paths, variables};
+
+    // A subquery rather than a join, so that a variable shared by several matching API paths
+    // is returned once.
+    let api_path_ids = api_paths::table
+        .filter(api_paths::c_dataset.contains(vec![Some(dataset)]))
+        .filter(api_paths::c_vintage.eq(vintage))
+        .select(api_paths::id);
+    variables::table
+        .filter(
+            variables::id.eq_any(
+                association::table
+                    .filter(association::api_paths_id.eq_any(api_path_ids))
+                    .select(association::variables_id),
+            ),
+        )
+        .select(VariablesItem::as_select())
+        .order((variables::name, variables::id))
+        .load::<VariablesItem<'static>>(conn)
+}
+
+/// Return the geographies of the API paths of a dataset and vintage, e.g. the levels that can
+/// be requested from "acs5" in 2020, ordered by `geo_level_id`.
+///
+/// # Arguments
+///
+/// * `conn` - the connection to the database
+/// * `dataset` - an element of the API paths' `c_dataset`
+/// * `vintage` - the API paths' `c_vintage`
+pub fn geographies_for(
+    conn: &mut PgConnection,
+    dataset: &str,
+    vintage: i32,
+) -> Result<Vec<GeographyItem<'static>>, DieselError> {
+    use crate::schema::api_paths_geography_association as association;
+    use crate::schema::{api_paths, geography};
+
+    // A subquery rather than a join, like in `variables_for`.
+    let api_path_ids = api_paths::table
+        .filter(api_paths::c_dataset.contains(vec![Some(dataset)]))
+        .filter(api_paths::c_vintage.eq(vintage))
+        .select(api_paths::id);
+    geography::table
+        .filter(
+            geography::id.eq_any(
+                association::table
+                    .filter(association::api_paths_id.eq_any(api_path_ids))
+                    .select(association::geography_id),
+            ),
+        )
+        .select(GeographyItem::as_select())
+        .order((geography::geo_level_id, geography::id))
+        .load::<GeographyItem<'static>>(conn)
+}
+
+/// The text search vector of a variable. It must match the expression of the index created by
+/// migration 009, or searches won't use the index.
+const VARIABLES_SEARCH_VECTOR: &str =
+    "to_tsvector('english', variables_search_text(variables.concept, variables.label))";
+
+/// Return up to `limit` variables whose concept or label match the keywords of `query`, e.g.
+/// "median household income", best matches first.
+///
+/// Keywords are matched after English stemming, so "incomes" matches "income". Requires the
+/// function and index created by migration 009.
+///
+/// # Arguments
+///
+/// * `conn` - the connection to the database
+/// * `query` - the keywords to search for, all of which must match
+/// * `limit` - the maximum number of variables to return
+pub fn search_variables(
+    conn: &mut PgConnection,
+    query: &str,
+    limit: i64,
+) -> Result<Vec<VariablesItem<'static>>, DieselError> {
+    use crate::schema::variables;
+
+    let matches = sql::<Bool>(&format!(
+        "{} @@ plainto_tsquery('english', ",
+        VARIABLES_SEARCH_VECTOR
+    ))
+    .bind::<Text, _>(query)
+    .sql(")");
+    let rank = sql::<Float>(&format!(
+        "ts_rank({}, plainto_tsquery('english', ",
+        VARIABLES_SEARCH_VECTOR
+    ))
+    .bind::<Text, _>(query)
+    .sql("))");
+    variables::table
+        .filter(matches)
+        .select(VariablesItem::as_select())
+        .order((rank.desc(), variables::id))
+        .limit(limit)
+        .load::<VariablesItem<'static>>(conn)
+}
+
+/// Return the number of variables and geography association rows whose API path isn't one of
+/// `api_path_ids`.
+///
+/// After ingesting a subset of the catalog, a non-zero count means that something else was
+/// ingested, too.
+///
+/// # Arguments
+///
+/// * `conn` - the connection to the database
+/// * `api_path_ids` - the ids of the API paths that were meant to be ingested
+pub fn associations_outside(
+    conn: &mut PgConnection,
+    api_path_ids: &[i32],
+) -> Result<usize, DieselError> {
+    use crate::schema::{api_paths_geography_association, api_paths_variables_association};
+
+    let variables: i64 = api_paths_variables_association::table
+        .filter(not(
+            api_paths_variables_association::api_paths_id.eq_any(api_path_ids)
+        ))
+        .count()
+        .get_result(conn)?;
+    let geographies: i64 = api_paths_geography_association::table
+        .filter(not(
+            api_paths_geography_association::api_paths_id.eq_any(api_path_ids)
+        ))
+        .count()
+        .get_result(conn)?;
+    Ok((variables + geographies) as usize)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse_geography::GeographyCollection;
+    use crate::parse_variables::VariablesCollection;
+    use crate::test_utils::{api_path, insert_api_path, insert_api_paths, test_connection};
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_geographies_requiring() {
+        // Arrange
+        let conn = &mut test_connection();
+        let geography_json = r#"
+    {
+      "fips": [
+        {"name": "us", "geoLevelDisplay": "010"},
+        {"name": "state", "geoLevelDisplay": "040"},
+        {"name": "county", "geoLevelDisplay": "050", "requires": ["state"]},
+        {"name": "tract", "geoLevelDisplay": "140", "requires": ["state", "county"]},
+        {"name": "place", "geoLevelDisplay": "160", "requires": ["county"]}
+      ]
+    }"#;
+        let collection: GeographyCollection =
+            serde_json::from_str(geography_json).expect("Error parsing JSON");
+        diesel::insert_into(crate::schema::geography::table)
+            .values(&collection.fips)
+            .execute(conn)
+            .unwrap();
+
+        // Act
+        let result = geographies_requiring(conn, "state").unwrap();
+
+        // Assert
+        let names: Vec<&str> = result.iter().map(|item| item.name.as_ref()).collect();
+        assert_eq!(names, vec!["county", "tract"]);
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_api_paths_without_variables() {
+        use crate::schema::{api_paths_variables_association, variables};
+
+        // Arrange
+        let conn = &mut test_connection();
+        let [with_variables, without_variables] =
+            insert_api_paths(conn, [(2019, "acs/acs5"), (2020, "acs/acs5")]);
+        let variables_json =
+            r#"{"variables": {"B01001_001E": {"label": "Estimate!!Total", "group": "B01001"}}}"#;
+        let collection: VariablesCollection =
+            serde_json::from_str(variables_json).expect("Error parsing JSON");
+        let variable_id: i32 = diesel::insert_into(variables::table)
+            .values(&collection.variables)
+            .returning(variables::id)
+            .get_result(conn)
+            .unwrap();
+        diesel::insert_into(api_paths_variables_association::table)
+            .values((
+                api_paths_variables_association::api_paths_id.eq(with_variables),
+                api_paths_variables_association::variables_id.eq(variable_id),
+            ))
+            .execute(conn)
+            .unwrap();
+
+        // Act
+        let result = api_paths_without_variables(conn).unwrap();
+
+        // Assert
+        let ids: Vec<i32> = result.iter().map(|api_path| api_path.id).collect();
+        assert!(ids.contains(&without_variables));
+        assert!(!ids.contains(&with_variables));
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_available_api_paths() {
+        // Arrange
+        let conn = &mut test_connection();
+        let ids = [(2097, Some(true)), (2098, Some(false)), (2099, None)].map(
+            |(vintage, c_is_available)| {
+                let api_path = ApiPaths {
+                    c_is_available,
+                    ..api_path(vintage, "acs/acs5")
+                };
+                insert_api_path(conn, &api_path)
+            },
+        );
+
+        // Act
+        let result = available_api_paths(conn).unwrap();
+
+        // Assert
+        let available: Vec<i32> = result.iter().map(|api_path| api_path.id).collect();
+        assert!(available.contains(&ids[0]));
+        assert!(!available.contains(&ids[1]));
+        assert!(available.contains(&ids[2]));
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_pending_api_paths() {
+        use crate::schema::ingest_state;
+
+        // Arrange
+        let conn = &mut test_connection();
+        let [completed, pending] = insert_api_paths(conn, [(2019, "acs/acs5"), (2020, "acs/acs5")]);
+        diesel::insert_into(ingest_state::table)
+            .values(ingest_state::api_paths_id.eq(completed))
+            .execute(conn)
+            .unwrap();
+
+        // Act
+        let result = pending_api_paths(conn).unwrap();
+
+        // Assert
+        let ids: Vec<i32> = result.iter().map(|api_path| api_path.id).collect();
+        assert!(ids.contains(&pending));
+        assert!(!ids.contains(&completed));
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_filter_api_paths() {
+        // Arrange
+        let conn = &mut test_connection();
+        let ids = insert_api_paths(
+            conn,
+            [(2099, "acs/acs5"), (2098, "acs/acs5"), (2099, "dec/pl")],
+        );
+        let mut filter = |dataset, vintage| -> Vec<i32> {
+            // The database may already contain other API paths.
+            filter_api_paths(conn, dataset, vintage)
+                .unwrap()
+                .iter()
+                .map(|api_path| api_path.id)
+                .filter(|id| ids.contains(id))
+                .collect()
+        };
+
+        // Act
+        let acs5_2099 = filter(Some("ACS/acs_"), Some(2099));
+        let acs = filter(Some("acs/%"), None);
+        let vintage_2099 = filter(None, Some(2099));
+        let quoted = filter(Some("acs'; DROP TABLE api_paths; --"), None);
+
+        // Assert
+        assert_eq!(acs5_2099, vec![ids[0]]);
+        assert_eq!(acs, vec![ids[0], ids[1]]);
+        assert_eq!(vintage_2099, vec![ids[0], ids[2]]);
+        assert!(quoted.is_empty());
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_variables_for() {
+        use crate::schema::{api_paths_variables_association, variables};
+
+        // Arrange
+        let conn = &mut test_connection();
+        let [acs5, acs1] = insert_api_paths(conn, [(2099, "acs/acs5"), (2099, "acs/acs1")]);
+        let variables_json = r#"
+    {
+      "variables": {
+        "NAME": {"label": "Geographic Area Name", "group": "N/A"},
+        "B01001_001E": {"label": "Estimate!!Total", "group": "B01001"},
+        "B01001_002E": {"label": "Estimate!!Total!!Male", "group": "B01001"}
+      }
+    }"#;
+        let collection: VariablesCollection =
+            serde_json::from_str(variables_json).expect("Error parsing JSON");
+        let variable_ids: Vec<i32> = diesel::insert_into(variables::table)
+            .values(&collection.variables)
+            .returning(variables::id)
+            .get_results(conn)
+            .unwrap();
+        let associations = [
+            (acs5, variable_ids[0]),
+            (acs5, variable_ids[1]),
+            (acs1, variable_ids[1]),
+            (acs1, variable_ids[2]),
+        ];
+        for (api_path_id, variable_id) in associations {
+            diesel::insert_into(api_paths_variables_association::table)
+                .values((
+                    api_paths_variables_association::api_paths_id.eq(api_path_id),
+                    api_paths_variables_association::variables_id.eq(variable_id),
+                ))
+                .execute(conn)
+                .unwrap();
+        }
+
+        // Act
+        let acs5_variables = variables_for(conn, "acs5", 2099).unwrap();
+        let acs_variables = variables_for(conn, "acs", 2099).unwrap();
+
+        // Assert
+        let names = |items: &[VariablesItem]| -> Vec<String> {
+            items.iter().map(|item| item.name.to_string()).collect()
+        };
+        assert_eq!(names(&acs5_variables), vec!["B01001_001E", "NAME"]);
+        assert_eq!(
+            names(&acs_variables),
+            vec!["B01001_001E", "B01001_002E", "NAME"]
+        );
+        assert_eq!(acs5_variables[0].label, vec!["Estimate", "Total"]);
+        assert!(variables_for(conn, "acs5", 2098).unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_geographies_for() {
+        use crate::schema::{api_paths_geography_association, geography};
+
+        // Arrange
+        let conn = &mut test_connection();
+        let [acs5, acs1] = insert_api_paths(conn, [(2099, "acs/acs5"), (2099, "acs/acs1")]);
+        let geography_json = r#"
+    {
+      "fips": [
+        {"name": "tract", "geoLevelId": "140", "requires": ["state", "county"]},
+        {"name": "us", "geoLevelId": "010"},
+        {"name": "state", "geoLevelId": "040"}
+      ],
+      "referenceDate": "2099-01-01"
+    }"#;
+        let collection: GeographyCollection =
+            serde_json::from_str(geography_json).expect("Error parsing JSON");
+        let geography_ids: Vec<i32> = diesel::insert_into(geography::table)
+            .values(&collection.fips)
+            .returning(geography::id)
+            .get_results(conn)
+            .unwrap();
+        let associations = [
+            (acs5, geography_ids[0]),
+            (acs5, geography_ids[1]),
+            (acs1, geography_ids[1]),
+            (acs1, geography_ids[2]),
+        ];
+        for (api_path_id, geography_id) in associations {
+            diesel::insert_into(api_paths_geography_association::table)
+                .values((
+                    api_paths_geography_association::api_paths_id.eq(api_path_id),
+                    api_paths_geography_association::geography_id.eq(geography_id),
+                ))
+                .execute(conn)
+                .unwrap();
+        }
+
+        // Act
+        let result = geographies_for(conn, "acs5", 2099).unwrap();
+
+        // Assert
+        let names: Vec<&str> = result.iter().map(|item| item.name.as_ref()).collect();
+        assert_eq!(names, vec!["us", "tract"]);
+        assert!(geographies_for(conn, "acs5", 2098).unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_search_variables() {
+        use crate::schema::variables;
+
+        // Arrange
+        let conn = &mut test_connection();
+        let variables_json = r#"
+    {
+      "variables": {
+        "B17001_002E": {
+          "label": "Estimate!!Total:!!Income in the past 12 months below poverty level:",
+          "concept": "POVERTY STATUS IN THE PAST 12 MONTHS BY SEX BY AGE",
+          "group": "B17001"
+        },
+        "B01001_001E": {"label": "Estimate!!Total:", "concept": "SEX BY AGE", "group": "B01001"},
+        "B19013_001E": {
+          "label": "Estimate!!Median household income in the past 12 months",
+          "concept": "MEDIAN HOUSEHOLD INCOME IN THE PAST 12 MONTHS (IN 2099 INFLATION-ADJUSTED DOLLARS)",
+          "group": "B19013"
+        }
+      }
+    }"#;
+        let collection: VariablesCollection =
+            serde_json::from_str(variables_json).expect("Error parsing JSON");
+        let variable_ids: Vec<i32> = diesel::insert_into(variables::table)
+            .values(&collection.variables)
+            .returning(variables::id)
+            .get_results(conn)
+            .unwrap();
+
+        // Act
+        let result = search_variables(conn, "incomes", 100).unwrap();
+
+        // Assert
+        // The database may already contain other variables.
+        let names: Vec<&str> = result
+            .iter()
+            .filter(|item| variable_ids.contains(&item.id))
+            .map(|item| item.name.as_ref())
+            .collect();
+        assert_eq!(names, vec!["B19013_001E", "B17001_002E"]);
+        let median = search_variables(conn, "median household income", 100).unwrap();
+        assert!(median.iter().any(|item| item.name == "B19013_001E"));
+        assert!(median.iter().all(|item| item.name != "B17001_002E"));
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_read_connection() {
+        // Arrange
+        let read_conn = &mut crate::establish_read_database_connection(None, None)
+            .expect("Could not connect to the read database");
+
+        // Act
+        let result = geographies_requiring(read_conn, "state");
+        let write_result =
+            diesel::sql_query("CREATE TEMPORARY TABLE read_only_check (id INT)").execute(read_conn);
+
+        // Assert
+        assert!(result.is_ok());
+        assert!(write_result.is_err());
+    }
+
+    #[test]
+    #[ignore = "requires a PostgreSQL database"]
+    fn test_associations_outside() {
+        use crate::schema::{api_paths_geography_association, geography};
+
+        // Arrange
+        let conn = &mut test_connection();
+        let [in_scope, out_of_scope] =
+            insert_api_paths(conn, [(2020, "acs/acs5"), (2020, "acs/acs1")]);
+        let geography_json = r#"{"fips": [{"name": "us", "geoLevelDisplay": "010"}]}"#;
+        let collection: GeographyCollection =
+            serde_json::from_str(geography_json).expect("Error parsing JSON");
+        let geography_id: i32 = diesel::insert_into(geography::table)
+            .values(&collection.fips)
+            .returning(geography::id)
+            .get_result(conn)
+            .unwrap();
+        // The database may already contain associations of other API paths.
+        let before = associations_outside(conn, &[in_scope]).unwrap();
+
+        // Act
+        for api_path_id in [in_scope, out_of_scope] {
+            diesel::insert_into(api_paths_geography_association::table)
+                .values((
+                    api_paths_geography_association::api_paths_id.eq(api_path_id),
+                    api_paths_geography_association::geography_id.eq(geography_id),
+                ))
+                .execute(conn)
+                .unwrap();
+        }
+        let result = associations_outside(conn, &[in_scope]).unwrap();
+
+        // Assert
+        assert_eq!(result, before + 1);
+        assert_eq!(
+            associations_outside(conn, &[in_scope, out_of_scope]).unwrap(),
+            before
+        );
+    }
+}
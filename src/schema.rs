@@ -9,6 +9,11 @@ diesel::table! {
         c_variables_link -> Text,
         title -> Text,
         description -> Text,
+        c_is_aggregate -> Nullable<Bool>,
+        c_is_available -> Nullable<Bool>,
+        spatial -> Nullable<Text>,
+        temporal -> Nullable<Text>,
+        modified -> Nullable<Text>,
     }
 }
 
@@ -39,6 +44,25 @@ diesel::table! {
         limit -> Nullable<Int4>,
         geo_level_id -> Nullable<Text>,
         optional_with_wildcard_for -> Nullable<Text>,
+        requires_jsonb -> Nullable<Jsonb>,
+        wildcard_jsonb -> Nullable<Jsonb>,
+        reference_date_precision -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    ingest_state (api_paths_id) {
+        api_paths_id -> Int4,
+        completed_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    variable_values (id) {
+        id -> Int4,
+        variables_id -> Int4,
+        code -> Text,
+        label -> Text,
     }
 }
 
@@ -51,7 +75,7 @@ diesel::table! {
         required -> Nullable<Text>,
         predicate_type -> Nullable<Text>,
         group -> Nullable<Array<Nullable<Text>>>,
-        limit -> Nullable<Int2>,
+        limit -> Nullable<Int4>,
         predicate_only -> Nullable<Bool>,
         attributes -> Nullable<Array<Nullable<Text>>>,
         _first_group -> Nullable<Text>,
@@ -64,11 +88,15 @@ diesel::joinable!(api_paths_geography_association -> api_paths (api_paths_id));
 diesel::joinable!(api_paths_geography_association -> geography (geography_id));
 diesel::joinable!(api_paths_variables_association -> api_paths (api_paths_id));
 diesel::joinable!(api_paths_variables_association -> variables (variables_id));
+diesel::joinable!(ingest_state -> api_paths (api_paths_id));
+diesel::joinable!(variable_values -> variables (variables_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     api_paths,
     api_paths_geography_association,
     api_paths_variables_association,
     geography,
+    ingest_state,
+    variable_values,
     variables,
 );
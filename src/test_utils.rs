@@ -0,0 +1,70 @@
+//! Helpers shared by tests that need a database.
+//!
+//! These tests are marked `#[ignore]` and run with `cargo test -- --ignored` against the
+//! database configured in `.local.env`, after the migrations have been applied.
+use crate::establish_database_connection;
+use crate::models::ApiPaths;
+use diesel::prelude::*;
+use std::borrow::Cow;
+
+/// Return a connection to the test database whose changes are rolled back when it's dropped.
+pub(crate) fn test_connection() -> PgConnection {
+    let mut conn =
+        establish_database_connection(None, None).expect("Could not connect to the test database");
+    conn.begin_test_transaction()
+        .expect("Could not begin a test transaction");
+    conn
+}
+
+/// Insert an API path and return its primary key.
+pub(crate) fn insert_api_path(conn: &mut PgConnection, api_path: &ApiPaths) -> i32 {
+    use crate::schema::api_paths::dsl::*;
+
+    diesel::insert_into(api_paths)
+        .values(api_path)
+        .returning(id)
+        .get_result(conn)
+        .expect("Could not insert API path")
+}
+
+/// Return an API path of the catalog whose links are under api.census.gov, with only the
+/// required fields. Set the other fields with struct update syntax.
+///
+/// # Arguments
+///
+/// * `vintage` - the `c_vintage`
+/// * `dataset` - the `c_dataset` joined with slashes, e.g. "acs/acs5"
+pub(crate) fn api_path(vintage: i32, dataset: &str) -> ApiPaths<'static> {
+    let link = |file: &str| {
+        Cow::from(format!(
+            "http://api.census.gov/data/{}/{}/{}",
+            vintage, dataset, file
+        ))
+    };
+    ApiPaths {
+        id: 0,
+        c_vintage: Some(vintage),
+        c_dataset: dataset
+            .split('/')
+            .map(|part| Some(Cow::from(part.to_string())))
+            .collect(),
+        c_geography_link: link("geography.json"),
+        c_variables_link: link("variables.json"),
+        title: Cow::from(format!("{} {}", dataset, vintage)),
+        description: Cow::from(""),
+        c_is_aggregate: None,
+        c_is_available: None,
+        spatial: None,
+        temporal: None,
+        modified: None,
+    }
+}
+
+/// Insert an `api_path` for each vintage and dataset, and return their primary keys in the
+/// same order.
+pub(crate) fn insert_api_paths<const N: usize>(
+    conn: &mut PgConnection,
+    api_paths: [(i32, &str); N],
+) -> [i32; N] {
+    api_paths.map(|(vintage, dataset)| insert_api_path(conn, &api_path(vintage, dataset)))
+}
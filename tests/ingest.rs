@@ -0,0 +1,532 @@
+//! End-to-end tests of fetching, parsing and inserting API metadata, against a mock Census API
+//! serving the files in `tests/fixtures/`.
+//!
+//! These tests need a database and are marked `#[ignore]`. See CONTRIBUTING.md.
+#![cfg(feature = "net")]
+
+use diesel::prelude::*;
+use reqwest::Client;
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::TempDir;
+use us_census::constraints::get_unique_constraints;
+use us_census::fetch_api_metadata::{CachedClient, FetchError};
+use us_census::models::UsCensusApisResponse;
+use us_census::query::pending_api_paths;
+use us_census::schema::{
+    api_paths, api_paths_geography_association, api_paths_variables_association,
+};
+use us_census::{
+    establish_database_connection, ingest_api_paths, ingest_datasets, ingest_survey_from,
+    sync_api_paths_from, IngestOptions, InsertError, ProgressEvent,
+};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Return the contents of a file in `tests/fixtures/`, with `{base_url}` replaced by `base_url`.
+fn fixture(name: &str, base_url: &str) -> String {
+    let fixture_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    std::fs::read_to_string(fixture_path)
+        .expect("Could not read fixture")
+        .replace("{base_url}", base_url)
+}
+
+/// Serve `fixture` at `url_path`.
+async fn mount_fixture(server: &MockServer, url_path: &str, fixture_name: &str) {
+    Mock::given(method("GET"))
+        .and(path(url_path))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_string(fixture(fixture_name, &server.uri())),
+        )
+        .mount(server)
+        .await;
+}
+
+/// Start a mock Census API serving the catalog and the variables and geography of each of the
+/// 2099 ACS `datasets`, e.g. "acs5". Return it with a client that caches in the returned
+/// temporary directory.
+async fn mock_census_api(datasets: &[&str]) -> (MockServer, CachedClient<'static>, TempDir) {
+    let server = MockServer::start().await;
+    mount_fixture(&server, "/data.json", "data.json").await;
+    for dataset in datasets {
+        let prefix = format!("/data/2099/acs/{}", dataset);
+        mount_fixture(
+            &server,
+            &format!("{}/variables.json", prefix),
+            "variables.json",
+        )
+        .await;
+        mount_fixture(
+            &server,
+            &format!("{}/geography.json", prefix),
+            "geography.json",
+        )
+        .await;
+    }
+    let cache_dir = tempfile::tempdir().unwrap();
+    let client = CachedClient::shared(cache_dir.path().to_path_buf(), Arc::new(Client::new()));
+    (server, client, cache_dir)
+}
+
+/// Return a connection whose changes are rolled back when it's dropped.
+fn test_connection() -> PgConnection {
+    let mut conn =
+        establish_database_connection(None, None).expect("Could not connect to the test database");
+    conn.begin_test_transaction()
+        .expect("Could not begin a test transaction");
+    conn
+}
+
+/// Fetch the mock catalog and insert its API paths.
+async fn insert_catalog(
+    conn: &mut PgConnection,
+    client: &CachedClient<'_>,
+    server: &MockServer,
+) -> UsCensusApisResponse<'static> {
+    let catalog_url = url::Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+    let mut catalog =
+        UsCensusApisResponse::from_slice(client.fetch(&catalog_url).await.unwrap().as_bytes())
+            .expect("Error parsing the catalog");
+    let ids: Vec<i32> = diesel::insert_into(api_paths::table)
+        .values(&catalog.dataset)
+        .returning(api_paths::id)
+        .get_results(conn)
+        .unwrap();
+    for (api_path, id) in catalog.dataset.iter_mut().zip(ids) {
+        api_path.id = id;
+    }
+    catalog
+}
+
+/// Return the number of variables and geographies associated with the API path.
+fn count_associations(conn: &mut PgConnection, api_path_id: i32) -> (i64, i64) {
+    let variables = api_paths_variables_association::table
+        .filter(api_paths_variables_association::api_paths_id.eq(api_path_id))
+        .count()
+        .get_result(conn)
+        .unwrap();
+    let geographies = api_paths_geography_association::table
+        .filter(api_paths_geography_association::api_paths_id.eq(api_path_id))
+        .count()
+        .get_result(conn)
+        .unwrap();
+    (variables, geographies)
+}
+
+#[tokio::test]
+#[ignore = "requires a PostgreSQL database"]
+async fn test_ingest_catalog() {
+    // Arrange
+    let (server, client, _cache_dir) = mock_census_api(&["acs1", "acs5"]).await;
+    let conn = &mut test_connection();
+    let catalog = insert_catalog(conn, &client, &server).await;
+    let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+
+    // Act
+    let report = ingest_api_paths(
+        conn,
+        &client,
+        &catalog.dataset,
+        constraint,
+        &IngestOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    assert!(report.failed.is_empty(), "{:?}", report.failed);
+    assert_eq!(report.ingested.len(), 2);
+    for (_, summary) in &report.ingested {
+        assert_eq!(summary.variables_inserted, 3);
+        assert_eq!(summary.geographies_inserted, 3);
+    }
+    for api_path in &catalog.dataset {
+        assert_eq!(count_associations(conn, api_path.id), (3, 3));
+    }
+    // Both API paths share the same variables.
+    let variables: Vec<i32> = api_paths_variables_association::table
+        .filter(
+            api_paths_variables_association::api_paths_id
+                .eq_any(catalog.dataset.iter().map(|a| a.id)),
+        )
+        .select(api_paths_variables_association::variables_id)
+        .distinct()
+        .load(conn)
+        .unwrap();
+    assert_eq!(variables.len(), 3);
+}
+
+#[tokio::test]
+#[ignore = "requires a PostgreSQL database"]
+async fn test_ingest_datasets_progress() {
+    // Arrange
+    let (server, client, _cache_dir) = mock_census_api(&["acs1", "acs5"]).await;
+    let conn = &mut test_connection();
+    let catalog = insert_catalog(conn, &client, &server).await;
+    let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+    let mut events = Vec::new();
+
+    // Act
+    let report = ingest_datasets(
+        conn,
+        &client,
+        &catalog.dataset,
+        constraint,
+        &IngestOptions::default(),
+        Some(&mut |event| events.push(event)),
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    assert!(report.failed.is_empty(), "{:?}", report.failed);
+    let variables_bytes = fixture("variables.json", &server.uri()).len();
+    let geography_bytes = fixture("geography.json", &server.uri()).len();
+    let expected: Vec<ProgressEvent> = report
+        .ingested
+        .iter()
+        .zip(&catalog.dataset)
+        .flat_map(|((_, summary), api_path)| {
+            [
+                ProgressEvent::EndpointStarted {
+                    title: api_path.title.to_string(),
+                },
+                ProgressEvent::Fetched {
+                    bytes: variables_bytes,
+                },
+                ProgressEvent::Fetched {
+                    bytes: geography_bytes,
+                },
+                ProgressEvent::EndpointFinished {
+                    summary: summary.clone(),
+                },
+            ]
+        })
+        .collect();
+    assert_eq!(events, expected);
+}
+
+#[tokio::test]
+#[ignore = "requires a PostgreSQL database"]
+async fn test_ingest_catalog_with_failing_api_path() {
+    // Arrange
+    let (server, client, _cache_dir) = mock_census_api(&["acs5"]).await;
+    Mock::given(method("GET"))
+        .and(path("/data/2099/acs/acs1/variables.json"))
+        .respond_with(ResponseTemplate::new(500).set_body_string("Internal Server Error"))
+        .mount(&server)
+        .await;
+    let conn = &mut test_connection();
+    let catalog = insert_catalog(conn, &client, &server).await;
+    let constraint = &get_unique_constraints(conn, "variables").unwrap()[0];
+
+    // Act
+    let report = ingest_api_paths(
+        conn,
+        &client,
+        &catalog.dataset,
+        constraint,
+        &IngestOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    let (acs5, acs1) = (&catalog.dataset[0], &catalog.dataset[1]);
+    assert_eq!(report.ingested.len(), 1);
+    assert_eq!(report.ingested[0].0, acs5.c_variables_link);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].0, acs1.c_variables_link);
+    assert!(matches!(
+        report.failed[0].1,
+        InsertError::Http(FetchError::HttpStatus { status, .. }) if status.as_u16() == 500
+    ));
+    assert_eq!(count_associations(conn, acs5.id), (3, 3));
+    assert_eq!(count_associations(conn, acs1.id), (0, 0));
+    let pending: Vec<i32> = pending_api_paths(conn)
+        .unwrap()
+        .iter()
+        .map(|api_path| api_path.id)
+        .collect();
+    assert!(pending.contains(&acs1.id));
+    assert!(!pending.contains(&acs5.id));
+}
+
+#[tokio::test]
+#[ignore = "requires a PostgreSQL database"]
+async fn test_ingest_survey() {
+    // Arrange
+    let (server, client, _cache_dir) = mock_census_api(&["acs5"]).await;
+    Mock::given(method("GET"))
+        .and(path("/data/2099/acs/acs1/variables.json"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+    let conn = &mut test_connection();
+    let catalog_url = url::Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+
+    // Act
+    let report = ingest_survey_from(
+        conn,
+        &client,
+        &catalog_url,
+        "acs/acs5",
+        Some(2099),
+        &IngestOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    // Assert
+    assert!(report.failed.is_empty(), "{:?}", report.failed);
+    assert_eq!(report.ingested.len(), 1);
+    assert_eq!(
+        report.ingested[0].0,
+        format!("{}/data/2099/acs/acs5/variables.json", server.uri())
+    );
+    let titles: Vec<String> = api_paths::table
+        .filter(api_paths::c_vintage.eq(2099))
+        .select(api_paths::title)
+        .load(conn)
+        .unwrap();
+    assert_eq!(titles, vec!["ACS 5-Year Detailed Tables"]);
+}
+
+#[tokio::test]
+#[ignore = "requires a PostgreSQL database"]
+async fn test_sync_api_paths() {
+    // Arrange
+    let (server, client, _cache_dir) = mock_census_api(&[]).await;
+    let conn = &mut test_connection();
+    let catalog_url = url::Url::parse(&format!("{}/data.json", server.uri())).unwrap();
+    let catalog =
+        UsCensusApisResponse::from_slice(fixture("data.json", &server.uri()).as_bytes()).unwrap();
+    let acs5 = &catalog.dataset[0];
+    diesel::insert_into(api_paths::table)
+        .values(acs5)
+        .execute(conn)
+        .unwrap();
+
+    // Act
+    let inserted = sync_api_paths_from(conn, &client, &catalog_url)
+        .await
+        .unwrap();
+    let inserted_again = sync_api_paths_from(conn, &client, &catalog_url)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!((inserted, inserted_again), (1, 0));
+    let mut titles: Vec<String> = api_paths::table
+        .filter(api_paths::c_vintage.eq(2099))
+        .select(api_paths::title)
+        .load(conn)
+        .unwrap();
+    titles.sort();
+    assert_eq!(
+        titles,
+        vec!["ACS 1-Year Detailed Tables", "ACS 5-Year Detailed Tables"]
+    );
+}
+
+/// Deletes the API paths of a vintage when dropped, so that a test that commits them cleans
+/// up even if it fails.
+#[cfg(feature = "cli")]
+struct DeleteVintage(i32);
+
+#[cfg(feature = "cli")]
+impl Drop for DeleteVintage {
+    fn drop(&mut self) {
+        let conn = &mut establish_database_connection(None, None)
+            .expect("Could not connect to the test database");
+        diesel::delete(api_paths::table.filter(api_paths::c_vintage.eq(self.0)))
+            .execute(conn)
+            .expect("Could not delete the test API paths");
+    }
+}
+
+#[cfg(feature = "cli")]
+#[tokio::test]
+#[ignore = "requires a PostgreSQL database"]
+async fn test_cli_dry_run() {
+    use us_census::upsert_api_paths;
+
+    // Arrange
+    // The binary connects on its own, so the API path it ingests is committed rather than
+    // seeded in a test transaction, under a vintage that only this test uses.
+    const VINTAGE: i32 = 2096;
+    let (server, _client, temp_dir) = mock_census_api(&["acs5"]).await;
+    let catalog_path = temp_dir.path().join("data.json");
+    let catalog_json = fixture("data.json", &server.uri());
+    std::fs::write(&catalog_path, &catalog_json).unwrap();
+    let mut catalog = UsCensusApisResponse::from_slice(catalog_json.as_bytes()).unwrap();
+    catalog.dataset.truncate(1);
+    catalog.dataset[0].c_vintage = Some(VINTAGE);
+    let _cleanup = DeleteVintage(VINTAGE);
+    let conn = &mut establish_database_connection(None, None).unwrap();
+    upsert_api_paths(conn, &catalog.dataset).unwrap();
+    let api_path_id: i32 = api_paths::table
+        .filter(api_paths::c_vintage.eq(VINTAGE))
+        .select(api_paths::id)
+        .get_result(conn)
+        .unwrap();
+    let count_catalog_vintage = |conn: &mut PgConnection| -> i64 {
+        api_paths::table
+            .filter(api_paths::c_vintage.eq(2099))
+            .count()
+            .get_result(conn)
+            .unwrap()
+    };
+    let catalog_vintage_before = count_catalog_vintage(conn);
+
+    // Act
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_us_census"))
+        .args([
+            "--dataset",
+            "acs/acs5",
+            "--vintage",
+            &VINTAGE.to_string(),
+            "--dry-run",
+        ])
+        .arg("--cache-dir")
+        .arg(temp_dir.path())
+        .arg(&catalog_path)
+        .output()
+        .await
+        .unwrap();
+
+    // Assert
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("/data/2099/acs/acs5/variables.json: 3 variables inserted"),
+        "{}",
+        stdout
+    );
+    assert_eq!(count_associations(conn, api_path_id), (0, 0));
+    // The catalog's API paths were rolled back.
+    assert_eq!(count_catalog_vintage(conn), catalog_vintage_before);
+    assert!(pending_api_paths(conn)
+        .unwrap()
+        .iter()
+        .any(|api_path| api_path.id == api_path_id));
+}
+
+/// Tests of `insert_variables_and_geography_for_api_path_async`. They use `diesel_async`'s
+/// `RunQueryDsl`, which conflicts with `diesel`'s.
+#[cfg(feature = "async-db")]
+mod async_db {
+    use super::{fixture, mock_census_api};
+    use diesel::{ExpressionMethods, QueryDsl};
+    use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+    use us_census::constraints::get_unique_constraints;
+    use us_census::models::{ApiPaths, UsCensusApisResponse};
+    use us_census::schema::{
+        api_paths, api_paths_geography_association, api_paths_variables_association,
+    };
+    use us_census::{
+        establish_async_database_connection, establish_database_connection,
+        insert_variables_and_geography_for_api_path_async, IngestOptions,
+    };
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, ResponseTemplate};
+
+    /// Return an async connection whose changes are rolled back when it's dropped.
+    async fn test_connection() -> AsyncPgConnection {
+        let mut conn = establish_async_database_connection(None, None)
+            .await
+            .expect("Could not connect to the test database");
+        conn.begin_test_transaction()
+            .await
+            .expect("Could not begin a test transaction");
+        conn
+    }
+
+    /// Insert the API path and return it with its id.
+    async fn insert_api_path(
+        conn: &mut AsyncPgConnection,
+        mut api_path: ApiPaths<'static>,
+    ) -> ApiPaths<'static> {
+        api_path.id = diesel::insert_into(api_paths::table)
+            .values(&api_path)
+            .returning(api_paths::id)
+            .get_result(conn)
+            .await
+            .unwrap();
+        api_path
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL database"]
+    async fn test_insert_concurrently() {
+        // Arrange
+        let (server, client, _cache_dir) = mock_census_api(&["acs5"]).await;
+        // Each connection's changes are uncommitted, so the two API paths must not upsert the
+        // same variables or geographies, or one would wait for the other to commit.
+        let acs1_variables = fixture("variables.json", &server.uri())
+            .replace(r#""concept": ""#, r#""concept": "ACS 1-Year "#);
+        let acs1_geography =
+            fixture("geography.json", &server.uri()).replace("2099-01-01", "2099-07-01");
+        for (url_path, body) in [
+            ("/data/2099/acs/acs1/variables.json", acs1_variables),
+            ("/data/2099/acs/acs1/geography.json", acs1_geography),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(url_path))
+                .respond_with(ResponseTemplate::new(200).set_body_string(body))
+                .mount(&server)
+                .await;
+        }
+        let mut catalog =
+            UsCensusApisResponse::from_slice(fixture("data.json", &server.uri()).as_bytes())
+                .unwrap()
+                .dataset
+                .into_iter();
+        let (conn_acs5, conn_acs1) = (&mut test_connection().await, &mut test_connection().await);
+        let acs5 = insert_api_path(conn_acs5, catalog.next().unwrap()).await;
+        let acs1 = insert_api_path(conn_acs1, catalog.next().unwrap()).await;
+        let constraint = &get_unique_constraints(
+            &mut establish_database_connection(None, None).unwrap(),
+            "variables",
+        )
+        .unwrap()[0];
+        let options = IngestOptions::default();
+
+        // Act
+        let (acs5_result, acs1_result) = futures::join!(
+            insert_variables_and_geography_for_api_path_async(
+                conn_acs5, &client, &acs5, constraint, &options,
+            ),
+            insert_variables_and_geography_for_api_path_async(
+                conn_acs1, &client, &acs1, constraint, &options,
+            ),
+        );
+
+        // Assert
+        for (conn, api_path, result) in [
+            (conn_acs5, &acs5, acs5_result),
+            (conn_acs1, &acs1, acs1_result),
+        ] {
+            let summary = result.unwrap();
+            assert_eq!(summary.variables_inserted, 3);
+            assert_eq!(summary.geographies_inserted, 3);
+            let variables: i64 = api_paths_variables_association::table
+                .filter(api_paths_variables_association::api_paths_id.eq(api_path.id))
+                .count()
+                .get_result(conn)
+                .await
+                .unwrap();
+            let geographies: i64 = api_paths_geography_association::table
+                .filter(api_paths_geography_association::api_paths_id.eq(api_path.id))
+                .count()
+                .get_result(conn)
+                .await
+                .unwrap();
+            assert_eq!((variables, geographies), (3, 3));
+        }
+    }
+}
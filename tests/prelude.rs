@@ -0,0 +1,24 @@
+//! Typical use needs nothing but the prelude.
+use us_census::prelude::*;
+
+#[test]
+fn test_parse_with_prelude() {
+    let variables_json = r#"
+    {
+      "variables": {
+        "B01001_002E": {
+          "label": "Estimate!!Total:!!Male:",
+          "group": "B01001"
+        }
+      }
+    }"#;
+    let geography_json = r#"{"fips": [{"name": "us", "geoLevelDisplay": "010"}]}"#;
+
+    let variables = VariablesCollection::parse(variables_json, ParseMode::Borrow).unwrap();
+    let geography: GeographyCollection = serde_json::from_str(geography_json).unwrap();
+
+    let variable: &VariablesItem = &variables.variables[0];
+    assert_eq!(variable.label_path().leaf(), Some("Male"));
+    let item: &GeographyItem = &geography.fips[0];
+    assert_eq!(item.effective_limit(), GeographyLimit::Unlimited);
+}
@@ -0,0 +1,84 @@
+//! Measures the peak memory of parsing a large variables.json with `VariablesStream`, compared
+//! to parsing it into a `VariablesCollection`.
+//!
+//! The allocator of this test binary counts the bytes allocated, so the file holds a single
+//! test.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use us_census::parse_variables::{ParseMode, VariablesCollection, VariablesStream};
+
+/// The system allocator, keeping track of the bytes currently allocated and their peak.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Run `f` and return its output, with the peak number of bytes allocated meanwhile beyond
+/// those allocated before.
+fn peak_memory<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let output = f();
+    (output, PEAK.load(Ordering::Relaxed) - before)
+}
+
+/// Return a variables.json with `count` variables, about 200 bytes each.
+fn large_variables_json(count: usize) -> String {
+    let variables: Vec<String> = (0..count)
+        .map(|i| {
+            format!(
+                r#""B{i:05}_001E": {{"label": "Estimate!!Total:!!Male:!!{i} years", "concept": "SEX BY AGE", "predicateType": "int", "group": "B{i:05}", "limit": 0, "attributes": "B{i:05}_001EA,B{i:05}_001M,B{i:05}_001MA"}}"#
+            )
+        })
+        .collect();
+    format!(r#"{{"variables": {{{}}}}}"#, variables.join(",\n"))
+}
+
+#[test]
+fn test_variables_stream_peak_memory() {
+    // Arrange
+    let count = 10_000;
+    let variables_json = large_variables_json(count);
+
+    // Act
+    let (collected, collection_peak) = peak_memory(|| {
+        VariablesCollection::parse(&variables_json, ParseMode::Borrow)
+            .unwrap()
+            .variables
+            .len()
+    });
+    let (streamed, stream_peak) = peak_memory(|| {
+        VariablesStream::new(variables_json.as_bytes())
+            .map(Result::unwrap)
+            .count()
+    });
+
+    // Assert
+    assert_eq!((collected, streamed), (count, count));
+    assert!(
+        stream_peak * 100 < collection_peak,
+        "{} bytes of JSON: streaming peaked at {} bytes, parsing a collection at {} bytes",
+        variables_json.len(),
+        stream_peak,
+        collection_peak
+    );
+}